@@ -0,0 +1,128 @@
+//! Benchmarks for reading the OpenType example data.
+//!
+//! OpenType fonts contain many links between tables, so these benchmarks are
+//! useful for measuring the performance of the reference cache. The fonts in
+//! the example data are small, so a synthetic table directory with thousands
+//! of links is also read, like those found in large font collections.
+
+use std::fs;
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use fathom::core::binary::{self, Buffer};
+use fathom::files::FileId;
+use fathom::source::ProgramSource;
+use fathom::surface::elaboration::{self, ItemEnv};
+use fathom::surface::{Module, Term};
+use scoped_arena::Scope;
+
+const DATA_DIRS: &[&str] = &["aots", "woff", "woff2"];
+
+fn read_opentype(c: &mut Criterion) {
+    let formats_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../formats");
+    let scope = Scope::new();
+
+    // Elaborate the OpenType module, and the entrypoint of the format
+    let source = fs::read_to_string(formats_dir.join("opentype.fathom")).unwrap();
+    let source = ProgramSource::try_from(source).unwrap();
+    let (surface_module, messages) = Module::parse(&scope, &source);
+    assert!(messages.is_empty());
+
+    let file_id = FileId::try_from(1).unwrap();
+    let mut context = elaboration::Context::new(file_id, &scope, ItemEnv::new());
+    let module = context.elab_module(&scope, &surface_module, &mut |_| {
+        panic!("unexpected message")
+    });
+
+    let source = ProgramSource::try_from("main".to_owned()).unwrap();
+    let (surface_term, _) = Term::parse(&scope, &source);
+    let mut context = elaboration::Context::new(file_id, &scope, context.finish());
+    let format = context.elab_format(&scope, &surface_term, &mut |_| panic!("unexpected message"));
+
+    let mut group = c.benchmark_group("read_opentype");
+    for dir in DATA_DIRS {
+        let mut paths = fs::read_dir(formats_dir.join("data/opentype").join(dir))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| matches!(path.extension(), Some(ext) if ext == "otf" || ext == "ttf"))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        for path in paths {
+            let data = fs::read(&path).unwrap();
+            let name = format!("{dir}/{}", path.file_name().unwrap().to_string_lossy());
+            group.bench_function(name, |b| {
+                b.iter(|| {
+                    let mut context = binary::Context::new(&scope, Buffer::from(&data[..]));
+                    context.add_module(&module);
+                    context.read_entrypoint(&format).unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// A table directory whose records link to tables that are shared between
+/// many records, with the format of each table depending on its record.
+const LINKS_SOURCE: &str = "
+def table (len : U16) : Format = repeat_len16 len u16be;
+def main : Format = {
+    start <- stream_pos,
+    count <- u16be,
+    records <- repeat_len16 count {
+        offset <- u16be,
+        len <- u16be,
+        link <- link (pos_add_u16 start offset) (table len),
+        table <- deref @(table len) link,
+    },
+};
+";
+
+/// The number of records in the table directory.
+const LINK_COUNT: u16 = 4096;
+/// The number of distinct tables that the records link to.
+const TABLE_COUNT: u16 = 16;
+/// The number of distinct lengths that each table is read with.
+const TABLE_LEN_COUNT: u16 = 32;
+
+fn read_links(c: &mut Criterion) {
+    let scope = Scope::new();
+
+    let source = ProgramSource::try_from(LINKS_SOURCE.to_owned()).unwrap();
+    let (surface_module, messages) = Module::parse(&scope, &source);
+    assert!(messages.is_empty());
+
+    let file_id = FileId::try_from(1).unwrap();
+    let mut context = elaboration::Context::new(file_id, &scope, ItemEnv::new());
+    let module = context.elab_module(&scope, &surface_module, &mut |_| {
+        panic!("unexpected message")
+    });
+
+    let source = ProgramSource::try_from("main".to_owned()).unwrap();
+    let (surface_term, _) = Term::parse(&scope, &source);
+    let mut context = elaboration::Context::new(file_id, &scope, context.finish());
+    let format = context.elab_format(&scope, &surface_term, &mut |_| panic!("unexpected message"));
+
+    let tables_start = 2 + 4 * LINK_COUNT;
+    let table_len = 2 * TABLE_LEN_COUNT;
+    let mut data = LINK_COUNT.to_be_bytes().to_vec();
+    for i in 0..LINK_COUNT {
+        let offset = tables_start + (i % TABLE_COUNT) * table_len;
+        let len = (i / TABLE_COUNT) % TABLE_LEN_COUNT;
+        data.extend(offset.to_be_bytes());
+        data.extend(len.to_be_bytes());
+    }
+    data.resize(usize::from(tables_start + TABLE_COUNT * table_len), 0);
+
+    c.bench_function("read_links", |b| {
+        b.iter(|| {
+            let mut context = binary::Context::new(&scope, Buffer::from(&data[..]));
+            context.add_module(&module);
+            context.read_entrypoint(&format).unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, read_opentype, read_links);
+criterion_main!(benches);
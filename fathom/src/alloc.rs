@@ -104,6 +104,13 @@ impl<'a, Elem> From<SliceVec<'a, Elem>> for &'a [Elem] {
     }
 }
 
+/// Allocates a copy of a string to the scope.
+pub fn to_scope_str<'a>(scope: &'a scoped_arena::Scope<'a>, string: &str) -> &'a str {
+    let bytes = scope.to_scope_from_iter(string.bytes());
+    // SAFETY: The bytes were copied from a `str`, so they are valid UTF-8.
+    unsafe { std::str::from_utf8_unchecked(bytes) }
+}
+
 // NOTE: This is the same implementation as
 // `MaybeUninit::slice_assume_init_ref`, which is currently unstable (see https://github.com/rust-lang/rust/issues/63569).
 #[allow(clippy::needless_lifetimes)] // These serve as important documentation
@@ -194,13 +194,13 @@ pub enum Term<'arena> {
     Prim(Span, Prim),
 
     /// Constant literals.
-    ConstLit(Span, Const),
+    ConstLit(Span, Const<'arena>),
     /// Match on a constant. The pattern branches should be unique, and listed
     /// in lexicographic order.
     ConstMatch(
         Span,
         &'arena Term<'arena>,
-        &'arena [(Const, Term<'arena>)],
+        &'arena [(Const<'arena>, Term<'arena>)],
         Option<(Option<Symbol>, &'arena Term<'arena>)>,
     ),
 }
@@ -345,6 +345,20 @@ def_prims! {
     PosType => "Pos",
     /// Type of stream references.
     RefType => "Ref",
+    /// Type of text strings.
+    StringType => "String",
+    /// Type of text encodings.
+    TextEncodingType => "TextEncoding",
+    /// The UTF-8 text encoding.
+    TextEncodingUtf8 => "utf8",
+    /// The UTF-16 text encoding (little-endian).
+    TextEncodingUtf16Le => "utf16le",
+    /// The UTF-16 text encoding (big-endian).
+    TextEncodingUtf16Be => "utf16be",
+    /// The 7-bit ASCII text encoding.
+    TextEncodingAscii => "ascii",
+    /// The ISO-8859-1 (Latin-1) text encoding.
+    TextEncodingLatin1 => "latin1",
 
     /// Type of format descriptions.
     FormatType => "Format",
@@ -384,6 +398,56 @@ def_prims! {
     FormatF64Be => "f64be",
     /// 64-bit, IEEE-754 floating point formats (little-endian).
     FormatF64Le => "f64le",
+    /// Single bit formats (most significant bit first).
+    FormatBitMsb => "bit_msb",
+    /// Single bit formats (least significant bit first).
+    FormatBitLsb => "bit_lsb",
+    /// Unsigned integer formats of up to 8 bits (most significant bit first).
+    FormatBits8Msb => "bits8_msb",
+    /// Unsigned integer formats of up to 8 bits (least significant bit first).
+    FormatBits8Lsb => "bits8_lsb",
+    /// Unsigned integer formats of up to 16 bits (most significant bit first).
+    FormatBits16Msb => "bits16_msb",
+    /// Unsigned integer formats of up to 16 bits (least significant bit first).
+    FormatBits16Lsb => "bits16_lsb",
+    /// Unsigned integer formats of up to 32 bits (most significant bit first).
+    FormatBits32Msb => "bits32_msb",
+    /// Unsigned integer formats of up to 32 bits (least significant bit first).
+    FormatBits32Lsb => "bits32_lsb",
+    /// Unsigned integer formats of up to 64 bits (most significant bit first).
+    FormatBits64Msb => "bits64_msb",
+    /// Unsigned integer formats of up to 64 bits (least significant bit first).
+    FormatBits64Lsb => "bits64_lsb",
+    /// A format that skips the remaining bits of a partially read byte.
+    FormatByteAlign => "byte_align",
+    /// Unsigned LEB128 variable-length integer formats.
+    FormatUleb128 => "uleb128",
+    /// Signed LEB128 variable-length integer formats.
+    FormatSleb128 => "sleb128",
+    /// Zigzag encoded variable-length integer formats, as used by protocol buffers.
+    FormatVarintZigzag => "varint_zigzag",
+    /// Big endian variable-length quantity formats, as used by MIDI.
+    FormatVlqBe => "vlq_be",
+    /// Null-terminated string formats.
+    FormatStringNul => "string_nul",
+    /// String formats prefixed with an unsigned 8-bit byte length.
+    FormatStringPrefixed8 => "string_prefixed8",
+    /// String formats prefixed with an unsigned 16-bit byte length (big-endian).
+    FormatStringPrefixed16Be => "string_prefixed16be",
+    /// String formats prefixed with an unsigned 16-bit byte length (little-endian).
+    FormatStringPrefixed16Le => "string_prefixed16le",
+    /// String formats prefixed with an unsigned 32-bit byte length (big-endian).
+    FormatStringPrefixed32Be => "string_prefixed32be",
+    /// String formats prefixed with an unsigned 32-bit byte length (little-endian).
+    FormatStringPrefixed32Le => "string_prefixed32le",
+    /// String formats with an unsigned 8-bit byte length.
+    FormatStringFixed8 => "string_fixed8",
+    /// String formats with an unsigned 16-bit byte length.
+    FormatStringFixed16 => "string_fixed16",
+    /// String formats with an unsigned 32-bit byte length.
+    FormatStringFixed32 => "string_fixed32",
+    /// String formats with an unsigned 64-bit byte length.
+    FormatStringFixed64 => "string_fixed64",
     /// Repeat formats up to an unsigned 8-bit length.
     FormatRepeatLen8 => "repeat_len8",
     /// Repeat formats up to an unsigned 16-bit length.
@@ -394,6 +458,12 @@ def_prims! {
     FormatRepeatLen64 => "repeat_len64",
     /// Repeat a format until the length of the given parse scope is reached.
     FormatRepeatUntilEnd => "repeat_until_end",
+    /// Repeat a format until an element satisfies a predicate, including the
+    /// terminating element in the array.
+    FormatRepeatUntil => "repeat_until",
+    /// Repeat a format while elements satisfy a predicate, excluding the first
+    /// element that does not (which is left unread).
+    FormatRepeatWhile => "repeat_while",
     /// Limit the format to an unsigned 8-bit byte length.
     FormatLimit8 => "limit8",
     /// Limit the format to an unsigned 16-bit byte length.
@@ -402,6 +472,41 @@ def_prims! {
     FormatLimit32 => "limit32",
     /// Limit the format to an unsigned 64-bit byte length.
     FormatLimit64 => "limit64",
+    /// Limit the format to an unsigned 8-bit byte length, failing if the
+    /// format does not consume all of the limited data.
+    FormatLimitExact8 => "limit_exact8",
+    /// Limit the format to an unsigned 16-bit byte length, failing if the
+    /// format does not consume all of the limited data.
+    FormatLimitExact16 => "limit_exact16",
+    /// Limit the format to an unsigned 32-bit byte length, failing if the
+    /// format does not consume all of the limited data.
+    FormatLimitExact32 => "limit_exact32",
+    /// Limit the format to an unsigned 64-bit byte length, failing if the
+    /// format does not consume all of the limited data.
+    FormatLimitExact64 => "limit_exact64",
+    /// Decompress a DEFLATE stream with an unsigned 32-bit byte length, and
+    /// read a format from the decompressed data.
+    FormatDeflate => "deflate",
+    /// Decompress a zlib stream with an unsigned 32-bit byte length, and read a
+    /// format from the decompressed data.
+    FormatZlib => "zlib",
+    /// Decompress a Brotli stream with an unsigned 32-bit byte length, and read
+    /// a format from the decompressed data.
+    FormatBrotli => "brotli",
+    /// Skip to the next multiple of an alignment, relative to the start of the
+    /// current buffer.
+    FormatAlign => "align",
+    /// Skip over a number of bytes.
+    FormatSkip => "skip",
+    /// A format that must read an expected value, which is otherwise ignored.
+    FormatReserved => "reserved",
+    /// A format that fails unless it is at the end of the current buffer.
+    FormatEndOfInput => "end_of_input",
+    /// Read the first of an array of formats that succeeds, starting from the
+    /// same position for each alternative.
+    FormatChoice => "choice",
+    /// Read a format, and then apply a function to the data that was read.
+    FormatMap => "map",
     /// A format which returns the current position in the input stream.
     FormatStreamPos => "stream_pos",
     /// A format that links to another location in the binary data stream,
@@ -552,6 +657,9 @@ def_prims! {
     S64Abs => "s64_abs",
     S64UAbs => "s64_unsigned_abs",
 
+    StringEq  => "string_eq",
+    StringNeq => "string_neq",
+
     OptionSome => "some",
     OptionNone => "none",
     OptionFold => "option_fold",
@@ -565,11 +673,27 @@ def_prims! {
     Array16Index => "array16_index",
     Array32Index => "array32_index",
     Array64Index => "array64_index",
+    ArrayIndex => "array_index",
 
     PosAddU8  => "pos_add_u8",
     PosAddU16 => "pos_add_u16",
     PosAddU32 => "pos_add_u32",
     PosAddU64 => "pos_add_u64",
+
+    /// CRC-32 checksums (as used by PNG, gzip and zip) of a range of the binary data.
+    Crc32 => "crc32",
+    /// Adler-32 checksums (as used by zlib) of a range of the binary data.
+    Adler32 => "adler32",
+    /// Wrapping sums of the bytes in a range of the binary data.
+    SumU8 => "sum_u8",
+    /// Wrapping sums of the 16-bit words in a range of the binary data (big-endian).
+    SumU16Be => "sum_u16be",
+    /// Wrapping sums of the 16-bit words in a range of the binary data (little-endian).
+    SumU16Le => "sum_u16le",
+    /// Wrapping sums of the 32-bit words in a range of the binary data (big-endian).
+    SumU32Be => "sum_u32be",
+    /// Wrapping sums of the 32-bit words in a range of the binary data (little-endian).
+    SumU32Le => "sum_u32le",
 }
 
 /// Formatting style for integers
@@ -582,9 +706,54 @@ pub enum UIntStyle {
     Ascii,
 }
 
+/// A position in binary data, made up of the buffer that the position is in
+/// and an offset from the start of that buffer.
+///
+/// Buffer `0` is the initial buffer, and buffers derived from it (for example
+/// by decompressing part of it) are numbered from `1` in the order that they
+/// were created. Moving a position past the end of its buffer never moves it
+/// into another buffer. Both parts are packed into a single integer to avoid
+/// increasing the size of constants.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pos(u64);
+
+impl Pos {
+    const OFFSET_BITS: u32 = 48;
+    const OFFSET_MASK: u64 = (1 << Pos::OFFSET_BITS) - 1;
+
+    /// Create a position at an offset into a buffer, returning `None` if the
+    /// offset is too large to be stored in a position.
+    pub fn new(buffer: u16, offset: usize) -> Option<Pos> {
+        let offset = u64::try_from(offset).ok()?;
+        (offset <= Pos::OFFSET_MASK).then(|| Pos((u64::from(buffer) << Pos::OFFSET_BITS) | offset))
+    }
+
+    /// Create a position at an offset into the initial buffer.
+    pub fn initial(offset: usize) -> Option<Pos> {
+        Pos::new(0, offset)
+    }
+
+    /// The buffer that the position is in.
+    pub fn buffer(self) -> u16 {
+        (self.0 >> Pos::OFFSET_BITS) as u16
+    }
+
+    /// The offset of the position from the start of its buffer.
+    pub fn offset(self) -> usize {
+        // Offsets are checked to fit in a `usize` when positions are created
+        (self.0 & Pos::OFFSET_MASK) as usize
+    }
+
+    /// Move the position forwards within its buffer, returning `None` if the
+    /// resulting offset is too large to be stored in a position.
+    pub fn checked_add(self, len: usize) -> Option<Pos> {
+        Pos::new(self.buffer(), usize::checked_add(self.offset(), len)?)
+    }
+}
+
 /// Constants
 #[derive(Debug, Copy, Clone)]
-pub enum Const {
+pub enum Const<'arena> {
     Bool(bool),
     U8(u8, UIntStyle),
     U16(u16, UIntStyle),
@@ -596,12 +765,50 @@ pub enum Const {
     S64(i64),
     F32(f32),
     F64(f64),
-    Pos(usize),
-    Ref(usize),
+    Pos(Pos),
+    /// A reference to the data at a position, along with the index of the
+    /// parsed reference at that position in the reference cache. The index is
+    /// kept small to avoid increasing the size of constants.
+    Ref(Pos, u32),
+    /// A string of text. Strings are allocated in an arena rather than being
+    /// interned, as they may be decoded from untrusted binary data. The string
+    /// is kept behind a thin reference to avoid increasing the size of
+    /// constants.
+    String(&'arena &'arena str),
+}
+
+impl<'arena> Const<'arena> {
+    /// A string constant, with a copy of `string` allocated to the scope.
+    pub fn string(scope: &'arena scoped_arena::Scope<'arena>, string: &str) -> Const<'arena> {
+        Const::String(scope.to_scope(crate::alloc::to_scope_str(scope, string)))
+    }
+
+    /// Copy the constant to a scope, along with any string that it contains.
+    pub fn to_scope<'out_arena>(
+        &self,
+        scope: &'out_arena scoped_arena::Scope<'out_arena>,
+    ) -> Const<'out_arena> {
+        match *self {
+            Const::Bool(value) => Const::Bool(value),
+            Const::U8(value, style) => Const::U8(value, style),
+            Const::U16(value, style) => Const::U16(value, style),
+            Const::U32(value, style) => Const::U32(value, style),
+            Const::U64(value, style) => Const::U64(value, style),
+            Const::S8(value) => Const::S8(value),
+            Const::S16(value) => Const::S16(value),
+            Const::S32(value) => Const::S32(value),
+            Const::S64(value) => Const::S64(value),
+            Const::F32(value) => Const::F32(value),
+            Const::F64(value) => Const::F64(value),
+            Const::Pos(pos) => Const::Pos(pos),
+            Const::Ref(pos, index) => Const::Ref(pos, index),
+            Const::String(string) => Const::string(scope, string),
+        }
+    }
 }
 
-impl PartialEq for Const {
-    fn eq(&self, other: &Const) -> bool {
+impl<'arena> PartialEq for Const<'arena> {
+    fn eq(&self, other: &Const<'arena>) -> bool {
         match (*self, *other) {
             (Const::Bool(a), Const::Bool(b)) => a == b,
             (Const::U8(a, _), Const::U8(b, _)) => a == b,
@@ -615,22 +822,23 @@ impl PartialEq for Const {
             (Const::F32(a), Const::F32(b)) => a.total_cmp(&b).is_eq(),
             (Const::F64(a), Const::F64(b)) => a.total_cmp(&b).is_eq(),
             (Const::Pos(a), Const::Pos(b)) => a == b,
-            (Const::Ref(a), Const::Ref(b)) => a == b,
+            (Const::Ref(a0, a1), Const::Ref(b0, b1)) => (a0, a1) == (b0, b1),
+            (Const::String(a), Const::String(b)) => a == b,
             _ => false,
         }
     }
 }
 
-impl Eq for Const {}
+impl<'arena> Eq for Const<'arena> {}
 
-impl PartialOrd for Const {
-    fn partial_cmp(&self, other: &Const) -> Option<std::cmp::Ordering> {
+impl<'arena> PartialOrd for Const<'arena> {
+    fn partial_cmp(&self, other: &Const<'arena>) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl Ord for Const {
-    fn cmp(&self, other: &Const) -> std::cmp::Ordering {
+impl<'arena> Ord for Const<'arena> {
+    fn cmp(&self, other: &Const<'arena>) -> std::cmp::Ordering {
         match (*self, *other) {
             (Const::Bool(a), Const::Bool(b)) => a.cmp(&b),
             (Const::U8(a, _), Const::U8(b, _)) => a.cmp(&b),
@@ -644,9 +852,10 @@ impl Ord for Const {
             (Const::F32(a), Const::F32(b)) => a.total_cmp(&b),
             (Const::F64(a), Const::F64(b)) => a.total_cmp(&b),
             (Const::Pos(a), Const::Pos(b)) => a.cmp(&b),
-            (Const::Ref(a), Const::Ref(b)) => a.cmp(&b),
+            (Const::Ref(a0, a1), Const::Ref(b0, b1)) => (a0, a1).cmp(&(b0, b1)),
+            (Const::String(a), Const::String(b)) => a.cmp(b),
             _ => {
-                fn discriminant(r#const: &Const) -> usize {
+                fn discriminant(r#const: &Const<'_>) -> usize {
                     match r#const {
                         Const::Bool(_) => 0,
                         Const::U8(_, _) => 1,
@@ -660,7 +869,8 @@ impl Ord for Const {
                         Const::F32(_) => 9,
                         Const::F64(_) => 10,
                         Const::Pos(_) => 11,
-                        Const::Ref(_) => 12,
+                        Const::Ref(_, _) => 12,
+                        Const::String(_) => 13,
                     }
                 }
 
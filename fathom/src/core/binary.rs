@@ -1,16 +1,27 @@
 //! Binary semantics of the data description language
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryFrom;
 use std::fmt;
 use std::fmt::Debug;
-use std::slice::SliceIndex;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
 use std::sync::Arc;
 
+use scoped_arena::Scope;
+
 use crate::core::semantics::{self, ArcValue, Elim, Head, Value};
-use crate::core::{Const, Item, Module, Prim, Term, UIntStyle};
+use crate::core::{prim, Const, Item, Module, Plicity, Pos, Prim, Term, UIntStyle};
 use crate::env::{EnvLen, SharedEnv, UniqueEnv};
 use crate::source::{Span, Spanned};
+use crate::symbol::Symbol;
+
+pub mod generate;
+pub mod hexdump;
+pub mod json;
+pub mod select;
+pub mod write;
 
 #[derive(Clone, Debug)]
 pub enum ReadError<'arena> {
@@ -20,6 +31,33 @@ pub enum ReadError<'arena> {
     UnwrappedNone(Span),
     ReadFailFormat(Span),
     CondFailure(Span, ArcValue<'arena>),
+    InvalidBitCount(Span, u8),
+    VarintOverflow(Span, usize),
+    InvalidString(Span, usize, TextEncoding),
+    DecompressionFailed(Span, usize, Compression),
+    RepeatWithoutProgress(Span),
+    ReservedMismatch(Span, ArcValue<'arena>, ArcValue<'arena>),
+    ExpectedEndOfInput(Span, usize),
+    UnconsumedLimit(Span, usize),
+    /// None of the alternatives of a choice format could be read, along with
+    /// the error from each alternative.
+    ChoiceFailure(Span, Vec<ReadError<'arena>>),
+    /// A reference was dereferenced while it was still being read, along with
+    /// the positions of the references leading back to it.
+    LinkCycle(Span, Vec<usize>),
+    /// An array was longer than [`ReadLimits::max_array_len`].
+    ArrayLimitExceeded(Span, u64),
+    /// More values were read than [`ReadLimits::max_values`].
+    ValueLimitExceeded(Span),
+    /// Formats were nested more deeply than [`ReadLimits::max_depth`].
+    DepthLimitExceeded(Span),
+    /// More links were waiting to be read than
+    /// [`ReadLimits::max_pending_links`].
+    PendingLinkLimitExceeded(Span),
+    /// More formats were read than [`ReadLimits::max_steps`].
+    StepLimitExceeded(Span),
+    /// More data was decompressed than [`ReadLimits::max_decompressed_len`].
+    DecompressedLimitExceeded(Span),
     BufferError(Span, BufferError),
 }
 
@@ -32,6 +70,30 @@ impl<'arena> fmt::Display for ReadError<'arena> {
             ReadError::UnknownItem => f.write_str("unknown item"),
             ReadError::ReadFailFormat(_) => f.write_str("read a fail format"),
             ReadError::CondFailure(_, _) => f.write_str("conditional format failed"),
+            ReadError::InvalidBitCount(_, _) => f.write_str("invalid bit count"),
+            ReadError::VarintOverflow(_, _) => f.write_str("variable-length integer overflow"),
+            ReadError::InvalidString(_, _, encoding) => write!(f, "invalid {encoding} string"),
+            ReadError::DecompressionFailed(_, _, compression) => {
+                write!(f, "invalid {compression} data")
+            }
+            ReadError::RepeatWithoutProgress(_) => f.write_str("repeated format made no progress"),
+            ReadError::ReservedMismatch(_, _, _) => {
+                f.write_str("unexpected value in reserved format")
+            }
+            ReadError::ExpectedEndOfInput(_, _) => f.write_str("expected end of input"),
+            ReadError::UnconsumedLimit(_, _) => f.write_str("unconsumed data in exact limit"),
+            ReadError::ChoiceFailure(_, _) => {
+                f.write_str("no alternative of choice format succeeded")
+            }
+            ReadError::LinkCycle(_, _) => f.write_str("cycle found when following links"),
+            ReadError::ArrayLimitExceeded(_, _) => f.write_str("array length limit exceeded"),
+            ReadError::ValueLimitExceeded(_) => f.write_str("value limit exceeded"),
+            ReadError::DepthLimitExceeded(_) => f.write_str("depth limit exceeded"),
+            ReadError::PendingLinkLimitExceeded(_) => f.write_str("pending link limit exceeded"),
+            ReadError::StepLimitExceeded(_) => f.write_str("step limit exceeded"),
+            ReadError::DecompressedLimitExceeded(_) => {
+                f.write_str("decompressed length limit exceeded")
+            }
             ReadError::BufferError(_, err) => fmt::Display::fmt(&err, f),
         }
     }
@@ -39,6 +101,34 @@ impl<'arena> fmt::Display for ReadError<'arena> {
 
 impl<'arena> std::error::Error for ReadError<'arena> {}
 
+impl<'arena> ReadError<'arena> {
+    /// Returns `true` if the error was caused by reaching the end of the
+    /// buffer, including when every alternative of a choice format did.
+    fn is_unexpected_end_of_buffer(&self) -> bool {
+        match self {
+            ReadError::BufferError(_, BufferError::UnexpectedEndOfBuffer) => true,
+            ReadError::ChoiceFailure(_, errors) => {
+                !errors.is_empty() && errors.iter().all(ReadError::is_unexpected_end_of_buffer)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the error was caused by exceeding one of the
+    /// [`ReadLimits`]. These errors are never recovered from.
+    pub fn is_limit_exceeded(&self) -> bool {
+        matches!(
+            self,
+            ReadError::ArrayLimitExceeded(_, _)
+                | ReadError::ValueLimitExceeded(_)
+                | ReadError::DepthLimitExceeded(_)
+                | ReadError::PendingLinkLimitExceeded(_)
+                | ReadError::StepLimitExceeded(_)
+                | ReadError::DecompressedLimitExceeded(_)
+        )
+    }
+}
+
 impl<'arena> From<BufferError> for ReadError<'arena> {
     fn from(err: BufferError) -> ReadError<'arena> {
         ReadError::BufferError(Span::Empty, err)
@@ -66,15 +156,80 @@ impl<'arena> From<BufferError> for ReadError<'arena> {
 pub struct Buffer<'data> {
     /// Offset from the starting position.
     start_offset: usize,
-    /// A slice of data, starting from an offset from the start of a larger
-    /// buffer.
-    data: &'data [u8],
+    /// The data, starting from an offset from the start of a larger buffer.
+    data: BufferData<'data>,
+}
+
+/// The backing data of a [`Buffer`].
+#[derive(Copy, Clone)]
+enum BufferData<'data> {
+    /// A slice of data that is already in memory.
+    Slice(&'data [u8]),
+    /// A window of `len` bytes into some stream data, starting at
+    /// `stream_offset`.
+    Stream {
+        stream: &'data StreamData,
+        stream_offset: usize,
+        len: usize,
+    },
+}
+
+impl<'data> BufferData<'data> {
+    fn len(&self) -> usize {
+        match self {
+            BufferData::Slice(data) => data.len(),
+            BufferData::Stream { len, .. } => *len,
+        }
+    }
+
+    /// Get the data in the supplied range, relative to the start of the data.
+    fn get_relative(&self, range: Range<usize>) -> Result<BufferData<'data>, BufferError> {
+        if range.start > range.end || range.end > self.len() {
+            return Err(BufferError::UnexpectedEndOfBuffer);
+        }
+
+        Ok(match *self {
+            BufferData::Slice(data) => BufferData::Slice(&data[range]),
+            BufferData::Stream {
+                stream,
+                stream_offset,
+                len: _,
+            } => BufferData::Stream {
+                stream,
+                stream_offset: stream_offset + range.start,
+                len: range.end - range.start,
+            },
+        })
+    }
+
+    /// Copy bytes into `bytes`, starting from an offset relative to the start
+    /// of the data.
+    fn copy_relative(&self, relative_offset: usize, bytes: &mut [u8]) -> Result<(), BufferError> {
+        let end = (relative_offset.checked_add(bytes.len()))
+            .filter(|end| *end <= self.len())
+            .ok_or(BufferError::UnexpectedEndOfBuffer)?;
+
+        match self {
+            BufferData::Slice(data) => {
+                bytes.copy_from_slice(&data[relative_offset..end]);
+                Ok(())
+            }
+            BufferData::Stream {
+                stream,
+                stream_offset,
+                len: _,
+            } => stream.copy_from(stream_offset + relative_offset, bytes),
+        }
+    }
 }
 
 impl<'data> Buffer<'data> {
     /// Create a new buffer at an offset into a base buffer.
     pub fn new(start_offset: usize, data: &'data [u8]) -> Buffer<'data> {
-        Buffer { start_offset, data }
+        Buffer {
+            start_offset,
+            data: BufferData::Slice(data),
+        }
     }
 
     /// The offset from the start of the base buffer.
@@ -94,20 +249,13 @@ impl<'data> Buffer<'data> {
 
     /// Return a buffer limited to the supplied length.
     pub fn with_remaining_len(&self, len: usize) -> Result<Buffer<'data>, BufferError> {
+        usize::checked_add(self.start_offset, len).ok_or(BufferError::PositionOverflow)?;
         Ok(Buffer {
             start_offset: self.start_offset,
-            data: self.get_relative(..len)?,
+            data: self.data.get_relative(0..len)?,
         })
     }
 
-    /// Get a slice of the bytes in the buffer, relative to the start of the
-    /// buffer.
-    fn get_relative<I: SliceIndex<[u8]>>(&self, index: I) -> Result<&'data I::Output, BufferError> {
-        self.data
-            .get(index)
-            .ok_or(BufferError::UnexpectedEndOfBuffer)
-    }
-
     /// Create a reader at the start of the buffer.
     pub fn reader(&self) -> BufferReader<'data> {
         BufferReader::from(*self)
@@ -121,21 +269,485 @@ impl<'data> Buffer<'data> {
     }
 }
 
+impl<'data> Buffer<'data> {
+    /// Call `on_chunk` with successive chunks of the `len` bytes starting at
+    /// an offset measured from the start of the base buffer, returning `None`
+    /// if any of the range lies outside of the buffer.
+    fn for_each_chunk(
+        &self,
+        offset: usize,
+        len: usize,
+        on_chunk: &mut dyn FnMut(&[u8]),
+    ) -> Option<()> {
+        const CHUNK_LEN: usize = 4096;
+
+        let start = usize::checked_sub(offset, self.start_offset)?;
+        let end = usize::checked_add(start, len).filter(|end| *end <= self.data.len())?;
+
+        let mut chunk = [0; CHUNK_LEN];
+        for chunk_start in (start..end).step_by(CHUNK_LEN) {
+            let chunk = &mut chunk[..usize::min(CHUNK_LEN, end - chunk_start)];
+            self.data.copy_relative(chunk_start, chunk).ok()?;
+            on_chunk(chunk);
+        }
+
+        Some(())
+    }
+}
+
 impl<'data> From<&'data [u8]> for Buffer<'data> {
     fn from(data: &'data [u8]) -> Buffer<'data> {
+        Buffer::new(0, data)
+    }
+}
+
+impl<'data> From<&'data StreamData> for Buffer<'data> {
+    fn from(stream: &'data StreamData) -> Buffer<'data> {
         Buffer {
             start_offset: 0,
-            data,
+            data: BufferData::Stream {
+                stream,
+                stream_offset: 0,
+                len: stream.len(),
+            },
+        }
+    }
+}
+
+/// Binary data that is read lazily from a seekable stream, for example a file.
+///
+/// Data is paged into memory on demand, and only a limited number of pages are
+/// kept in memory at once, allowing for large inputs to be read without
+/// loading them into memory all at once.
+pub struct StreamData {
+    /// The total length of the stream.
+    len: usize,
+    /// The pages of the stream that are currently in memory.
+    pages: RefCell<StreamPages>,
+}
+
+/// Pages of stream data, along with the stream they are read from.
+struct StreamPages {
+    stream: Box<dyn ReadSeek>,
+    /// Pages that have been read, indexed by page number.
+    pages: HashMap<usize, Box<[u8]>>,
+    /// Page numbers in the order they were read, used for eviction.
+    page_order: VecDeque<usize>,
+}
+
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+impl StreamData {
+    /// The size of the pages read from the stream.
+    const PAGE_LEN: usize = 64 * 1024;
+    /// The maximum number of pages that are kept in memory at once.
+    const MAX_PAGES: usize = 64;
+
+    /// Create stream data that reads from the supplied stream.
+    pub fn new(mut stream: impl Read + Seek + 'static) -> io::Result<StreamData> {
+        let len = stream.seek(SeekFrom::End(0))?;
+        let len = usize::try_from(len).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+        Ok(StreamData {
+            len,
+            pages: RefCell::new(StreamPages {
+                stream: Box::new(stream),
+                pages: HashMap::new(),
+                page_order: VecDeque::new(),
+            }),
+        })
+    }
+
+    /// The length of the stream.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the stream is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copy the bytes at an offset in the stream into `bytes`, reading any
+    /// pages that are not yet in memory.
+    fn copy_from(&self, mut offset: usize, mut bytes: &mut [u8]) -> Result<(), BufferError> {
+        let mut pages = self.pages.borrow_mut();
+
+        while !bytes.is_empty() {
+            let page_number = offset / Self::PAGE_LEN;
+            let page_offset = offset % Self::PAGE_LEN;
+            let page = pages.get_page(page_number, self.len)?;
+
+            let page_bytes = page
+                .get(page_offset..)
+                .filter(|page_bytes| !page_bytes.is_empty())
+                .ok_or(BufferError::UnexpectedEndOfBuffer)?;
+            let copy_len = std::cmp::min(page_bytes.len(), bytes.len());
+
+            let (dest, rest) = std::mem::take(&mut bytes).split_at_mut(copy_len);
+            dest.copy_from_slice(&page_bytes[..copy_len]);
+            bytes = rest;
+            offset += copy_len;
+        }
+
+        Ok(())
+    }
+}
+
+impl StreamPages {
+    /// Get a page from memory, reading it from the stream if needed.
+    fn get_page(&mut self, page_number: usize, stream_len: usize) -> Result<&[u8], BufferError> {
+        if !self.pages.contains_key(&page_number) {
+            let page = self.read_page(page_number, stream_len)?;
+
+            if self.page_order.len() >= StreamData::MAX_PAGES {
+                if let Some(evicted) = self.page_order.pop_front() {
+                    self.pages.remove(&evicted);
+                }
+            }
+            self.page_order.push_back(page_number);
+            self.pages.insert(page_number, page);
+        }
+
+        Ok(&self.pages[&page_number])
+    }
+
+    fn read_page(
+        &mut self,
+        page_number: usize,
+        stream_len: usize,
+    ) -> Result<Box<[u8]>, BufferError> {
+        let start = page_number * StreamData::PAGE_LEN;
+        let end = std::cmp::min(start + StreamData::PAGE_LEN, stream_len);
+        let mut page = vec![0; end.saturating_sub(start)].into_boxed_slice();
+
+        let read_error = |err: io::Error| BufferError::ReadFailed {
+            offset: start,
+            kind: err.kind(),
+        };
+        self.stream
+            .seek(SeekFrom::Start(start as u64))
+            .map_err(read_error)?;
+        self.stream.read_exact(&mut page).map_err(read_error)?;
+
+        Ok(page)
+    }
+}
+
+/// The order in which bits are read from each byte.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Read the most significant bit of each byte first.
+    MsbFirst,
+    /// Read the least significant bit of each byte first.
+    LsbFirst,
+}
+
+/// The encodings that strings can be read and written in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Ascii,
+    Latin1,
+}
+
+impl TextEncoding {
+    /// Return the encoding described by a value of type `TextEncoding`.
+    pub fn from_value(value: &ArcValue<'_>) -> Option<TextEncoding> {
+        match value.match_prim_spine()? {
+            (Prim::TextEncodingUtf8, []) => Some(TextEncoding::Utf8),
+            (Prim::TextEncodingUtf16Le, []) => Some(TextEncoding::Utf16Le),
+            (Prim::TextEncodingUtf16Be, []) => Some(TextEncoding::Utf16Be),
+            (Prim::TextEncodingAscii, []) => Some(TextEncoding::Ascii),
+            (Prim::TextEncodingLatin1, []) => Some(TextEncoding::Latin1),
+            _ => None,
+        }
+    }
+
+    /// The number of bytes in each code unit of the encoding.
+    pub fn code_unit_len(self) -> usize {
+        match self {
+            TextEncoding::Utf16Le | TextEncoding::Utf16Be => 2,
+            TextEncoding::Utf8 | TextEncoding::Ascii | TextEncoding::Latin1 => 1,
+        }
+    }
+
+    /// Decode a string, returning `None` if the bytes are not valid in this
+    /// encoding.
+    pub fn decode(self, bytes: &[u8]) -> Option<String> {
+        match self {
+            TextEncoding::Utf8 => String::from_utf8(bytes.to_vec()).ok(),
+            TextEncoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+            TextEncoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+            TextEncoding::Ascii => match bytes.is_ascii() {
+                true => Some(bytes.iter().map(|byte| char::from(*byte)).collect()),
+                false => None,
+            },
+            TextEncoding::Latin1 => Some(bytes.iter().map(|byte| char::from(*byte)).collect()),
+        }
+    }
+
+    /// Encode a string, returning `None` if it contains characters that cannot
+    /// be represented in this encoding.
+    pub fn encode(self, string: &str) -> Option<Vec<u8>> {
+        match self {
+            TextEncoding::Utf8 => Some(string.as_bytes().to_vec()),
+            TextEncoding::Utf16Le => {
+                Some(string.encode_utf16().flat_map(u16::to_le_bytes).collect())
+            }
+            TextEncoding::Utf16Be => {
+                Some(string.encode_utf16().flat_map(u16::to_be_bytes).collect())
+            }
+            TextEncoding::Ascii => match string.is_ascii() {
+                true => Some(string.as_bytes().to_vec()),
+                false => None,
+            },
+            TextEncoding::Latin1 => (string.chars()).map(|ch| u8::try_from(ch).ok()).collect(),
+        }
+    }
+}
+
+impl fmt::Display for TextEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextEncoding::Utf8 => f.write_str("UTF-8"),
+            TextEncoding::Utf16Le => f.write_str("UTF-16LE"),
+            TextEncoding::Utf16Be => f.write_str("UTF-16BE"),
+            TextEncoding::Ascii => f.write_str("ASCII"),
+            TextEncoding::Latin1 => f.write_str("Latin-1"),
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Option<String> {
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+
+    let units = bytes
+        .chunks_exact(2)
+        .map(|unit| from_bytes([unit[0], unit[1]]));
+    char::decode_utf16(units).collect::<Result<_, _>>().ok()
+}
+
+/// The compression schemes that data can be decompressed from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    Deflate,
+    Zlib,
+    Brotli,
+}
+
+impl Compression {
+    /// The format primitive that reads data compressed with this scheme.
+    pub fn prim(self) -> Prim {
+        match self {
+            Compression::Deflate => Prim::FormatDeflate,
+            Compression::Zlib => Prim::FormatZlib,
+            Compression::Brotli => Prim::FormatBrotli,
+        }
+    }
+
+    /// Decompress some data, producing at most `max_len` bytes of
+    /// decompressed data.
+    pub fn decompress(self, bytes: &[u8], max_len: usize) -> Result<Vec<u8>, DecompressError> {
+        use miniz_oxide::inflate::{self, TINFLStatus};
+
+        let result = match self {
+            Compression::Deflate => inflate::decompress_to_vec_with_limit(bytes, max_len),
+            Compression::Zlib => inflate::decompress_to_vec_zlib_with_limit(bytes, max_len),
+            Compression::Brotli => {
+                let mut writer = LimitedWriter {
+                    data: Vec::new(),
+                    max_len,
+                    is_limit_exceeded: false,
+                };
+                return match brotli_decompressor::BrotliDecompress(&mut &bytes[..], &mut writer) {
+                    Ok(()) => Ok(writer.data),
+                    Err(_) if writer.is_limit_exceeded => Err(DecompressError::LimitExceeded),
+                    Err(_) => Err(DecompressError::InvalidData),
+                };
+            }
+        };
+        result.map_err(|err| match err.status {
+            TINFLStatus::HasMoreOutput => DecompressError::LimitExceeded,
+            _ => DecompressError::InvalidData,
+        })
+    }
+}
+
+/// The reasons that data could not be decompressed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecompressError {
+    /// The data was not valid in the compression scheme.
+    InvalidData,
+    /// The decompressed data was longer than the maximum length.
+    LimitExceeded,
+}
+
+/// A writer that fails once more than `max_len` bytes are written to it.
+struct LimitedWriter {
+    data: Vec<u8>,
+    max_len: usize,
+    is_limit_exceeded: bool,
+}
+
+impl io::Write for LimitedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.max_len - self.data.len() {
+            self.is_limit_exceeded = true;
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Deflate => f.write_str("DEFLATE"),
+            Compression::Zlib => f.write_str("zlib"),
+            Compression::Brotli => f.write_str("Brotli"),
+        }
+    }
+}
+
+/// Binary data that was derived from a range of another buffer by
+/// decompressing it.
+///
+/// Position values in derived data refer to the derived buffer that they are
+/// in, so they remain distinct from positions in the initial buffer and in
+/// other derived buffers. Internally, derived buffers are also given offsets
+/// after the end of the initial buffer, with a gap of one offset between each
+/// of them, so that references into derived data can be cached and followed
+/// like any other reference.
+pub struct DerivedBuffer {
+    /// The compression scheme that the data was decompressed from.
+    pub compression: Compression,
+    /// The position of the compressed data.
+    pub source_pos: usize,
+    /// The length of the compressed data.
+    pub source_len: usize,
+    /// The offset of the start of the decompressed data, following the end of
+    /// the initial buffer and any previously derived buffers.
+    pub start_pos: usize,
+    /// The decompressed data.
+    data: Arc<[u8]>,
+}
+
+impl DerivedBuffer {
+    /// The offset of the end of the decompressed data.
+    pub fn end_pos(&self) -> usize {
+        self.start_pos + self.data.len()
+    }
+
+    /// The decompressed data.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// The binary data being read, along with any data derived from it.
+struct Buffers<'data> {
+    initial: Buffer<'data>,
+    // Invariant: sorted by `DerivedBuffer::start_pos`
+    derived: Vec<DerivedBuffer>,
+}
+
+impl<'data> Buffers<'data> {
+    /// Find the derived buffer that a position is in, if any.
+    fn find_derived(&self, pos: usize) -> Option<&DerivedBuffer> {
+        let index = self
+            .derived
+            .partition_point(|derived| derived.start_pos <= pos);
+        let derived = &self.derived[index.checked_sub(1)?];
+        (pos <= derived.end_pos()).then_some(derived)
+    }
+
+    /// The position that the next derived buffer should start at.
+    fn next_derived_pos(&self) -> Result<usize, BufferError> {
+        // Derived buffers are numbered from `1` in position values
+        if self.derived.len() >= usize::from(u16::MAX) {
+            return Err(BufferError::PositionOverflow);
+        }
+        let end_pos = match self.derived.last() {
+            Some(derived) => derived.end_pos(),
+            None => self.initial.len()?,
+        };
+        usize::checked_add(end_pos, 1).ok_or(BufferError::PositionOverflow)
+    }
+
+    /// Find the offset of a position value, failing if the position is past
+    /// the end of the buffer that it is in.
+    fn pos_to_offset(&self, pos: Pos) -> Result<usize, BufferError> {
+        let (start_pos, len) = match usize::from(pos.buffer()).checked_sub(1) {
+            None => (0, self.initial.len()?),
+            Some(index) => match self.derived.get(index) {
+                Some(derived) => (derived.start_pos, derived.data.len()),
+                None => return Err(BufferError::UnexpectedEndOfBuffer),
+            },
+        };
+        match pos.offset() <= len {
+            true => Ok(start_pos + pos.offset()),
+            false => Err(BufferError::UnexpectedEndOfBuffer),
+        }
+    }
+
+    /// Find the position value of an offset.
+    fn offset_to_pos(&self, offset: usize) -> Result<Pos, BufferError> {
+        let index = self
+            .derived
+            .partition_point(|derived| derived.start_pos <= offset);
+        let pos = match index.checked_sub(1) {
+            Some(index) if offset <= self.derived[index].end_pos() => {
+                // The number of derived buffers is checked when they are added
+                let buffer = (index + 1) as u16;
+                Pos::new(buffer, offset - self.derived[index].start_pos)
+            }
+            _ => Pos::initial(offset),
+        };
+        pos.ok_or(BufferError::PositionOverflow)
+    }
+}
+
+impl<'data> semantics::BinaryData for Buffers<'data> {
+    fn for_each_chunk(&self, pos: Pos, len: usize, on_chunk: &mut dyn FnMut(&[u8])) -> Option<()> {
+        let offset = self.pos_to_offset(pos).ok()?;
+        match usize::from(pos.buffer()).checked_sub(1) {
+            Some(index) => {
+                let derived = &self.derived[index];
+                let buffer = Buffer::new(derived.start_pos, derived.data());
+                buffer.for_each_chunk(offset, len, on_chunk)
+            }
+            None => self.initial.for_each_chunk(offset, len, on_chunk),
         }
     }
 }
 
 /// Stateful reader with a backing buffer.
+///
+/// Data is usually read a byte at a time, but the reader can also read
+/// individual bits. Once part of a byte has been read, the reader must be
+/// aligned to the next byte with [`BufferReader::align_to_byte`] before bytes
+/// can be read again.
 #[derive(Clone)]
 pub struct BufferReader<'data> {
     /// Offset relative to the start of the buffer.
     // Invariant: self.relative_offset <= self.buffer.remaining_len()
     relative_offset: usize,
+    /// Number of bits that have been read from the byte at the current
+    /// relative offset.
+    // Invariant: self.bit_offset < 8
+    bit_offset: u8,
     /// Backing buffer.
     buffer: Buffer<'data>,
 }
@@ -157,6 +769,16 @@ impl<'data> BufferReader<'data> {
             .ok_or(BufferError::PositionOverflow)
     }
 
+    /// The number of bits that have been read from the current byte.
+    pub fn bit_offset(&self) -> u8 {
+        self.bit_offset
+    }
+
+    /// Returns `true` if the reader is not part way through reading a byte.
+    pub fn is_byte_aligned(&self) -> bool {
+        self.bit_offset == 0
+    }
+
     /// Remaining number of bytes from the current position to the end of the
     /// buffer.
     pub fn remaining_len(&self) -> usize {
@@ -165,17 +787,25 @@ impl<'data> BufferReader<'data> {
 
     /// Return a buffer of the remaining data from the current relative offset.
     pub fn remaining_buffer(&self) -> Result<Buffer<'data>, BufferError> {
-        Ok(Buffer::new(
-            self.offset()?,
-            self.buffer.get_relative(self.relative_offset..)?,
-        ))
+        if !self.is_byte_aligned() {
+            return Err(BufferError::UnalignedRead);
+        }
+
+        let data = self.buffer.data;
+        Ok(Buffer {
+            start_offset: self.offset()?,
+            data: data.get_relative(self.relative_offset..data.len())?,
+        })
     }
 
     /// Set the offset of the reader relative to the start of the backing
     /// buffer.
     pub fn set_relative_offset(&mut self, relative_offset: usize) -> Result<(), BufferError> {
         (relative_offset <= self.buffer.remaining_len())
-            .then(|| self.relative_offset = relative_offset)
+            .then(|| {
+                self.relative_offset = relative_offset;
+                self.bit_offset = 0;
+            })
             .ok_or(BufferError::SetOffsetAfterEndOfBuffer {
                 offset: self.buffer.start_offset.checked_add(relative_offset),
             })
@@ -188,28 +818,99 @@ impl<'data> BufferReader<'data> {
             .and_then(|relative_offset| self.set_relative_offset(relative_offset))
     }
 
-    /// Get a slice of the bytes in the buffer, relative to the current offset
-    /// in the buffer.
-    fn get_relative<I: SliceIndex<[u8]>>(&self, index: I) -> Result<&'data I::Output, BufferError> {
-        let data = self.buffer.get_relative(self.relative_offset..)?;
-        data.get(index).ok_or(BufferError::UnexpectedEndOfBuffer)
+    /// Advance the reader by a number of bytes without reading them.
+    pub fn skip_bytes(&mut self, len: usize) -> Result<(), BufferError> {
+        if !self.is_byte_aligned() {
+            return Err(BufferError::UnalignedRead);
+        }
+        if len > self.remaining_len() {
+            return Err(BufferError::UnexpectedEndOfBuffer);
+        }
+
+        self.relative_offset += len;
+        Ok(())
     }
 
     /// Read a byte and advance the reader.
     pub fn read_byte(&mut self) -> Result<u8, BufferError> {
-        let first = self.buffer.get_relative(self.relative_offset)?;
-        self.relative_offset += 1;
-        Ok(*first)
+        let [byte] = self.read_byte_array()?;
+        Ok(byte)
     }
 
     /// Read an array of bytes and advance the offset into the buffer.
-    pub fn read_byte_array<const N: usize>(&mut self) -> Result<&'data [u8; N], BufferError> {
-        let slice = self.get_relative(..N)?;
-        // SAFETY: slice points to [u8; N]? Yes it's [u8] of length N (checked by
-        // BufferReader::get_relative)
-        let array = unsafe { &*(slice.as_ptr() as *const [u8; N]) };
+    pub fn read_byte_array<const N: usize>(&mut self) -> Result<[u8; N], BufferError> {
+        if !self.is_byte_aligned() {
+            return Err(BufferError::UnalignedRead);
+        }
+
+        let mut bytes = [0; N];
+        (self.buffer.data).copy_relative(self.relative_offset, &mut bytes)?;
         self.relative_offset += N;
-        Ok(array)
+        Ok(bytes)
+    }
+
+    /// Read a number of bytes into a vector and advance the offset into the
+    /// buffer.
+    pub fn read_byte_vec(&mut self, len: usize) -> Result<Vec<u8>, BufferError> {
+        if !self.is_byte_aligned() {
+            return Err(BufferError::UnalignedRead);
+        }
+        // Avoid allocating for lengths that could never be satisfied
+        if len > self.remaining_len() {
+            return Err(BufferError::UnexpectedEndOfBuffer);
+        }
+
+        let mut bytes = vec![0; len];
+        (self.buffer.data).copy_relative(self.relative_offset, &mut bytes)?;
+        self.relative_offset += len;
+        Ok(bytes)
+    }
+
+    /// Read a single bit and advance the reader.
+    pub fn read_bit(&mut self, order: BitOrder) -> Result<bool, BufferError> {
+        let mut byte = [0];
+        (self.buffer.data).copy_relative(self.relative_offset, &mut byte)?;
+
+        let shift = match order {
+            BitOrder::MsbFirst => 7 - self.bit_offset,
+            BitOrder::LsbFirst => self.bit_offset,
+        };
+
+        self.bit_offset += 1;
+        if self.bit_offset == 8 {
+            self.bit_offset = 0;
+            self.relative_offset += 1;
+        }
+
+        Ok((byte[0] >> shift) & 1 == 1)
+    }
+
+    /// Read an unsigned integer of `count` bits and advance the reader. Bits
+    /// are taken from each byte in the supplied order, with the first bit read
+    /// being the most significant bit of the result in the case of
+    /// [`BitOrder::MsbFirst`], and the least significant bit in the case of
+    /// [`BitOrder::LsbFirst`].
+    pub fn read_bits(&mut self, order: BitOrder, count: u8) -> Result<u64, BufferError> {
+        debug_assert!(count <= 64);
+
+        let mut value = 0;
+        for index in 0..count {
+            let bit = u64::from(self.read_bit(order)?);
+            value = match order {
+                BitOrder::MsbFirst => (value << 1) | bit,
+                BitOrder::LsbFirst => value | (bit << index),
+            };
+        }
+
+        Ok(value)
+    }
+
+    /// Skip the remaining bits of a partially read byte, if any.
+    pub fn align_to_byte(&mut self) {
+        if !self.is_byte_aligned() {
+            self.bit_offset = 0;
+            self.relative_offset += 1;
+        }
     }
 }
 
@@ -217,6 +918,7 @@ impl<'data> From<Buffer<'data>> for BufferReader<'data> {
     fn from(buffer: Buffer<'data>) -> BufferReader<'data> {
         BufferReader {
             relative_offset: 0,
+            bit_offset: 0,
             buffer,
         }
     }
@@ -228,6 +930,8 @@ pub enum BufferError {
     SetOffsetAfterEndOfBuffer { offset: Option<usize> },
     UnexpectedEndOfBuffer,
     PositionOverflow,
+    UnalignedRead,
+    ReadFailed { offset: usize, kind: io::ErrorKind },
 }
 
 impl BufferError {
@@ -247,18 +951,83 @@ impl fmt::Display for BufferError {
             }
             BufferError::UnexpectedEndOfBuffer => f.write_str("unexpected end of buffer"),
             BufferError::PositionOverflow => f.write_str("position overflow"),
+            BufferError::UnalignedRead => {
+                f.write_str("attempt to read bytes part way through a byte")
+            }
+            BufferError::ReadFailed { kind, .. } => write!(f, "failed to read data: {kind}"),
         }
     }
 }
 
 impl std::error::Error for BufferError {}
 
+/// Limits on the resources used when reading binary data, for reading data
+/// from untrusted sources. Every limit is unbounded by default.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReadLimits {
+    /// The maximum number of elements in an array.
+    pub max_array_len: Option<u64>,
+    /// The maximum number of values read in total.
+    pub max_values: Option<usize>,
+    /// The maximum depth that formats can be nested when reading, including
+    /// formats read by following links.
+    pub max_depth: Option<usize>,
+    /// The maximum number of links that are waiting to be read.
+    pub max_pending_links: Option<usize>,
+    /// The maximum number of formats that can be read, including those that
+    /// failed to be read.
+    pub max_steps: Option<u64>,
+    /// The maximum number of bytes that can be decompressed by compressed
+    /// formats, in total.
+    pub max_decompressed_len: Option<usize>,
+}
+
 pub struct Context<'arena, 'data> {
+    /// Scope used to allocate the strings that are decoded from the data.
+    scope: &'arena Scope<'arena>,
     item_exprs: UniqueEnv<ArcValue<'arena>>,
     local_exprs: SharedEnv<ArcValue<'arena>>,
-    initial_buffer: Buffer<'data>,
-    pending_formats: Vec<(usize, ArcValue<'arena>)>,
-    cached_refs: HashMap<usize, Vec<ParsedRef<'arena>>>,
+    buffers: Buffers<'data>,
+    /// The positions and indices of the cached references that are yet to
+    /// be read.
+    pending_refs: Vec<(usize, usize)>,
+    cached_refs: HashMap<usize, Vec<CachedRef<'arena>>>,
+    /// The positions of the references allocated in the reference cache, in
+    /// the order that they were allocated.
+    allocated_refs: Vec<usize>,
+    /// The positions and indices of the cached references that are currently
+    /// being read, from outermost to innermost.
+    reading_refs: Vec<(usize, usize)>,
+    limits: ReadLimits,
+    /// The number of values that have been read.
+    values: usize,
+    /// The depth of the format currently being read.
+    depth: usize,
+    /// The number of formats that have been read.
+    steps: u64,
+    /// Whether to recover from errors when reading fields and references.
+    recover: bool,
+    /// The errors that have been recovered from.
+    error_nodes: Vec<ErrorNode<'arena>>,
+    /// Where the values were read from, if provenance is being recorded.
+    provenance: Option<ProvenanceMap>,
+    /// The path to the value currently being read.
+    current_path: ValuePath,
+    /// The indices of the provenance entries of the value currently being
+    /// read and the values that contain it, outermost first.
+    current_entries: Vec<usize>,
+}
+
+/// An error that was recovered from while reading, in place of which an error
+/// value was produced.
+#[derive(Clone, Debug)]
+pub struct ErrorNode<'arena> {
+    /// The format that failed to be read
+    pub span: Span,
+    /// The position at which the format started to be read
+    pub pos: usize,
+    /// The error that was encountered
+    pub error: ReadError<'arena>,
 }
 
 pub struct ParsedRef<'arena> {
@@ -270,57 +1039,578 @@ pub struct ParsedRef<'arena> {
     pub expr: ArcValue<'arena>,
 }
 
-impl<'arena, 'data> Context<'arena, 'data> {
-    pub fn new(initial_buffer: Buffer<'data>) -> Context<'arena, 'data> {
-        Context {
-            item_exprs: UniqueEnv::new(),
-            local_exprs: SharedEnv::new(),
-            initial_buffer,
-            pending_formats: Vec::new(),
-            cached_refs: HashMap::new(),
-        }
-    }
+/// A reference in the reference cache. References are allocated when a link to
+/// them is read, allowing links to store the index of the reference, and their
+/// expressions are filled in once they have been read.
+struct CachedRef<'arena> {
+    // Invariant: `format : Format`
+    format: ArcValue<'arena>,
+    // Invariant: `expr : Repr format`
+    expr: Option<ArcValue<'arena>>,
+}
 
-    fn eval_env(&mut self) -> semantics::EvalEnv<'arena, '_> {
-        let elim_env = semantics::ElimEnv::new(&self.item_exprs, [][..].into());
-        semantics::EvalEnv::new(elim_env, &mut self.local_exprs)
+/// A value contained within another value that was read.
+pub struct ValueChild<'arena> {
+    /// The step from the containing value to this value
+    pub segment: PathSegment,
+    /// The format that this value was read with
+    // Invariant: `format : Format`
+    pub format: ArcValue<'arena>,
+    /// The value that was read
+    // Invariant: `expr : Repr format`
+    pub expr: ArcValue<'arena>,
+}
+
+/// A step in the path from a parsed reference to a value within it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    /// A field of a record
+    Field(Symbol),
+    /// An element of an array
+    Index(usize),
+}
+
+/// The path to a value that was read, starting from the parsed reference that
+/// contains it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ValuePath {
+    /// The position of the reference
+    pub ref_pos: usize,
+    /// The index of the reference in the references parsed at `ref_pos`
+    pub ref_index: usize,
+    /// The fields and elements leading to the value
+    pub segments: Vec<PathSegment>,
+}
+
+impl ValuePath {
+    fn new(ref_pos: usize, ref_index: usize) -> ValuePath {
+        ValuePath {
+            ref_pos,
+            ref_index,
+            segments: Vec::new(),
+        }
     }
+}
 
-    fn elim_env(&self) -> semantics::ElimEnv<'arena, '_> {
-        semantics::ElimEnv::new(&self.item_exprs, [][..].into())
+impl fmt::Display for ValuePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_segments(f, &self.segments)
     }
+}
 
-    pub fn add_module(&mut self, module: &Module<'arena>) {
-        self.item_exprs.reserve(module.items.len());
-        for item in module.items {
-            match item {
-                Item::Def { expr, .. } => {
-                    let expr = self.eval_env().eval(expr);
-                    self.item_exprs.push(expr);
-                }
-            }
+fn fmt_segments(f: &mut fmt::Formatter<'_>, segments: &[PathSegment]) -> fmt::Result {
+    for (index, segment) in segments.iter().enumerate() {
+        match segment {
+            PathSegment::Field(label) if index == 0 => write!(f, "{}", label.resolve())?,
+            PathSegment::Field(label) => write!(f, ".{}", label.resolve())?,
+            PathSegment::Index(index) => write!(f, "[{index}]")?,
+        }
+    }
+    Ok(())
+}
+
+/// The range of binary data that a value was read from.
+#[derive(Copy, Clone, Debug)]
+pub struct ValueRange {
+    /// The position of the first byte that was read
+    pub start: usize,
+    /// The position after the last byte that was read
+    pub end: usize,
+    /// The format that the value was read with
+    pub span: Span,
+}
+
+/// The ranges of binary data that values were read from, in the order that
+/// the values were read.
+#[derive(Clone, Debug, Default)]
+pub struct ProvenanceMap {
+    entries: Vec<(ValuePath, ValueRange)>,
+    indices: HashMap<ValuePath, usize>,
+}
+
+impl ProvenanceMap {
+    /// The range of data that the value at a path was read from.
+    pub fn get(&self, path: &ValuePath) -> Option<&ValueRange> {
+        let index = self.indices.get(path)?;
+        Some(&self.entries[*index].1)
+    }
+
+    /// Iterate over the values in the order that they were read. Values are
+    /// visited before the values contained within them.
+    pub fn iter(&self) -> impl Iterator<Item = (&ValuePath, &ValueRange)> {
+        self.entries.iter().map(|(path, range)| (path, range))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn push(&mut self, path: ValuePath, range: ValueRange) -> usize {
+        let index = self.entries.len();
+        self.indices.insert(path.clone(), index);
+        self.entries.push((path, range));
+        index
+    }
+
+    fn extend_end(&mut self, index: usize, end: usize) {
+        let range = &mut self.entries[index].1;
+        range.end = std::cmp::max(range.end, end);
+    }
+
+    /// Forget the values read after the first `len` values, for example when
+    /// they were part of a format that failed to be read.
+    fn truncate(&mut self, len: usize) {
+        for (path, _) in self.entries.drain(len..) {
+            self.indices.remove(&path);
+        }
+    }
+}
+
+impl<'arena, 'data> Context<'arena, 'data> {
+    pub fn new(
+        scope: &'arena Scope<'arena>,
+        initial_buffer: Buffer<'data>,
+    ) -> Context<'arena, 'data> {
+        Context {
+            scope,
+            item_exprs: UniqueEnv::new(),
+            local_exprs: SharedEnv::new(),
+            buffers: Buffers {
+                initial: initial_buffer,
+                derived: Vec::new(),
+            },
+            pending_refs: Vec::new(),
+            cached_refs: HashMap::new(),
+            allocated_refs: Vec::new(),
+            reading_refs: Vec::new(),
+            limits: ReadLimits::default(),
+            values: 0,
+            depth: 0,
+            steps: 0,
+            recover: false,
+            error_nodes: Vec::new(),
+            provenance: None,
+            current_path: ValuePath::new(0, 0),
+            current_entries: Vec::new(),
+        }
+    }
+
+    /// Record the ranges of binary data that each value was read from. The
+    /// ranges can be retrieved with [`Context::provenance`].
+    pub fn set_record_provenance(&mut self, record_provenance: bool) {
+        self.provenance = match record_provenance {
+            true => Some(ProvenanceMap::default()),
+            false => None,
+        };
+    }
+
+    /// The ranges of binary data that values were read from, if provenance
+    /// is being recorded.
+    pub fn provenance(&self) -> Option<&ProvenanceMap> {
+        self.provenance.as_ref()
+    }
+
+    /// Recover from errors encountered when reading record fields and
+    /// references, producing error values in their place and continuing with
+    /// the remaining fields and references. The errors can be retrieved with
+    /// [`Context::error_nodes`].
+    pub fn set_recover(&mut self, recover: bool) {
+        self.recover = recover;
+    }
+
+    /// Limit the resources used when reading. The limits apply to everything
+    /// read with this context.
+    pub fn set_limits(&mut self, limits: ReadLimits) {
+        self.limits = limits;
+    }
+
+    /// The limits on the resources used when reading.
+    pub fn limits(&self) -> &ReadLimits {
+        &self.limits
+    }
+
+    /// The errors that were recovered from while reading.
+    pub fn error_nodes(&self) -> &[ErrorNode<'arena>] {
+        &self.error_nodes
+    }
+
+    fn eval_env(&mut self) -> semantics::EvalEnv<'arena, '_> {
+        let elim_env = semantics::ElimEnv::new(&self.item_exprs, [][..].into())
+            .with_binary_data(&self.buffers);
+        semantics::EvalEnv::new(elim_env, &mut self.local_exprs)
+    }
+
+    fn elim_env(&self) -> semantics::ElimEnv<'arena, '_> {
+        semantics::ElimEnv::new(&self.item_exprs, [][..].into()).with_binary_data(&self.buffers)
+    }
+
+    pub fn add_module(&mut self, module: &Module<'arena>) {
+        self.item_exprs.reserve(module.items.len());
+        for item in module.items {
+            match item {
+                Item::Def { expr, .. } => {
+                    let expr = self.eval_env().eval(expr);
+                    self.item_exprs.push(expr);
+                }
+            }
         }
     }
 
     pub fn read_entrypoint(
-        mut self,
+        &mut self,
         format: &Term<'arena>,
     ) -> Result<HashMap<usize, Vec<ParsedRef<'arena>>>, ReadError<'arena>> {
         // Parse the entrypoint from the start of the binary data
-        let offset = self.initial_buffer.start_offset();
+        let offset = self.buffers.initial.start_offset();
+        self.read_entrypoint_at(format, offset, None)
+    }
+
+    /// Read the entrypoint from an offset in the initial buffer, limiting it
+    /// to `len` bytes if a length is provided. Positions remain relative to
+    /// the start of the initial buffer, and references can be read from
+    /// anywhere in the buffer.
+    pub fn read_entrypoint_at(
+        &mut self,
+        format: &Term<'arena>,
+        offset: usize,
+        len: Option<usize>,
+    ) -> Result<HashMap<usize, Vec<ParsedRef<'arena>>>, ReadError<'arena>> {
         let format = self.eval_env().eval(format);
-        self.pending_formats.push((offset, format));
+        let index = self.allocate_ref(offset, &format);
+
+        match len {
+            None => self.pending_refs.push((offset, index)),
+            Some(len) => {
+                let buffer = (self.buffers.initial.reader_with_offset(offset))
+                    .and_then(|reader| reader.remaining_buffer())
+                    .and_then(|buffer| buffer.with_remaining_len(len))?;
+                let result = self.read_ref(offset, index, buffer.reader());
+                self.recover_ref(offset, index, result)?;
+            }
+        }
+
+        while let Some((pos, index)) = self.pending_refs.pop() {
+            let result = self.lookup_or_read_ref(pos, index);
+            self.recover_ref(pos, index, result)?;
+        }
+
+        self.allocated_refs.clear();
+        let cached_refs = std::mem::take(&mut self.cached_refs);
+        let parsed_refs = cached_refs.into_iter().map(|(pos, cached_refs)| {
+            let parsed_refs = cached_refs.into_iter().map(|cached_ref| ParsedRef {
+                format: cached_ref.format,
+                // Every reference that was allocated has been read once there
+                // are no more pending references
+                expr: cached_ref.expr.expect("reference was not read"),
+            });
+            (pos, parsed_refs.collect())
+        });
+
+        Ok(parsed_refs.collect())
+    }
+
+    /// Recover from an error encountered when reading a reference, if
+    /// recovering from errors.
+    fn recover_ref(
+        &mut self,
+        pos: usize,
+        index: usize,
+        result: Result<ArcValue<'arena>, ReadError<'arena>>,
+    ) -> Result<(), ReadError<'arena>> {
+        match result {
+            Ok(_) => Ok(()),
+            Err(error) if self.recover && !error.is_limit_exceeded() => {
+                let cached_ref = &self.cached_refs[&pos][index];
+                let expr = self.recover_error(cached_ref.format.span(), pos, error);
+                self.cached_refs.get_mut(&pos).unwrap()[index].expr = Some(expr);
+                Ok(())
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The buffers that were decompressed while reading.
+    pub fn derived_buffers(&self) -> &[DerivedBuffer] {
+        &self.buffers.derived
+    }
+
+    /// Render a position for display, describing positions in derived
+    /// buffers relative to the start of the derived data and the position of
+    /// the data it was derived from. For example `zlib[12]+4` is four bytes
+    /// into the data decompressed from the zlib stream at position `12`.
+    pub fn pos_to_string(&self, pos: usize) -> String {
+        match self.buffers.offset_to_pos(pos) {
+            Ok(pos) => self.pos_value_to_string(pos),
+            Err(_) => pos.to_string(),
+        }
+    }
+
+    /// Find the offset that references to a position value are keyed by in
+    /// the references returned by [`Context::read_entrypoint`], returning
+    /// `None` if the position is past the end of its buffer.
+    pub fn pos_to_offset(&self, pos: Pos) -> Option<usize> {
+        self.buffers.pos_to_offset(pos).ok()
+    }
+
+    /// Render a position value for display, in the same way as
+    /// [`Context::pos_to_string`].
+    pub fn pos_value_to_string(&self, pos: Pos) -> String {
+        let derived = (usize::from(pos.buffer()).checked_sub(1))
+            .and_then(|index| self.buffers.derived.get(index));
+        match derived {
+            None => pos.offset().to_string(),
+            Some(derived) => format!(
+                "{}[{}]+{}",
+                derived.compression.prim().name(),
+                self.pos_to_string(derived.source_pos),
+                pos.offset(),
+            ),
+        }
+    }
+
+    /// Remove the fields of an expression that were read using `skip`,
+    /// `align` or `reserved` formats, so that they can be omitted when
+    /// displaying the data that was read.
+    pub fn hide_skipped(
+        &self,
+        scope: &'arena Scope<'arena>,
+        format: &ArcValue<'arena>,
+        expr: &ArcValue<'arena>,
+    ) -> ArcValue<'arena> {
+        use crate::core::semantics::Elim::FunApp;
+
+        match (format.as_ref(), expr.as_ref()) {
+            (
+                Value::FormatRecord(_, formats) | Value::FormatOverlap(_, formats),
+                Value::RecordLit(labels, exprs),
+            ) => {
+                let mut formats = formats.clone();
+                let mut fields = Vec::with_capacity(exprs.len());
+
+                for (label, expr) in Iterator::zip(labels.iter(), exprs.iter()) {
+                    let (format, next_formats) = match self.elim_env().split_telescope(formats) {
+                        Some(split) => split,
+                        None => break,
+                    };
+                    if !is_skipped_format(&format) {
+                        fields.push((*label, self.hide_skipped(scope, &format, expr)));
+                    }
+                    formats = next_formats(expr.clone());
+                }
 
-        while let Some((pos, format)) = self.pending_formats.pop() {
-            self.lookup_or_read_ref(pos, &format)?;
+                let labels = scope.to_scope_from_iter(fields.iter().map(|(label, _)| *label));
+                let exprs = fields.into_iter().map(|(_, expr)| expr).collect();
+                Spanned::new(expr.span(), Arc::new(Value::RecordLit(labels, exprs)))
+            }
+            (Value::FormatCond(_, format, _), _) => self.hide_skipped(scope, format, expr),
+            (Value::Stuck(Head::Prim(prim), slice), _) => match (prim, &slice[..]) {
+                (
+                    Prim::FormatRepeatLen8
+                    | Prim::FormatRepeatLen16
+                    | Prim::FormatRepeatLen32
+                    | Prim::FormatRepeatLen64,
+                    [_, FunApp(_, elem_format)],
+                )
+                | (Prim::FormatRepeatUntilEnd, [FunApp(_, elem_format)])
+                | (
+                    Prim::FormatRepeatUntil | Prim::FormatRepeatWhile,
+                    [FunApp(_, elem_format), _],
+                ) => match expr.as_ref() {
+                    Value::ArrayLit(elem_exprs) => {
+                        let elem_exprs = (elem_exprs.iter())
+                            .map(|elem_expr| self.hide_skipped(scope, elem_format, elem_expr))
+                            .collect();
+                        Spanned::new(expr.span(), Arc::new(Value::ArrayLit(elem_exprs)))
+                    }
+                    _ => expr.clone(),
+                },
+                (
+                    Prim::FormatLimit8
+                    | Prim::FormatLimit16
+                    | Prim::FormatLimit32
+                    | Prim::FormatLimit64
+                    | Prim::FormatLimitExact8
+                    | Prim::FormatLimitExact16
+                    | Prim::FormatLimitExact32
+                    | Prim::FormatLimitExact64
+                    | Prim::FormatDeflate
+                    | Prim::FormatZlib
+                    | Prim::FormatBrotli,
+                    [_, FunApp(_, elem_format)],
+                )
+                | (Prim::FormatDeref, [FunApp(_, elem_format), _]) => {
+                    self.hide_skipped(scope, elem_format, expr)
+                }
+                (Prim::FormatChoice, [FunApp(_, formats)]) => {
+                    match (formats.as_ref(), expr.as_ref()) {
+                        (Value::ArrayLit(formats), Value::RecordLit(labels, exprs)) => {
+                            let format = match exprs[0].as_ref() {
+                                Value::ConstLit(Const::U64(index, _)) => {
+                                    usize::try_from(*index).ok().and_then(|i| formats.get(i))
+                                }
+                                _ => None,
+                            };
+                            match format {
+                                Some(format) => {
+                                    let value = self.hide_skipped(scope, format, &exprs[1]);
+                                    let exprs = vec![exprs[0].clone(), value];
+                                    Spanned::new(
+                                        expr.span(),
+                                        Arc::new(Value::RecordLit(labels, exprs)),
+                                    )
+                                }
+                                None => expr.clone(),
+                            }
+                        }
+                        _ => expr.clone(),
+                    }
+                }
+                _ => expr.clone(),
+            },
+            _ => expr.clone(),
         }
+    }
+
+    /// The values contained within a value that was read, along with the
+    /// formats that they were read with. Data that was skipped over while
+    /// reading is omitted.
+    pub fn value_children(
+        &self,
+        format: &ArcValue<'arena>,
+        expr: &ArcValue<'arena>,
+    ) -> Vec<ValueChild<'arena>> {
+        use crate::core::semantics::Elim::FunApp;
+
+        match (format.as_ref(), expr.as_ref()) {
+            (
+                Value::FormatRecord(_, formats) | Value::FormatOverlap(_, formats),
+                Value::RecordLit(labels, exprs),
+            ) => {
+                let mut formats = formats.clone();
+                let mut children = Vec::with_capacity(exprs.len());
+
+                for (label, expr) in Iterator::zip(labels.iter(), exprs.iter()) {
+                    let (format, next_formats) = match self.elim_env().split_telescope(formats) {
+                        Some(split) => split,
+                        None => break,
+                    };
+                    if !is_skipped_format(&format) {
+                        let segment = PathSegment::Field(*label);
+                        let expr = expr.clone();
+                        children.push(ValueChild {
+                            segment,
+                            format,
+                            expr,
+                        });
+                    }
+                    formats = next_formats(expr.clone());
+                }
 
-        Ok(self.cached_refs)
+                children
+            }
+            (Value::FormatCond(_, format, _), _) => self.value_children(format, expr),
+            (Value::Stuck(Head::Prim(prim), slice), _) => match (prim, &slice[..]) {
+                (
+                    Prim::FormatRepeatLen8
+                    | Prim::FormatRepeatLen16
+                    | Prim::FormatRepeatLen32
+                    | Prim::FormatRepeatLen64,
+                    [_, FunApp(_, elem_format)],
+                )
+                | (Prim::FormatRepeatUntilEnd, [FunApp(_, elem_format)])
+                | (
+                    Prim::FormatRepeatUntil | Prim::FormatRepeatWhile,
+                    [FunApp(_, elem_format), _],
+                ) => match expr.as_ref() {
+                    Value::ArrayLit(elem_exprs) => (elem_exprs.iter().enumerate())
+                        .map(|(index, elem_expr)| ValueChild {
+                            segment: PathSegment::Index(index),
+                            format: elem_format.clone(),
+                            expr: elem_expr.clone(),
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                },
+                (
+                    Prim::FormatLimit8
+                    | Prim::FormatLimit16
+                    | Prim::FormatLimit32
+                    | Prim::FormatLimit64
+                    | Prim::FormatLimitExact8
+                    | Prim::FormatLimitExact16
+                    | Prim::FormatLimitExact32
+                    | Prim::FormatLimitExact64
+                    | Prim::FormatDeflate
+                    | Prim::FormatZlib
+                    | Prim::FormatBrotli,
+                    [_, FunApp(_, elem_format)],
+                )
+                | (Prim::FormatDeref, [FunApp(_, elem_format), _]) => {
+                    self.value_children(elem_format, expr)
+                }
+                (Prim::FormatChoice, [FunApp(_, formats)]) => {
+                    match (formats.as_ref(), expr.as_ref()) {
+                        (Value::ArrayLit(formats), Value::RecordLit(labels, exprs)) => {
+                            let format = match exprs[0].as_ref() {
+                                Value::ConstLit(Const::U64(index, _)) => {
+                                    usize::try_from(*index).ok().and_then(|i| formats.get(i))
+                                }
+                                _ => None,
+                            };
+                            match format {
+                                Some(format) => vec![ValueChild {
+                                    segment: PathSegment::Field(labels[1]),
+                                    format: format.clone(),
+                                    expr: exprs[1].clone(),
+                                }],
+                                None => Vec::new(),
+                            }
+                        }
+                        _ => Vec::new(),
+                    }
+                }
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
     }
 
+    /// Read a format, keeping track of the resources used against the
+    /// [`ReadLimits`].
     fn read_format(
         &mut self,
-        reader: &mut BufferReader<'data>,
+        reader: &mut BufferReader<'_>,
+        format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let span = format.span();
+        self.steps += 1;
+        if (self.limits.max_steps).map_or(false, |max_steps| self.steps > max_steps) {
+            return Err(ReadError::StepLimitExceeded(span));
+        }
+        if (self.limits.max_depth).map_or(false, |max_depth| self.depth >= max_depth) {
+            return Err(ReadError::DepthLimitExceeded(span));
+        }
+
+        self.depth += 1;
+        let result = self.read_nested_format(reader, format);
+        self.depth -= 1;
+        let expr = result?;
+
+        self.values += 1;
+        if (self.limits.max_values).map_or(false, |max_values| self.values > max_values) {
+            return Err(ReadError::ValueLimitExceeded(span));
+        }
+
+        Ok(expr)
+    }
+
+    fn read_nested_format(
+        &mut self,
+        reader: &mut BufferReader<'_>,
         format: &ArcValue<'arena>,
     ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
         match format.as_ref() {
@@ -332,7 +1622,8 @@ impl<'arena, 'data> Context<'arena, 'data> {
                 let mut exprs = Vec::with_capacity(formats.len());
 
                 while let Some((format, next_formats)) = self.elim_env().split_telescope(formats) {
-                    let expr = self.read_format(reader, &format)?;
+                    let label = labels[exprs.len()];
+                    let expr = self.read_field(reader, label, &format)?;
                     exprs.push(expr.clone());
                     formats = next_formats(expr);
                 }
@@ -358,7 +1649,7 @@ impl<'arena, 'data> Context<'arena, 'data> {
                 }
             }
             Value::FormatOverlap(labels, formats) => {
-                let mut max_relative_offset = reader.relative_offset();
+                let mut max_reader = reader.clone();
 
                 let mut formats = formats.clone();
                 let mut exprs = Vec::with_capacity(formats.len());
@@ -366,17 +1657,20 @@ impl<'arena, 'data> Context<'arena, 'data> {
                 while let Some((format, next_formats)) = self.elim_env().split_telescope(formats) {
                     let mut reader = reader.clone();
 
-                    let expr = self.read_format(&mut reader, &format)?;
+                    let label = labels[exprs.len()];
+                    let expr = self.read_field(&mut reader, label, &format)?;
                     exprs.push(expr.clone());
                     formats = next_formats(expr);
 
-                    max_relative_offset =
-                        std::cmp::max(max_relative_offset, reader.relative_offset());
+                    let position =
+                        |reader: &BufferReader<'_>| (reader.relative_offset(), reader.bit_offset());
+                    if position(&reader) > position(&max_reader) {
+                        max_reader = reader;
+                    }
                 }
 
-                // Seek to the maximum stream length. unwrap is safe due to that offset being
-                // reached in loop above.
-                reader.set_relative_offset(max_relative_offset).unwrap();
+                // Seek to the maximum stream length
+                *reader = max_reader;
 
                 Ok(Spanned::new(
                     format.span(),
@@ -396,10 +1690,136 @@ impl<'arena, 'data> Context<'arena, 'data> {
         }
     }
 
+    /// Read the format of a field, producing an error value in place of the
+    /// field if an error is encountered when recovering from errors.
+    fn read_field(
+        &mut self,
+        reader: &mut BufferReader<'_>,
+        label: Symbol,
+        format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let pos = reader
+            .offset()
+            .map_err(|err| err.with_span(format.span()))?;
+
+        match self.read_segment(reader, PathSegment::Field(label), format) {
+            Err(error) if self.recover && !error.is_limit_exceeded() => {
+                Ok(self.recover_error(format.span(), pos, error))
+            }
+            result => result,
+        }
+    }
+
+    /// Record an error that was recovered from, returning the error value that
+    /// is used in place of the data that could not be read.
+    fn recover_error(
+        &mut self,
+        span: Span,
+        pos: usize,
+        error: ReadError<'arena>,
+    ) -> ArcValue<'arena> {
+        self.error_nodes.push(ErrorNode { span, pos, error });
+        Spanned::new(span, Arc::new(Value::prim(Prim::ReportedError, [])))
+    }
+
+    /// Read a segment without recovering from errors, for formats that depend
+    /// on errors to decide how to continue reading.
+    fn read_segment_without_recovery(
+        &mut self,
+        reader: &mut BufferReader<'_>,
+        segment: PathSegment,
+        format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let recover = std::mem::replace(&mut self.recover, false);
+        let result = self.read_segment(reader, segment, format);
+        self.recover = recover;
+        result
+    }
+
+    /// Read a value contained within the value currently being read.
+    fn read_segment(
+        &mut self,
+        reader: &mut BufferReader<'_>,
+        segment: PathSegment,
+        format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        self.current_path.segments.push(segment);
+        let result = self.read_value(reader, format);
+        self.current_path.segments.pop();
+        result
+    }
+
+    /// Read the value at the current path, recording the range of data that
+    /// it was read from if provenance is being recorded.
+    fn read_value(
+        &mut self,
+        reader: &mut BufferReader<'_>,
+        format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let provenance = match &mut self.provenance {
+            Some(provenance) => provenance,
+            None => return self.read_format(reader, format),
+        };
+
+        let span = format.span();
+        let start = reader.offset().map_err(|err| err.with_span(span))?;
+        let provenance_len = provenance.len();
+        let range = ValueRange {
+            start,
+            end: start,
+            span,
+        };
+        let entry = provenance.push(self.current_path.clone(), range);
+
+        self.current_entries.push(entry);
+        let result = self.read_format(reader, format);
+        self.current_entries.pop();
+
+        match result {
+            Ok(expr) => {
+                let end = reader.offset().map_err(|err| err.with_span(span))?;
+                self.extend_provenance(entry, end);
+                Ok(expr)
+            }
+            Err(err) => {
+                // Forget the values read as part of the failed format
+                self.truncate_provenance(provenance_len);
+                Err(err)
+            }
+        }
+    }
+
+    fn provenance_len(&self) -> usize {
+        self.provenance.as_ref().map_or(0, ProvenanceMap::len)
+    }
+
+    fn truncate_provenance(&mut self, len: usize) {
+        if let Some(provenance) = &mut self.provenance {
+            provenance.truncate(len);
+        }
+    }
+
+    fn extend_provenance(&mut self, entry: usize, end: usize) {
+        if let Some(provenance) = &mut self.provenance {
+            provenance.extend_end(entry, end);
+        }
+    }
+
+    /// Extend the range of the value currently being read, along with the
+    /// values that contain it, for formats that cover more data than they
+    /// advance the reader past.
+    fn extend_current_provenance(&mut self, end: usize) {
+        if let Some(provenance) = &mut self.provenance {
+            for entry in &self.current_entries {
+                provenance.extend_end(*entry, end);
+            }
+        }
+    }
+
     #[rustfmt::skip]
     fn read_prim(
         &mut self,
-        reader: &mut BufferReader<'data>,
+        reader: &mut BufferReader<'_>,
         prim: Prim,
         slice: &[Elim<'arena>],
         span: Span,
@@ -425,18 +1845,63 @@ impl<'arena, 'data> Context<'arena, 'data> {
             (Prim::FormatF32Le, []) => read_const(reader, span, read_f32le, Const::F32),
             (Prim::FormatF64Be, []) => read_const(reader, span, read_f64be, Const::F64),
             (Prim::FormatF64Le, []) => read_const(reader, span, read_f64le, Const::F64),
+            (Prim::FormatBitMsb, []) => read_bit(reader, span, BitOrder::MsbFirst),
+            (Prim::FormatBitLsb, []) => read_bit(reader, span, BitOrder::LsbFirst),
+            (Prim::FormatBits8Msb, [FunApp(_, count)]) => read_bits(reader, span, count, BitOrder::MsbFirst, 8, |num| Const::U8(num as u8, UIntStyle::Decimal)),
+            (Prim::FormatBits8Lsb, [FunApp(_, count)]) => read_bits(reader, span, count, BitOrder::LsbFirst, 8, |num| Const::U8(num as u8, UIntStyle::Decimal)),
+            (Prim::FormatBits16Msb, [FunApp(_, count)]) => read_bits(reader, span, count, BitOrder::MsbFirst, 16, |num| Const::U16(num as u16, UIntStyle::Decimal)),
+            (Prim::FormatBits16Lsb, [FunApp(_, count)]) => read_bits(reader, span, count, BitOrder::LsbFirst, 16, |num| Const::U16(num as u16, UIntStyle::Decimal)),
+            (Prim::FormatBits32Msb, [FunApp(_, count)]) => read_bits(reader, span, count, BitOrder::MsbFirst, 32, |num| Const::U32(num as u32, UIntStyle::Decimal)),
+            (Prim::FormatBits32Lsb, [FunApp(_, count)]) => read_bits(reader, span, count, BitOrder::LsbFirst, 32, |num| Const::U32(num as u32, UIntStyle::Decimal)),
+            (Prim::FormatBits64Msb, [FunApp(_, count)]) => read_bits(reader, span, count, BitOrder::MsbFirst, 64, |num| Const::U64(num, UIntStyle::Decimal)),
+            (Prim::FormatBits64Lsb, [FunApp(_, count)]) => read_bits(reader, span, count, BitOrder::LsbFirst, 64, |num| Const::U64(num, UIntStyle::Decimal)),
+            (Prim::FormatByteAlign, []) => {
+                reader.align_to_byte();
+                Ok(Spanned::new(span, Arc::new(Value::RecordLit(&[], Vec::new()))))
+            }
+            (Prim::FormatUleb128, []) => read_varint(reader, span, read_uleb128, |num| Const::U64(num, UIntStyle::Decimal)),
+            (Prim::FormatSleb128, []) => read_varint(reader, span, read_sleb128, Const::S64),
+            (Prim::FormatVarintZigzag, []) => read_varint(reader, span, read_varint_zigzag, Const::S64),
+            (Prim::FormatVlqBe, []) => read_varint(reader, span, read_vlq_be, |num| Const::U64(num, UIntStyle::Decimal)),
+            (Prim::FormatStringNul, [FunApp(_, encoding)]) => read_string_nul(self.scope, reader, span, encoding),
+            (Prim::FormatStringPrefixed8, [FunApp(_, encoding)]) => read_string_prefixed(self.scope, reader, span, encoding, |reader| read_u8(reader).map(u64::from)),
+            (Prim::FormatStringPrefixed16Be, [FunApp(_, encoding)]) => read_string_prefixed(self.scope, reader, span, encoding, |reader| read_u16be(reader).map(u64::from)),
+            (Prim::FormatStringPrefixed16Le, [FunApp(_, encoding)]) => read_string_prefixed(self.scope, reader, span, encoding, |reader| read_u16le(reader).map(u64::from)),
+            (Prim::FormatStringPrefixed32Be, [FunApp(_, encoding)]) => read_string_prefixed(self.scope, reader, span, encoding, |reader| read_u32be(reader).map(u64::from)),
+            (Prim::FormatStringPrefixed32Le, [FunApp(_, encoding)]) => read_string_prefixed(self.scope, reader, span, encoding, |reader| read_u32le(reader).map(u64::from)),
+            (Prim::FormatStringFixed8, [FunApp(_, len), FunApp(_, encoding)]) => read_string_fixed(self.scope, reader, span, len, encoding),
+            (Prim::FormatStringFixed16, [FunApp(_, len), FunApp(_, encoding)]) => read_string_fixed(self.scope, reader, span, len, encoding),
+            (Prim::FormatStringFixed32, [FunApp(_, len), FunApp(_, encoding)]) => read_string_fixed(self.scope, reader, span, len, encoding),
+            (Prim::FormatStringFixed64, [FunApp(_, len), FunApp(_, encoding)]) => read_string_fixed(self.scope, reader, span, len, encoding),
             (Prim::FormatRepeatLen8, [FunApp(_, len), FunApp(_, format)]) => self.read_repeat_len(reader, span, len, format),
             (Prim::FormatRepeatLen16, [FunApp(_, len), FunApp(_, format)]) => self.read_repeat_len(reader, span, len, format),
             (Prim::FormatRepeatLen32, [FunApp(_, len), FunApp(_, format)]) => self.read_repeat_len(reader, span, len, format),
             (Prim::FormatRepeatLen64, [FunApp(_, len), FunApp(_, format)]) => self.read_repeat_len(reader, span, len, format),
             (Prim::FormatRepeatUntilEnd, [FunApp(_,format)]) => self.read_repeat_until_end(reader, format),
+            (Prim::FormatRepeatUntil, [FunApp(_, format), FunApp(_, pred)]) => self.read_repeat_until(reader, span, format, pred),
+            (Prim::FormatRepeatWhile, [FunApp(_, format), FunApp(_, pred)]) => self.read_repeat_while(reader, span, format, pred),
             (Prim::FormatLimit8, [FunApp(_, limit), FunApp(_, format)]) => self.read_limit(reader, limit, format),
             (Prim::FormatLimit16, [FunApp(_, limit), FunApp(_, format)]) => self.read_limit(reader, limit, format),
             (Prim::FormatLimit32, [FunApp(_, limit), FunApp(_, format)]) => self.read_limit(reader, limit, format),
             (Prim::FormatLimit64, [FunApp(_, limit), FunApp(_, format)]) => self.read_limit(reader, limit, format),
+            (Prim::FormatLimitExact8, [FunApp(_, limit), FunApp(_, format)]) => self.read_limit_exact(reader, span, limit, format),
+            (Prim::FormatLimitExact16, [FunApp(_, limit), FunApp(_, format)]) => self.read_limit_exact(reader, span, limit, format),
+            (Prim::FormatLimitExact32, [FunApp(_, limit), FunApp(_, format)]) => self.read_limit_exact(reader, span, limit, format),
+            (Prim::FormatLimitExact64, [FunApp(_, limit), FunApp(_, format)]) => self.read_limit_exact(reader, span, limit, format),
+            (Prim::FormatDeflate, [FunApp(_, len), FunApp(_, format)]) => self.read_compressed(reader, span, len, format, Compression::Deflate),
+            (Prim::FormatZlib, [FunApp(_, len), FunApp(_, format)]) => self.read_compressed(reader, span, len, format, Compression::Zlib),
+            (Prim::FormatBrotli, [FunApp(_, len), FunApp(_, format)]) => self.read_compressed(reader, span, len, format, Compression::Brotli),
+            (Prim::FormatAlign, [FunApp(_, align)]) => read_align(reader, span, align),
+            (Prim::FormatSkip, [FunApp(_, len)]) => read_skip(reader, span, len),
+            (Prim::FormatReserved, [FunApp(_, format), FunApp(_, expected)]) => self.read_reserved(reader, span, format, expected),
             (Prim::FormatLink, [FunApp(_, pos), FunApp(_, format)]) => self.read_link(span, pos, format),
-            (Prim::FormatDeref, [FunApp(_, format), FunApp(_, r#ref)]) => self.read_deref(format, r#ref),
-            (Prim::FormatStreamPos, []) => read_stream_pos(reader, span),
+            (Prim::FormatDeref, [FunApp(_, format), FunApp(_, r#ref)]) => {
+                self.read_deref(span, format, r#ref)
+            }
+            (Prim::FormatEndOfInput, []) => read_end_of_input(reader, span),
+            (Prim::FormatChoice, [FunApp(_, formats)]) => self.read_choice(reader, span, formats),
+            (Prim::FormatMap, [FunApp(_, format), _, FunApp(_, map_fn)]) => self.read_map(reader, format, map_fn),
+            (Prim::FormatStreamPos, []) => self.read_stream_pos(reader, span),
             (Prim::FormatSucceed, [_, FunApp(_, elem)]) => Ok(elem.clone()),
             (Prim::FormatFail, []) => Err(ReadError::ReadFailFormat(span)),
             (Prim::FormatUnwrap, [_, FunApp(_, option)]) => match option.match_prim_spine() {
@@ -450,7 +1915,7 @@ impl<'arena, 'data> Context<'arena, 'data> {
 
     fn read_repeat_len(
         &mut self,
-        reader: &mut BufferReader<'data>,
+        reader: &mut BufferReader<'_>,
         span: Span,
         len: &ArcValue<'arena>,
         elem_format: &ArcValue<'arena>,
@@ -462,9 +1927,15 @@ impl<'arena, 'data> Context<'arena, 'data> {
             Value::ConstLit(Const::U64(len, _)) => *len,
             _ => return Err(ReadError::InvalidValue(len.span())),
         };
+        // Check the length up front, so that we don't start reading arrays
+        // that are too long
+        self.check_array_len(span, len)?;
 
         let elem_exprs = (0..len)
-            .map(|_| self.read_format(reader, elem_format))
+            .map(|index| {
+                let segment = PathSegment::Index(index as usize);
+                self.read_segment(reader, segment, elem_format)
+            })
             .collect::<Result<_, _>>()?;
 
         Ok(Spanned::new(span, Arc::new(Value::ArrayLit(elem_exprs))))
@@ -472,22 +1943,25 @@ impl<'arena, 'data> Context<'arena, 'data> {
 
     fn read_repeat_until_end(
         &mut self,
-        reader: &mut BufferReader<'data>,
+        reader: &mut BufferReader<'_>,
         elem_format: &ArcValue<'arena>,
     ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
-        let mut current_offset = reader.relative_offset();
+        let mut current_reader = reader.clone();
         let mut elems = Vec::new();
 
         loop {
-            match self.read_format(reader, elem_format) {
+            // The end of the repetition is found using errors
+            let segment = PathSegment::Index(elems.len());
+            match self.read_segment_without_recovery(reader, segment, elem_format) {
                 Ok(elem) => {
+                    self.check_array_len(elem_format.span(), elems.len() as u64 + 1)?;
                     elems.push(elem);
-                    current_offset = reader.relative_offset();
+                    current_reader = reader.clone();
                 }
-                Err(ReadError::BufferError(_, BufferError::UnexpectedEndOfBuffer)) => {
-                    // unwrap shouldn't panic as we're rewinding to a known good offset
+                Err(err) if err.is_unexpected_end_of_buffer() => {
+                    // Rewind to the end of the last element that was read
                     // Should this be set to the end of the current buffer?
-                    reader.set_relative_offset(current_offset).unwrap();
+                    *reader = current_reader;
                     return Ok(Spanned::new(
                         elem_format.span(),
                         Arc::new(Value::ArrayLit(elems)),
@@ -498,123 +1972,619 @@ impl<'arena, 'data> Context<'arena, 'data> {
         }
     }
 
-    fn read_limit(
+    fn read_repeat_until(
         &mut self,
-        reader: &BufferReader<'data>,
-        len: &ArcValue<'arena>,
+        reader: &mut BufferReader<'_>,
+        span: Span,
         elem_format: &ArcValue<'arena>,
+        pred: &ArcValue<'arena>,
     ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
-        let len_span = len.span();
-        let len = match len.as_ref() {
-            Value::ConstLit(Const::U8(len, _)) => Some(usize::from(*len)),
-            Value::ConstLit(Const::U16(len, _)) => Some(usize::from(*len)),
-            Value::ConstLit(Const::U32(len, _)) => usize::try_from(*len).ok(),
-            Value::ConstLit(Const::U64(len, _)) => usize::try_from(*len).ok(),
-            _ => return Err(ReadError::InvalidValue(len_span)),
-        }
-        .ok_or_else(|| BufferError::PositionOverflow.with_span(len_span))?;
-
-        let buffer = reader
-            .remaining_buffer()
-            .and_then(|buf| buf.with_remaining_len(len))
-            .map_err(|err| err.with_span(len_span))?;
+        let mut elems = Vec::new();
 
-        self.read_format(&mut buffer.reader(), elem_format)
+        loop {
+            let start_position = (reader.relative_offset(), reader.bit_offset());
+            let segment = PathSegment::Index(elems.len());
+            let elem = self.read_segment(reader, segment, elem_format)?;
+            self.check_array_len(span, elems.len() as u64 + 1)?;
+            elems.push(elem.clone());
+
+            // The terminating element is included in the array
+            if self.apply_repeat_pred(pred, elem)? {
+                return Ok(Spanned::new(span, Arc::new(Value::ArrayLit(elems))));
+            }
+            // Reading the same element again would produce the same result,
+            // so the repetition would never terminate
+            if (reader.relative_offset(), reader.bit_offset()) == start_position {
+                return Err(ReadError::RepeatWithoutProgress(span));
+            }
+        }
     }
 
-    fn read_link(
+    fn read_repeat_while(
         &mut self,
+        reader: &mut BufferReader<'_>,
         span: Span,
-        pos_value: &ArcValue<'arena>,
         elem_format: &ArcValue<'arena>,
+        pred: &ArcValue<'arena>,
     ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
-        let pos = match pos_value.as_ref() {
-            Value::ConstLit(Const::Pos(pos)) => *pos,
-            _ => return Err(ReadError::InvalidValue(pos_value.span())),
-        };
+        let mut elems = Vec::new();
 
-        self.pending_formats.push((pos, elem_format.clone()));
+        loop {
+            let mut elem_reader = reader.clone();
+            let provenance_len = self.provenance_len();
+            let pending_len = self.pending_refs.len();
+            let allocated_len = self.allocated_refs.len();
+            let segment = PathSegment::Index(elems.len());
+            let elem = self.read_segment(&mut elem_reader, segment, elem_format)?;
+
+            // The terminating element is excluded from the array, and the
+            // reader is left at the start of it, so the links that were found
+            // in it are forgotten
+            if !self.apply_repeat_pred(pred, elem.clone())? {
+                self.truncate_provenance(provenance_len);
+                self.pending_refs.truncate(pending_len);
+                self.forget_refs(allocated_len);
+                return Ok(Spanned::new(span, Arc::new(Value::ArrayLit(elems))));
+            }
+            // Reading the same element again would produce the same result,
+            // so the repetition would never terminate
+            if (elem_reader.relative_offset(), elem_reader.bit_offset())
+                == (reader.relative_offset(), reader.bit_offset())
+            {
+                return Err(ReadError::RepeatWithoutProgress(span));
+            }
 
-        Ok(Spanned::new(
-            span,
-            Arc::new(Value::ConstLit(Const::Ref(pos))),
-        ))
+            self.check_array_len(span, elems.len() as u64 + 1)?;
+            elems.push(elem);
+            *reader = elem_reader;
+        }
     }
 
-    fn read_deref(
-        &mut self,
-        format: &ArcValue<'arena>,
-        r#ref: &ArcValue<'arena>,
-    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
-        let pos = match r#ref.as_ref() {
-            Value::ConstLit(Const::Ref(pos)) => *pos,
-            _ => return Err(ReadError::InvalidValue(r#ref.span())),
-        };
+    /// Check that an array of `len` elements is within the array length
+    /// limit.
+    fn check_array_len(&self, span: Span, len: u64) -> Result<(), ReadError<'arena>> {
+        match self.limits.max_array_len {
+            Some(max_array_len) if len > max_array_len => {
+                Err(ReadError::ArrayLimitExceeded(span, len))
+            }
+            _ => Ok(()),
+        }
+    }
 
-        self.lookup_or_read_ref(pos, format)
+    /// Apply the predicate of a repeat format to an element that was read.
+    fn apply_repeat_pred(
+        &self,
+        pred: &ArcValue<'arena>,
+        elem: ArcValue<'arena>,
+    ) -> Result<bool, ReadError<'arena>> {
+        let result = self
+            .elim_env()
+            .fun_app(Plicity::Explicit, pred.clone(), elem);
+        match result.as_ref() {
+            Value::ConstLit(Const::Bool(result)) => Ok(*result),
+            _ => Err(ReadError::InvalidValue(pred.span())),
+        }
     }
 
-    fn lookup_ref<'context>(
-        &'context self,
-        pos: usize,
-        format: &ArcValue<'_>,
-    ) -> Option<&'context ParsedRef<'arena>> {
-        // NOTE: The number of calls to `semantics::ConversionEnv::is_equal`
-        // when looking up cached references is a bit of a pain. If this ever
-        // becomes a problem we could improve performance by pre-allocating a
-        // `ParsedRef` in the cache during `read_link`, and storing the index of
-        // that parsed reference alongside the position in `Const::Ref`.
-
-        (self.cached_refs.get(&pos)?.iter()).find(|r| {
-            self.elim_env()
-                .conversion_env(EnvLen::new())
-                .is_equal(&r.format, format)
-        })
+    fn read_limit(
+        &mut self,
+        reader: &BufferReader<'_>,
+        len: &ArcValue<'arena>,
+        elem_format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let buffer = limit_buffer(reader, len)?;
+        let expr = self.read_format(&mut buffer.reader(), elem_format)?;
+        self.extend_limit_provenance(&buffer, len.span())?;
+        Ok(expr)
     }
 
-    fn lookup_or_read_ref(
+    fn read_limit_exact(
         &mut self,
-        pos: usize,
-        format: &ArcValue<'arena>,
+        reader: &BufferReader<'_>,
+        span: Span,
+        len: &ArcValue<'arena>,
+        elem_format: &ArcValue<'arena>,
     ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
-        if let Some(parsed_ref) = self.lookup_ref(pos, format) {
-            return Ok(parsed_ref.expr.clone());
+        let buffer = limit_buffer(reader, len)?;
+        let mut reader = buffer.reader();
+        let expr = self.read_format(&mut reader, elem_format)?;
+
+        match unconsumed_len(&reader) {
+            0 => {
+                self.extend_limit_provenance(&buffer, span)?;
+                Ok(expr)
+            }
+            len => Err(ReadError::UnconsumedLimit(span, len)),
         }
+    }
 
-        // Read the data at the ref location
-        let mut reader = self.initial_buffer.reader_with_offset(pos)?;
-        let expr = self.read_format(&mut reader, format)?;
+    /// Limits do not advance the reader, so the range of a limited value is
+    /// extended to cover the whole limit.
+    fn extend_limit_provenance(
+        &mut self,
+        buffer: &Buffer<'_>,
+        span: Span,
+    ) -> Result<(), ReadError<'arena>> {
+        let end = buffer.len().map_err(|err| err.with_span(span))?;
+        self.extend_current_provenance(end);
+        Ok(())
+    }
 
-        // We might have parsed the current reference during the above call to
-        // `read_format`. It's unclear if this could ever happen in practice,
-        // especially without succumbing to non-termination, but we'll panic
-        // here just in case.
-        if self.lookup_ref(pos, format).is_some() {
-            panic!("recursion found when storing cached reference {pos}");
-        }
+    fn read_choice(
+        &mut self,
+        reader: &mut BufferReader<'_>,
+        span: Span,
+        formats: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let formats = match formats.as_ref() {
+            Value::ArrayLit(formats) => formats,
+            _ => return Err(ReadError::InvalidValue(formats.span())),
+        };
 
-        // Store the parsed reference in the reference cache
-        self.cached_refs
-            .entry(pos)
-            .or_insert_with(|| Vec::with_capacity(1))
-            .push(ParsedRef {
-                format: format.clone(),
-                expr: expr.clone(),
-            });
+        let mut errors = Vec::with_capacity(formats.len());
+        for (index, format) in formats.iter().enumerate() {
+            let mut choice_reader = reader.clone();
+            let pending_len = self.pending_refs.len();
+            let allocated_len = self.allocated_refs.len();
+
+            let segment = PathSegment::Field(prim::choice_labels()[1]);
+            match self.read_segment_without_recovery(&mut choice_reader, segment, format) {
+                Ok(expr) => {
+                    *reader = choice_reader;
+                    let index = Const::U64(index as u64, UIntStyle::Decimal);
+                    let exprs = vec![Spanned::empty(Arc::new(Value::ConstLit(index))), expr];
+                    let labels = prim::choice_labels();
+                    return Ok(Spanned::new(
+                        span,
+                        Arc::new(Value::RecordLit(labels, exprs)),
+                    ));
+                }
+                // Limits apply regardless of the alternative being read
+                Err(err) if err.is_limit_exceeded() => return Err(err),
+                Err(err) => {
+                    // Forget any links that were found by the failed alternative
+                    self.pending_refs.truncate(pending_len);
+                    self.forget_refs(allocated_len);
+                    errors.push(err);
+                }
+            }
+        }
 
-        Ok(expr)
+        Err(ReadError::ChoiceFailure(span, errors))
+    }
+
+    fn read_map(
+        &mut self,
+        reader: &mut BufferReader<'_>,
+        format: &ArcValue<'arena>,
+        map_fn: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let expr = self.read_format(reader, format)?;
+        Ok(self
+            .elim_env()
+            .fun_app(Plicity::Explicit, map_fn.clone(), expr))
+    }
+
+    fn read_compressed(
+        &mut self,
+        reader: &mut BufferReader<'_>,
+        span: Span,
+        len: &ArcValue<'arena>,
+        elem_format: &ArcValue<'arena>,
+        compression: Compression,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let len_span = len.span();
+        let len = match len.as_ref() {
+            Value::ConstLit(Const::U32(len, _)) => usize::try_from(*len).ok(),
+            _ => return Err(ReadError::InvalidValue(len_span)),
+        }
+        .ok_or_else(|| BufferError::PositionOverflow.with_span(len_span))?;
+
+        let source_pos = reader.offset().map_err(|err| err.with_span(span))?;
+        let compressed = reader
+            .read_byte_vec(len)
+            .map_err(|err| err.with_span(span))?;
+
+        // Reuse the decompressed data if this stream has already been read,
+        // so that positions in the derived buffer remain stable
+        let derived = self.buffers.derived.iter().find(|derived| {
+            derived.compression == compression
+                && derived.source_pos == source_pos
+                && derived.source_len == len
+        });
+        let (start_pos, data) = match derived {
+            Some(derived) => (derived.start_pos, derived.data.clone()),
+            None => {
+                let max_len = match self.limits.max_decompressed_len {
+                    Some(max_len) => {
+                        let derived = self.buffers.derived.iter();
+                        max_len.saturating_sub(derived.map(|derived| derived.data.len()).sum())
+                    }
+                    None => usize::MAX,
+                };
+                let data = match compression.decompress(&compressed, max_len) {
+                    Ok(data) => Arc::<[u8]>::from(data),
+                    Err(DecompressError::InvalidData) => {
+                        return Err(ReadError::DecompressionFailed(
+                            span,
+                            source_pos,
+                            compression,
+                        ))
+                    }
+                    Err(DecompressError::LimitExceeded) => {
+                        return Err(ReadError::DecompressedLimitExceeded(span))
+                    }
+                };
+                let start_pos =
+                    (self.buffers.next_derived_pos()).map_err(|err| err.with_span(span))?;
+
+                self.buffers.derived.push(DerivedBuffer {
+                    compression,
+                    source_pos,
+                    source_len: len,
+                    start_pos,
+                    data: data.clone(),
+                });
+                (start_pos, data)
+            }
+        };
+
+        let buffer = Buffer::new(start_pos, &data);
+        self.read_format(&mut buffer.reader(), elem_format)
+    }
+
+    fn read_reserved(
+        &mut self,
+        reader: &mut BufferReader<'_>,
+        span: Span,
+        format: &ArcValue<'arena>,
+        expected: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let found = self.read_format(reader, format)?;
+
+        if !(self.elim_env().conversion_env(EnvLen::new())).is_equal(expected, &found) {
+            return Err(ReadError::ReservedMismatch(span, expected.clone(), found));
+        }
+
+        Ok(Spanned::new(
+            span,
+            Arc::new(Value::RecordLit(&[], Vec::new())),
+        ))
+    }
+
+    fn read_stream_pos(
+        &mut self,
+        reader: &mut BufferReader<'_>,
+        span: Span,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let pos = (reader.offset())
+            .and_then(|offset| self.buffers.offset_to_pos(offset))
+            .map_err(|err| err.with_span(span))?;
+
+        Ok(Spanned::new(
+            span,
+            Arc::new(Value::ConstLit(Const::Pos(pos))),
+        ))
+    }
+
+    fn read_link(
+        &mut self,
+        span: Span,
+        pos_value: &ArcValue<'arena>,
+        elem_format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let pos_value = match pos_value.as_ref() {
+            Value::ConstLit(Const::Pos(pos)) => *pos,
+            _ => return Err(ReadError::InvalidValue(pos_value.span())),
+        };
+        // Positions past the end of their buffer can't be read from
+        let pos = (self.buffers.pos_to_offset(pos_value)).map_err(|err| err.with_span(span))?;
+
+        let index = match self.find_ref(pos, elem_format) {
+            // References are only found if their index was checked when they
+            // were allocated
+            Some(index) => index as u32,
+            None => {
+                let index = self.cached_refs.get(&pos).map_or(0, Vec::len);
+                let index = u32::try_from(index).map_err(|_| ReadError::InvalidValue(span))?;
+                if let Some(max_pending_links) = self.limits.max_pending_links {
+                    if self.pending_refs.len() >= max_pending_links {
+                        return Err(ReadError::PendingLinkLimitExceeded(span));
+                    }
+                }
+                let allocated_index = self.allocate_ref(pos, elem_format);
+                self.pending_refs.push((pos, allocated_index));
+                index
+            }
+        };
+
+        Ok(Spanned::new(
+            span,
+            Arc::new(Value::ConstLit(Const::Ref(pos_value, index))),
+        ))
+    }
+
+    fn read_deref(
+        &mut self,
+        span: Span,
+        format: &ArcValue<'arena>,
+        r#ref: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let (pos, index) = match r#ref.as_ref() {
+            Value::ConstLit(Const::Ref(pos, index)) => match self.buffers.pos_to_offset(*pos) {
+                Ok(pos) => (pos, *index as usize),
+                Err(_) => return Err(ReadError::InvalidValue(r#ref.span())),
+            },
+            _ => return Err(ReadError::InvalidValue(r#ref.span())),
+        };
+        let cached_ref = match self.lookup_ref(pos, index) {
+            Some(cached_ref) => cached_ref,
+            None => return Err(ReadError::InvalidValue(r#ref.span())),
+        };
+        // The format only needs to be compared against the single reference
+        // that the link points to, and the comparison is usually skipped
+        // because the formats share the same value.
+        if !(std::ptr::eq(cached_ref.format.as_ref(), format.as_ref())
+            || (self.elim_env().conversion_env(EnvLen::new())).is_equal(&cached_ref.format, format))
+        {
+            return Err(ReadError::InvalidValue(r#ref.span()));
+        }
+
+        // Reading a reference that is already being read would never finish
+        if let Some(start) = self.reading_refs.iter().position(|r| *r == (pos, index)) {
+            let chain = self.reading_refs[start..].iter().map(|(pos, _)| *pos);
+            return Err(ReadError::LinkCycle(span, chain.chain([pos]).collect()));
+        }
+
+        self.lookup_or_read_ref(pos, index)
+    }
+
+    /// Find the index of a reference to `format` at `pos` in the reference
+    /// cache. Formats are only compared when links are read, so that
+    /// references can be looked up by index once they have been allocated.
+    fn find_ref(&self, pos: usize, format: &ArcValue<'arena>) -> Option<usize> {
+        let cached_refs = self.cached_refs.get(&pos)?;
+        (cached_refs.iter()).position(|cached_ref| {
+            // Links with the same format often share the same value
+            std::ptr::eq(cached_ref.format.as_ref(), format.as_ref())
+                || (self.elim_env().conversion_env(EnvLen::new()))
+                    .is_equal(&cached_ref.format, format)
+        })
+    }
+
+    /// Allocate a new reference to `format` at `pos` in the reference cache,
+    /// returning its index.
+    fn allocate_ref(&mut self, pos: usize, format: &ArcValue<'arena>) -> usize {
+        let cached_refs = self.cached_refs.entry(pos).or_default();
+        cached_refs.push(CachedRef {
+            format: format.clone(),
+            expr: None,
+        });
+        self.allocated_refs.push(pos);
+
+        cached_refs.len() - 1
+    }
+
+    /// Forget the references allocated after the first `len` references, for
+    /// example when the links to them were part of a format that failed to be
+    /// read.
+    fn forget_refs(&mut self, len: usize) {
+        for pos in self.allocated_refs.drain(len..).rev() {
+            if let Some(cached_refs) = self.cached_refs.get_mut(&pos) {
+                cached_refs.pop();
+                if cached_refs.is_empty() {
+                    self.cached_refs.remove(&pos);
+                }
+            }
+        }
+    }
+
+    fn lookup_ref(&self, pos: usize, index: usize) -> Option<&CachedRef<'arena>> {
+        self.cached_refs.get(&pos)?.get(index)
+    }
+
+    fn lookup_or_read_ref(
+        &mut self,
+        pos: usize,
+        index: usize,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        if let Some(expr) = self.lookup_ref(pos, index).and_then(|r| r.expr.as_ref()) {
+            return Ok(expr.clone());
+        }
+
+        // Read the data at the ref location, which might be in a derived buffer
+        match self.buffers.find_derived(pos) {
+            Some(derived) => {
+                let data = derived.data.clone();
+                let reader = Buffer::new(derived.start_pos, &data).reader_with_offset(pos)?;
+                self.read_ref(pos, index, reader)
+            }
+            None => {
+                let reader = self.buffers.initial.reader_with_offset(pos)?;
+                self.read_ref(pos, index, reader)
+            }
+        }
+    }
+
+    /// Read a reference that was allocated in the reference cache, using a
+    /// reader positioned at the reference.
+    fn read_ref(
+        &mut self,
+        pos: usize,
+        index: usize,
+        mut reader: BufferReader<'_>,
+    ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+        let format = self.cached_refs[&pos][index].format.clone();
+
+        // Values within the reference are read relative to it
+        let parent_path = std::mem::replace(&mut self.current_path, ValuePath::new(pos, index));
+        let parent_entries = std::mem::take(&mut self.current_entries);
+        self.reading_refs.push((pos, index));
+
+        let result = self.read_value(&mut reader, &format);
+
+        self.reading_refs.pop();
+        self.current_path = parent_path;
+        self.current_entries = parent_entries;
+        let expr = result?;
+
+        // Store the parsed reference in the reference cache. Dereferencing the
+        // current reference while reading it results in a `LinkCycle` error,
+        // so it can't have been stored already.
+        let cached_ref = &mut self.cached_refs.get_mut(&pos).unwrap()[index];
+        debug_assert!(cached_ref.expr.is_none());
+        cached_ref.expr = Some(expr.clone());
+
+        Ok(expr)
     }
 }
 
-fn read_stream_pos<'arena>(
+/// The buffer of data that a limit format reads from.
+fn limit_buffer<'arena, 'data>(
+    reader: &BufferReader<'data>,
+    len: &ArcValue<'arena>,
+) -> Result<Buffer<'data>, ReadError<'arena>> {
+    let len_span = len.span();
+    let len = match len.as_ref() {
+        Value::ConstLit(Const::U8(len, _)) => Some(usize::from(*len)),
+        Value::ConstLit(Const::U16(len, _)) => Some(usize::from(*len)),
+        Value::ConstLit(Const::U32(len, _)) => usize::try_from(*len).ok(),
+        Value::ConstLit(Const::U64(len, _)) => usize::try_from(*len).ok(),
+        _ => return Err(ReadError::InvalidValue(len_span)),
+    }
+    .ok_or_else(|| BufferError::PositionOverflow.with_span(len_span))?;
+
+    reader
+        .remaining_buffer()
+        .and_then(|buf| buf.with_remaining_len(len))
+        .map_err(|err| err.with_span(len_span))
+}
+
+/// The number of bytes left unread in the buffer of a reader. The remaining
+/// bits of a partially read byte are considered to be consumed.
+fn unconsumed_len(reader: &BufferReader<'_>) -> usize {
+    match reader.is_byte_aligned() {
+        true => reader.remaining_len(),
+        false => reader.remaining_len() - 1,
+    }
+}
+
+fn read_end_of_input<'arena>(
     reader: &mut BufferReader<'_>,
     span: Span,
 ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+    match unconsumed_len(reader) {
+        0 => Ok(Spanned::new(
+            span,
+            Arc::new(Value::RecordLit(&[], Vec::new())),
+        )),
+        len => Err(ReadError::ExpectedEndOfInput(span, len)),
+    }
+}
+
+/// Returns `true` if the format only advances past some data, without
+/// producing a meaningful value. Conditional and limited formats are looked
+/// through, as they produce the value of the format that they wrap.
+fn is_skipped_format(format: &ArcValue<'_>) -> bool {
+    use crate::core::semantics::Elim::FunApp;
+
+    match format.as_ref() {
+        Value::FormatCond(_, format, _) => is_skipped_format(format),
+        Value::Stuck(Head::Prim(prim), slice) => match (prim, &slice[..]) {
+            (Prim::FormatAlign | Prim::FormatSkip | Prim::FormatReserved, _) => true,
+            (
+                Prim::FormatLimit8
+                | Prim::FormatLimit16
+                | Prim::FormatLimit32
+                | Prim::FormatLimit64
+                | Prim::FormatLimitExact8
+                | Prim::FormatLimitExact16
+                | Prim::FormatLimitExact32
+                | Prim::FormatLimitExact64,
+                [_, FunApp(_, elem_format)],
+            ) => is_skipped_format(elem_format),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn read_byte_count<'arena>(value: &ArcValue<'arena>) -> Result<usize, ReadError<'arena>> {
+    match value.as_ref() {
+        Value::ConstLit(Const::U64(count, _)) => usize::try_from(*count)
+            .map_err(|_| BufferError::PositionOverflow.with_span(value.span())),
+        _ => Err(ReadError::InvalidValue(value.span())),
+    }
+}
+
+fn read_align<'arena>(
+    reader: &mut BufferReader<'_>,
+    span: Span,
+    align: &ArcValue<'arena>,
+) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+    let align = read_byte_count(align)?;
+
+    // Padding is relative to the start of the current buffer, so that
+    // alignment is preserved within limited and decompressed data
+    if align > 0 {
+        let padding = (align - reader.relative_offset() % align) % align;
+        (reader.skip_bytes(padding)).map_err(|err| err.with_span(span))?;
+    } else if !reader.is_byte_aligned() {
+        return Err(BufferError::UnalignedRead.with_span(span));
+    }
+
+    Ok(Spanned::new(
+        span,
+        Arc::new(Value::RecordLit(&[], Vec::new())),
+    ))
+}
+
+fn read_skip<'arena>(
+    reader: &mut BufferReader<'_>,
+    span: Span,
+    len: &ArcValue<'arena>,
+) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+    let len = read_byte_count(len)?;
+    (reader.skip_bytes(len)).map_err(|err| err.with_span(span))?;
+
     Ok(Spanned::new(
         span,
-        Arc::new(Value::ConstLit(Const::Pos(
-            reader.offset().map_err(|err| err.with_span(span))?,
-        ))),
+        Arc::new(Value::RecordLit(&[], Vec::new())),
+    ))
+}
+
+fn read_bit<'arena>(
+    reader: &mut BufferReader<'_>,
+    span: Span,
+    order: BitOrder,
+) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+    let bit = reader.read_bit(order).map_err(|err| err.with_span(span))?;
+    Ok(Spanned::new(
+        span,
+        Arc::new(Value::ConstLit(Const::Bool(bit))),
+    ))
+}
+
+fn read_bits<'arena>(
+    reader: &mut BufferReader<'_>,
+    span: Span,
+    count: &ArcValue<'arena>,
+    order: BitOrder,
+    max_count: u8,
+    wrap_const: fn(u64) -> Const<'arena>,
+) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+    let count = match count.as_ref() {
+        Value::ConstLit(Const::U8(count, _)) if *count <= max_count => *count,
+        Value::ConstLit(Const::U8(count, _)) => {
+            return Err(ReadError::InvalidBitCount(span, *count));
+        }
+        _ => return Err(ReadError::InvalidValue(count.span())),
+    };
+
+    let data = (reader.read_bits(order, count)).map_err(|err| err.with_span(span))?;
+    Ok(Spanned::new(
+        span,
+        Arc::new(Value::ConstLit(wrap_const(data))),
     ))
 }
 
@@ -622,7 +2592,7 @@ fn read_const<'arena, 'data, T>(
     reader: &mut BufferReader<'data>,
     span: Span,
     read: fn(&mut BufferReader<'data>) -> Result<T, BufferError>,
-    wrap_const: fn(T) -> Const,
+    wrap_const: fn(T) -> Const<'arena>,
 ) -> Result<ArcValue<'arena>, ReadError<'arena>> {
     let data = read(reader).map_err(|err| err.with_span(span))?;
     Ok(Spanned::new(
@@ -631,6 +2601,195 @@ fn read_const<'arena, 'data, T>(
     ))
 }
 
+fn text_encoding<'arena>(encoding: &ArcValue<'arena>) -> Result<TextEncoding, ReadError<'arena>> {
+    TextEncoding::from_value(encoding).ok_or_else(|| ReadError::InvalidValue(encoding.span()))
+}
+
+fn decode_string<'arena>(
+    scope: &'arena Scope<'arena>,
+    span: Span,
+    offset: usize,
+    encoding: TextEncoding,
+    bytes: &[u8],
+) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+    match encoding.decode(bytes) {
+        Some(string) => Ok(Spanned::new(
+            span,
+            Arc::new(Value::ConstLit(Const::string(scope, &string))),
+        )),
+        None => Err(ReadError::InvalidString(span, offset, encoding)),
+    }
+}
+
+/// Read a string up to the next null code unit, consuming the null.
+fn read_string_nul<'arena>(
+    scope: &'arena Scope<'arena>,
+    reader: &mut BufferReader<'_>,
+    span: Span,
+    encoding: &ArcValue<'arena>,
+) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+    let encoding = text_encoding(encoding)?;
+    let offset = reader.offset().map_err(|err| err.with_span(span))?;
+
+    let mut bytes = Vec::new();
+    loop {
+        let unit =
+            (reader.read_byte_vec(encoding.code_unit_len())).map_err(|err| err.with_span(span))?;
+        if unit.iter().all(|byte| *byte == 0) {
+            break;
+        }
+        bytes.extend(unit);
+    }
+
+    decode_string(scope, span, offset, encoding, &bytes)
+}
+
+/// Read a string, preceded by its length in bytes.
+fn read_string_prefixed<'arena, 'data>(
+    scope: &'arena Scope<'arena>,
+    reader: &mut BufferReader<'data>,
+    span: Span,
+    encoding: &ArcValue<'arena>,
+    read_len: fn(&mut BufferReader<'data>) -> Result<u64, BufferError>,
+) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+    let encoding = text_encoding(encoding)?;
+    let offset = reader.offset().map_err(|err| err.with_span(span))?;
+
+    let bytes = read_len(reader)
+        .and_then(|len| usize::try_from(len).map_err(|_| BufferError::UnexpectedEndOfBuffer))
+        .and_then(|len| reader.read_byte_vec(len))
+        .map_err(|err| err.with_span(span))?;
+
+    decode_string(scope, span, offset, encoding, &bytes)
+}
+
+/// Read a string that occupies a fixed number of bytes. The string ends early
+/// at the first null code unit, if any.
+fn read_string_fixed<'arena>(
+    scope: &'arena Scope<'arena>,
+    reader: &mut BufferReader<'_>,
+    span: Span,
+    len: &ArcValue<'arena>,
+    encoding: &ArcValue<'arena>,
+) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+    let len = match len.as_ref() {
+        Value::ConstLit(Const::U8(len, _)) => u64::from(*len),
+        Value::ConstLit(Const::U16(len, _)) => u64::from(*len),
+        Value::ConstLit(Const::U32(len, _)) => u64::from(*len),
+        Value::ConstLit(Const::U64(len, _)) => *len,
+        _ => return Err(ReadError::InvalidValue(len.span())),
+    };
+    let encoding = text_encoding(encoding)?;
+    let offset = reader.offset().map_err(|err| err.with_span(span))?;
+
+    let mut bytes = usize::try_from(len)
+        .map_err(|_| BufferError::UnexpectedEndOfBuffer)
+        .and_then(|len| reader.read_byte_vec(len))
+        .map_err(|err| err.with_span(span))?;
+
+    let unit_len = encoding.code_unit_len();
+    if let Some(index) = (bytes.chunks(unit_len)).position(|unit| unit.iter().all(|b| *b == 0)) {
+        bytes.truncate(index * unit_len);
+    }
+
+    decode_string(scope, span, offset, encoding, &bytes)
+}
+
+fn read_varint<'arena, 'data, T>(
+    reader: &mut BufferReader<'data>,
+    span: Span,
+    read: fn(&mut BufferReader<'data>) -> Result<Option<T>, BufferError>,
+    wrap_const: fn(T) -> Const<'arena>,
+) -> Result<ArcValue<'arena>, ReadError<'arena>> {
+    let offset = reader.offset().map_err(|err| err.with_span(span))?;
+    match read(reader).map_err(|err| err.with_span(span))? {
+        Some(data) => Ok(Spanned::new(
+            span,
+            Arc::new(Value::ConstLit(wrap_const(data))),
+        )),
+        None => Err(ReadError::VarintOverflow(span, offset)),
+    }
+}
+
+/// The maximum number of bytes needed to encode a 64-bit integer using seven
+/// bits per byte.
+const MAX_VARINT_LEN: u32 = 10;
+
+/// Read an unsigned LEB128 integer, returning `None` if it does not fit in
+/// 64 bits.
+fn read_uleb128(reader: &mut BufferReader<'_>) -> Result<Option<u64>, BufferError> {
+    let mut value = 0;
+    for index in 0..MAX_VARINT_LEN {
+        let byte = reader.read_byte()?;
+        let group = u64::from(byte & 0x7f);
+        let shift = index * 7;
+
+        // Bits shifted past the end of the value would be lost
+        if (group << shift) >> shift != group {
+            return Ok(None);
+        }
+        value |= group << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// Read a signed LEB128 integer, returning `None` if it does not fit in
+/// 64 bits.
+fn read_sleb128(reader: &mut BufferReader<'_>) -> Result<Option<i64>, BufferError> {
+    let mut value = 0;
+    for index in 0..MAX_VARINT_LEN {
+        let byte = reader.read_byte()?;
+        let group = i64::from(byte & 0x7f);
+        let shift = index * 7;
+
+        // The final byte only has room for the sign bit, so the rest of the
+        // group must be a sign extension of it
+        if shift == 63 && group != 0 && group != 0x7f {
+            return Ok(None);
+        }
+        value |= group << shift;
+
+        if byte & 0x80 == 0 {
+            if shift + 7 < 64 && byte & 0x40 != 0 {
+                value |= -1 << (shift + 7);
+            }
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// Read a zigzag encoded integer, returning `None` if it does not fit in
+/// 64 bits.
+fn read_varint_zigzag(reader: &mut BufferReader<'_>) -> Result<Option<i64>, BufferError> {
+    let value = read_uleb128(reader)?;
+    Ok(value.map(|value| (value >> 1) as i64 ^ -((value & 1) as i64)))
+}
+
+/// Read a big endian variable-length quantity, returning `None` if it does not
+/// fit in 64 bits.
+fn read_vlq_be(reader: &mut BufferReader<'_>) -> Result<Option<u64>, BufferError> {
+    let mut value: u64 = 0;
+    for _ in 0..MAX_VARINT_LEN {
+        let byte = reader.read_byte()?;
+
+        // Shifting the next group in would lose the most significant bits
+        if value >> (64 - 7) != 0 {
+            return Ok(None);
+        }
+        value = (value << 7) | u64::from(byte & 0x7f);
+
+        if byte & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
 fn read_u8(reader: &mut BufferReader<'_>) -> Result<u8, BufferError> {
     reader.read_byte()
 }
@@ -645,7 +2804,7 @@ macro_rules! read_multibyte_prim {
         fn $read_multibyte_prim<'data>(
             reader: &mut BufferReader<'data>,
         ) -> Result<$T, BufferError> {
-            Ok($T::$from_bytes(*reader.read_byte_array()?))
+            Ok($T::$from_bytes(reader.read_byte_array()?))
         }
     };
 }
@@ -666,3 +2825,908 @@ read_multibyte_prim!(read_f32le, from_le_bytes, f32);
 read_multibyte_prim!(read_f32be, from_be_bytes, f32);
 read_multibyte_prim!(read_f64le, from_le_bytes, f64);
 read_multibyte_prim!(read_f64be, from_be_bytes, f64);
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::files::FileId;
+    use crate::source::ProgramSource;
+    use crate::surface::elaboration::{self, ItemEnv};
+    use crate::surface::{Module as SurfaceModule, Term as SurfaceTerm};
+
+    /// Elaborate a format from its source, panicking if there are any errors.
+    pub(super) fn elab_format<'arena>(scope: &'arena Scope<'arena>, source: &str) -> Term<'arena> {
+        let file_id = FileId::try_from(1).unwrap();
+        let source = ProgramSource::try_from(source.to_owned()).unwrap();
+        let (surface_term, messages) = SurfaceTerm::parse(scope, &source);
+        assert!(messages.is_empty());
+
+        let mut context = elaboration::Context::new(file_id, scope, ItemEnv::new());
+        context.elab_format(scope, &surface_term, &mut |_| panic!("unexpected message"))
+    }
+
+    /// The fields of the record read by the entrypoint.
+    fn record_fields<'arena>(
+        refs: &HashMap<usize, Vec<ParsedRef<'arena>>>,
+    ) -> Vec<ArcValue<'arena>> {
+        match refs[&0][0].expr.as_ref() {
+            Value::RecordLit(_, exprs) => exprs.clone(),
+            _ => panic!("expected record"),
+        }
+    }
+
+    #[test]
+    fn stream_data_across_pages() {
+        const PAGE_LEN: usize = StreamData::PAGE_LEN;
+
+        let data = (0..PAGE_LEN * 3).map(|i| i as u8).collect::<Vec<_>>();
+        let stream = StreamData::new(Cursor::new(data.clone())).unwrap();
+        let buffer = Buffer::from(&stream);
+
+        let mut reader = buffer.reader_with_offset(PAGE_LEN - 2).unwrap();
+        let expected = u32::from_be_bytes(data[PAGE_LEN - 2..][..4].try_into().unwrap());
+        assert_eq!(read_u32be(&mut reader).unwrap(), expected);
+
+        let mut reader = buffer.reader_with_offset(data.len() - 1).unwrap();
+        assert!(matches!(
+            read_u16be(&mut reader),
+            Err(BufferError::UnexpectedEndOfBuffer),
+        ));
+    }
+
+    #[test]
+    fn varint_bounds() {
+        fn read<T>(read: fn(&mut BufferReader<'_>) -> Result<T, BufferError>, data: &[u8]) -> T {
+            read(&mut BufferReader::from(Buffer::from(data))).unwrap()
+        }
+
+        let max = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(read(read_uleb128, &max), Some(u64::MAX));
+        assert_eq!(
+            read(
+                read_uleb128,
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]
+            ),
+            None
+        );
+        assert_eq!(read(read_uleb128, &[0x80; 11]), None);
+
+        assert_eq!(
+            read(
+                read_sleb128,
+                &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7f]
+            ),
+            Some(i64::MIN)
+        );
+        assert_eq!(
+            read(
+                read_sleb128,
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00]
+            ),
+            Some(i64::MAX)
+        );
+        assert_eq!(
+            read(
+                read_sleb128,
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+            ),
+            None
+        );
+
+        assert_eq!(read(read_varint_zigzag, &max), Some(i64::MIN));
+
+        assert_eq!(
+            read(
+                read_vlq_be,
+                &[0x81, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]
+            ),
+            Some(u64::MAX)
+        );
+        assert_eq!(
+            read(
+                read_vlq_be,
+                &[0x82, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn read_compressed() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{
+                a <- deflate 7 (string_fixed8 5 ascii),
+                b <- zlib 13 (string_fixed8 5 ascii),
+                c <- brotli 9 { start <- stream_pos, r <- link (pos_add_u8 start 1) u8 },
+            }",
+        );
+        // "hello", compressed with each of the supported compression schemes
+        let data = [
+            0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, // deflate
+            0x78, 0xda, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0x06, 0x2c, 0x02,
+            0x15, // zlib
+            0x0b, 0x02, 0x80, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x03, // brotli
+        ];
+
+        let mut context = Context::new(&scope, Buffer::from(&data[..]));
+        let refs = context.read_entrypoint(&format).unwrap();
+
+        let derived = context.derived_buffers();
+        let sources = derived
+            .iter()
+            .map(|derived| (derived.compression, derived.source_pos, derived.data()));
+        assert!(sources.eq([
+            (Compression::Deflate, 0, &b"hello"[..]),
+            (Compression::Zlib, 7, &b"hello"[..]),
+            (Compression::Brotli, 20, &b"hello"[..]),
+        ]));
+
+        // Derived buffers are positioned after the initial buffer, and after
+        // each other, without overlapping
+        assert_eq!(derived[0].start_pos, data.len() + 1);
+        assert_eq!(derived[1].start_pos, derived[0].end_pos() + 1);
+        assert_eq!(derived[2].start_pos, derived[1].end_pos() + 1);
+
+        // Links are followed into the derived buffer
+        let link_pos = derived[2].start_pos + 1;
+        let link_expr = &refs[&link_pos][0].expr;
+        assert!(matches!(
+            link_expr.as_ref(),
+            Value::ConstLit(Const::U8(b'e', _))
+        ));
+        assert_eq!(context.pos_to_string(link_pos), "brotli[20]+1");
+        assert_eq!(context.pos_to_string(20), "20");
+
+        // Position values are relative to the derived buffer that they are in
+        let exprs = record_fields(&refs);
+        let start = match exprs[2].as_ref() {
+            Value::RecordLit(_, exprs) => match exprs[0].as_ref() {
+                Value::ConstLit(Const::Pos(pos)) => *pos,
+                _ => panic!("expected position"),
+            },
+            _ => panic!("expected record"),
+        };
+        assert_eq!((start.buffer(), start.offset()), (3, 0));
+        assert_eq!(context.pos_value_to_string(start), "brotli[20]+0");
+    }
+
+    #[test]
+    fn read_compressed_past_end() {
+        let scope = Scope::new();
+        // Positions past the end of the initial buffer do not refer to the
+        // data that follows it in derived buffers
+        let format = elab_format(
+            &scope,
+            "{
+                start <- stream_pos,
+                r <- link (pos_add_u8 start 8) u8,
+                a <- deflate 7 u8,
+            }",
+        );
+        let data = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+
+        let mut context = Context::new(&scope, Buffer::from(&data[..]));
+        let result = context.read_entrypoint(&format);
+        assert!(matches!(
+            result,
+            Err(ReadError::BufferError(
+                _,
+                BufferError::UnexpectedEndOfBuffer
+            )),
+        ));
+    }
+
+    #[test]
+    fn read_compressed_invalid() {
+        let scope = Scope::new();
+        let format = elab_format(&scope, "{ a <- u8, b <- zlib 2 u8 }");
+
+        let result = Context::new(&scope, Buffer::from(&[0, 1, 2][..])).read_entrypoint(&format);
+        assert!(matches!(
+            result,
+            Err(ReadError::DecompressionFailed(_, 1, Compression::Zlib)),
+        ));
+    }
+
+    #[test]
+    fn read_compressed_limit() {
+        let scope = Scope::new();
+        // "hello", compressed with DEFLATE and Brotli
+        let data = [
+            0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, // deflate
+            0x0b, 0x02, 0x80, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x03, // brotli
+        ];
+        let read = |source, max_decompressed_len| {
+            let format = elab_format(&scope, source);
+            let mut context = Context::new(&scope, Buffer::from(&data[..]));
+            context.set_limits(ReadLimits {
+                max_decompressed_len: Some(max_decompressed_len),
+                ..ReadLimits::default()
+            });
+            context.read_entrypoint(&format).map(|_| ())
+        };
+
+        let source = "{ a <- deflate 7 (repeat_until_end u8) }";
+        assert!(read(source, 5).is_ok());
+        assert!(matches!(
+            read(source, 4),
+            Err(ReadError::DecompressedLimitExceeded(_)),
+        ));
+
+        let source = "{ a <- skip 7, b <- brotli 9 (repeat_until_end u8) }";
+        assert!(read(source, 5).is_ok());
+        assert!(matches!(
+            read(source, 4),
+            Err(ReadError::DecompressedLimitExceeded(_)),
+        ));
+
+        // The limit applies to all of the data that is decompressed
+        let source =
+            "{ a <- deflate 7 (repeat_until_end u8), b <- brotli 9 (repeat_until_end u8) }";
+        assert!(read(source, 10).is_ok());
+        assert!(matches!(
+            read(source, 9),
+            Err(ReadError::DecompressedLimitExceeded(_)),
+        ));
+    }
+
+    #[test]
+    fn read_repeat_pred() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{ a <- repeat_until u8 (fun x => u8_eq x 0), b <- repeat_while u8 (fun x => u8_lt x 128), c <- u8 }",
+        );
+        let data = [1, 2, 0, 3, 4, 200];
+
+        let refs = Context::new(&scope, Buffer::from(&data[..]))
+            .read_entrypoint(&format)
+            .unwrap();
+        let exprs = record_fields(&refs);
+        let array_len = |expr: &ArcValue<'_>| match expr.as_ref() {
+            Value::ArrayLit(elems) => elems.len(),
+            _ => panic!("expected array"),
+        };
+
+        // The terminator of `repeat_until` is included, but the terminator of
+        // `repeat_while` is left for the next format
+        assert_eq!(array_len(&exprs[0]), 3);
+        assert_eq!(array_len(&exprs[1]), 2);
+        assert!(matches!(
+            exprs[2].as_ref(),
+            Value::ConstLit(Const::U8(200, _))
+        ));
+    }
+
+    #[test]
+    fn read_repeat_while_links() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{
+                start <- stream_pos,
+                elems <- repeat_while
+                    { x <- u8, r <- link (pos_add_u8 start x) u8 }
+                    (fun elem => u8_lt elem.x 4),
+                end <- u8,
+            }",
+        );
+        let data = [1, 2, 5, 9, 9, 7];
+
+        let refs = Context::new(&scope, Buffer::from(&data[..]))
+            .read_entrypoint(&format)
+            .unwrap();
+
+        // Links in the terminating element are forgotten, as it is left to be
+        // read by the next format
+        let mut positions = refs.keys().copied().collect::<Vec<_>>();
+        positions.sort_unstable();
+        assert_eq!(positions, [0, 1, 2]);
+    }
+
+    #[test]
+    fn read_align_skip_reserved() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{
+                a <- u8,
+                pad <- align 4,
+                b <- u8,
+                gap <- skip 2,
+                r <- reserved u16be 0xffff,
+                c <- limit8 3 { x <- u8, pad <- align 2, y <- u8 },
+            }",
+        );
+        let data = [1, 7, 7, 7, 2, 9, 9, 0xff, 0xff, 3, 8, 4];
+
+        let mut context = Context::new(&scope, Buffer::from(&data[..]));
+        let refs = context.read_entrypoint(&format).unwrap();
+        let parsed_ref = &refs[&0][0];
+        let expr = context.hide_skipped(&scope, &parsed_ref.format, &parsed_ref.expr);
+
+        let (labels, exprs) = match expr.as_ref() {
+            Value::RecordLit(labels, exprs) => (*labels, exprs.clone()),
+            _ => panic!("expected record"),
+        };
+        let labels = labels
+            .iter()
+            .map(|label| label.resolve())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, ["a", "b", "c"]);
+
+        // Alignment within the limited format is relative to its start
+        match exprs[2].as_ref() {
+            Value::RecordLit(labels, exprs) => {
+                assert_eq!(labels.len(), 2);
+                assert!(matches!(
+                    exprs[1].as_ref(),
+                    Value::ConstLit(Const::U8(4, _))
+                ));
+            }
+            _ => panic!("expected record"),
+        }
+
+        let mut corrupted = data;
+        corrupted[8] = 0;
+        let result = Context::new(&scope, Buffer::from(&corrupted[..])).read_entrypoint(&format);
+        assert!(matches!(result, Err(ReadError::ReservedMismatch(_, _, _))));
+    }
+
+    #[test]
+    fn hide_skipped_wrapped() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{
+                a <- u8,
+                gap <- skip 2 where u8_eq a 1,
+                r <- limit8 1 (reserved u8 0),
+                b <- u8,
+            }",
+        );
+        let data = [1, 9, 9, 0, 2];
+
+        let mut context = Context::new(&scope, Buffer::from(&data[..]));
+        let refs = context.read_entrypoint(&format).unwrap();
+        let parsed_ref = &refs[&0][0];
+        let expr = context.hide_skipped(&scope, &parsed_ref.format, &parsed_ref.expr);
+
+        // Skipped formats are hidden even when they are wrapped
+        let labels = match expr.as_ref() {
+            Value::RecordLit(labels, _) => labels.iter().map(|label| label.resolve()),
+            _ => panic!("expected record"),
+        };
+        assert_eq!(labels.collect::<Vec<_>>(), ["a", "b"]);
+    }
+
+    #[test]
+    fn read_end_of_input() {
+        let scope = Scope::new();
+        let format = elab_format(&scope, "{ a <- u16be, b <- end_of_input }");
+
+        let result = Context::new(&scope, Buffer::from(&[1, 2][..])).read_entrypoint(&format);
+        assert!(result.is_ok());
+
+        let result = Context::new(&scope, Buffer::from(&[1, 2, 3, 4][..])).read_entrypoint(&format);
+        assert!(matches!(result, Err(ReadError::ExpectedEndOfInput(_, 2))));
+
+        // Partially read bytes are consumed by exact limits
+        let format = elab_format(&scope, "limit_exact8 2 { x <- u8, y <- bits8_msb 4 }");
+        let result = Context::new(&scope, Buffer::from(&[1, 2][..])).read_entrypoint(&format);
+        assert!(result.is_ok());
+
+        let format = elab_format(&scope, "limit_exact8 3 u8");
+        let result = Context::new(&scope, Buffer::from(&[1, 2, 3][..])).read_entrypoint(&format);
+        assert!(matches!(result, Err(ReadError::UnconsumedLimit(_, 2))));
+    }
+
+    #[test]
+    fn read_choice() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{
+                a <- choice [
+                    { tag <- u8 where u8_eq tag 1, start <- stream_pos, r <- link start u8 },
+                    { tag <- u8, b <- u8 },
+                ],
+                c <- u8,
+            }",
+        );
+
+        let refs = Context::new(&scope, Buffer::from(&[2, 3, 4][..]))
+            .read_entrypoint(&format)
+            .unwrap();
+        // The link from the failed alternative is not followed
+        assert_eq!(refs.len(), 1);
+
+        let exprs = record_fields(&refs);
+        match exprs[0].as_ref() {
+            Value::RecordLit(_, exprs) => {
+                assert!(matches!(
+                    exprs[0].as_ref(),
+                    Value::ConstLit(Const::U64(1, _))
+                ));
+            }
+            _ => panic!("expected record"),
+        }
+        // The reader is left at the end of the alternative that succeeded
+        assert!(matches!(
+            exprs[1].as_ref(),
+            Value::ConstLit(Const::U8(4, _))
+        ));
+
+        let result = Context::new(&scope, Buffer::from(&[2][..])).read_entrypoint(&format);
+        match result {
+            Err(ReadError::ChoiceFailure(_, errors)) => {
+                assert!(matches!(errors[0], ReadError::CondFailure(_, _)));
+                assert!(matches!(errors[1], ReadError::BufferError(_, _)));
+            }
+            _ => panic!("expected choice failure"),
+        }
+    }
+
+    #[test]
+    fn read_map() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{ len <- map u8 U8 (fun x => u8_mul x 2), data <- repeat_len8 len u8 }",
+        );
+
+        let refs = Context::new(&scope, Buffer::from(&[2, 1, 2, 3, 4][..]))
+            .read_entrypoint(&format)
+            .unwrap();
+        let exprs = record_fields(&refs);
+
+        // Later fields depend on the mapped value
+        assert!(matches!(
+            exprs[0].as_ref(),
+            Value::ConstLit(Const::U8(4, _))
+        ));
+        assert!(matches!(exprs[1].as_ref(), Value::ArrayLit(elems) if elems.len() == 4));
+    }
+
+    #[test]
+    fn read_recover() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{
+                start <- stream_pos,
+                a <- u8 where u8_eq a 1,
+                r <- link (pos_add_u8 start 3) u16be,
+                c <- choice [{ x <- u16be where u16_eq x 0 }, u8],
+                d <- u8,
+            }",
+        );
+
+        let mut context = Context::new(&scope, Buffer::from(&[2, 3, 4, 5][..]));
+        context.set_recover(true);
+        let refs = context.read_entrypoint(&format).unwrap();
+
+        let exprs = record_fields(&refs);
+        let is_error = |expr: &ArcValue<'_>| {
+            matches!(
+                expr.as_ref(),
+                Value::Stuck(Head::Prim(Prim::ReportedError), _)
+            )
+        };
+
+        // Reading continues with the next field after the failed field
+        assert!(is_error(&exprs[1]));
+        // Errors in choice alternatives are not recovered from
+        match exprs[3].as_ref() {
+            Value::RecordLit(_, exprs) => {
+                assert!(matches!(
+                    exprs[0].as_ref(),
+                    Value::ConstLit(Const::U64(1, _))
+                ));
+            }
+            _ => panic!("expected record"),
+        }
+        assert!(matches!(
+            exprs[4].as_ref(),
+            Value::ConstLit(Const::U8(4, _))
+        ));
+        // The link that could not be read is replaced with an error
+        assert!(is_error(&refs[&3][0].expr));
+
+        let error_nodes = context.error_nodes();
+        assert_eq!(error_nodes.len(), 2);
+        assert_eq!(error_nodes[0].pos, 0);
+        assert!(matches!(error_nodes[0].error, ReadError::CondFailure(..)));
+        assert_eq!(error_nodes[1].pos, 3);
+        assert!(error_nodes[1].error.is_unexpected_end_of_buffer());
+    }
+
+    #[test]
+    fn read_provenance() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{
+                start <- stream_pos,
+                len <- u8,
+                data <- repeat_len8 len u16be,
+                rest <- limit8 3 { c <- choice [{ x <- u16be where u16_eq x 0 }, u8] },
+                r <- link (pos_add_u8 start 1) u8,
+            }",
+        );
+
+        let mut context = Context::new(&scope, Buffer::from(&[2, 1, 2, 3, 4, 5, 6, 7][..]));
+        context.set_record_provenance(true);
+        context.read_entrypoint(&format).unwrap();
+        let provenance = context.provenance().unwrap();
+
+        let range = |ref_pos, segments: &[PathSegment]| {
+            let path = ValuePath {
+                ref_pos,
+                ref_index: 0,
+                segments: segments.to_vec(),
+            };
+            let range = provenance.get(&path)?;
+            Some((range.start, range.end))
+        };
+        let field = |label| PathSegment::Field(Symbol::intern(label));
+
+        assert_eq!(range(0, &[]), Some((0, 8)));
+        assert_eq!(range(0, &[field("len")]), Some((0, 1)));
+        assert_eq!(range(0, &[field("data")]), Some((1, 5)));
+        assert_eq!(
+            range(0, &[field("data"), PathSegment::Index(1)]),
+            Some((3, 5))
+        );
+        // Limits cover the whole limit, even though they do not advance the reader
+        assert_eq!(range(0, &[field("rest")]), Some((5, 8)));
+        assert_eq!(
+            range(0, &[field("rest"), field("c"), field("value")]),
+            Some((5, 6))
+        );
+        // Values from failed alternatives are forgotten
+        let x_path = [field("rest"), field("c"), field("value"), field("x")];
+        assert_eq!(range(0, &x_path), None);
+        // Values within references are relative to the reference
+        assert_eq!(range(1, &[]), Some((1, 2)));
+
+        // Values are contained within the values that they were read as part of
+        for (path, child) in provenance.iter() {
+            let mut parent_path = path.clone();
+            while parent_path.segments.pop().is_some() {
+                if let Some(parent) = provenance.get(&parent_path) {
+                    assert!(parent.start <= child.start && child.end <= parent.end);
+                }
+            }
+        }
+
+        let path = ValuePath {
+            ref_pos: 0,
+            ref_index: 0,
+            segments: vec![field("data"), PathSegment::Index(1)],
+        };
+        assert_eq!(path.to_string(), "data[1]");
+    }
+
+    #[test]
+    fn refs_to_json() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{
+                start <- stream_pos,
+                small <- u64le,
+                large <- u64le,
+                padding <- skip 1,
+                r <- link (pos_add_u8 start 1) s8,
+            }",
+        );
+
+        let mut data = [0; 17];
+        data[..8].copy_from_slice(&(1u64 << 53).to_le_bytes());
+        data[8..16].copy_from_slice(&((1u64 << 53) + 1).to_le_bytes());
+        let mut context = Context::new(&scope, Buffer::from(&data[..]));
+        let refs = context.read_entrypoint(&format).unwrap();
+
+        let json = json::refs_to_json(&scope, &context, &refs);
+        let expected = serde_json::json!({
+            "0": [{
+                "start": { "pos": 0 },
+                "small": 9007199254740992u64,
+                "large": "9007199254740993",
+                "r": { "ref": 1 },
+            }],
+            "1": [0],
+        });
+        assert_eq!(json, expected);
+        // Fields are kept in the order that they were read
+        let fields = json["0"][0].as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(fields, ["start", "small", "large", "r"]);
+    }
+
+    #[test]
+    fn read_link_indices() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{
+                start <- stream_pos,
+                a <- repeat_len8 2 (link (pos_add_u8 start 1) u8),
+                b <- link (pos_add_u8 start 1) u16be,
+                c <- link (pos_add_u8 start 1) u8,
+                d <- choice [
+                    { r <- link (pos_add_u8 start 2) u8, x <- fail },
+                    { y <- deref b },
+                ],
+            }",
+        );
+        let data = [0, 1, 2];
+        let refs = Context::new(&scope, Buffer::from(&data[..]))
+            .read_entrypoint(&format)
+            .unwrap();
+
+        let exprs = record_fields(&refs);
+        let as_ref = |expr: &ArcValue<'_>| match expr.as_ref() {
+            Value::ConstLit(Const::Ref(pos, index)) => (pos.offset(), *index),
+            _ => panic!("expected ref"),
+        };
+        // Links that share the same format value share the same reference
+        match exprs[1].as_ref() {
+            Value::ArrayLit(elems) => {
+                assert_eq!(
+                    elems.iter().map(as_ref).collect::<Vec<_>>(),
+                    [(1, 0), (1, 0)]
+                );
+            }
+            _ => panic!("expected array"),
+        }
+        assert_eq!(as_ref(&exprs[2]), (1, 1));
+        // Links to equal formats share the same reference, even when the
+        // formats were evaluated separately
+        assert_eq!(as_ref(&exprs[3]), (1, 0));
+        assert_eq!(refs[&1].len(), 2);
+
+        // Links in failed alternatives are forgotten
+        assert!(!refs.contains_key(&2));
+    }
+
+    #[test]
+    fn read_link_cycle() {
+        let scope = Scope::new();
+        let file_id = FileId::try_from(1).unwrap();
+        let source = ProgramSource::try_from(
+            "def next : Format = u8;
+            def node : Format = {
+                start <- stream_pos,
+                offset <- u8,
+                link <- link (pos_add_u8 start offset) next,
+                value <- deref @next link,
+            };"
+            .to_owned(),
+        )
+        .unwrap();
+        let (surface_module, messages) = SurfaceModule::parse(&scope, &source);
+        assert!(messages.is_empty());
+        let mut context = elaboration::Context::new(file_id, &scope, ItemEnv::new());
+        let module = context.elab_module(&scope, &surface_module, &mut |_| {
+            panic!("unexpected message")
+        });
+
+        let data = [1, 0];
+        let mut context = Context::new(&scope, Buffer::from(&data[..]));
+        context.add_module(&module);
+
+        // Items cannot refer to themselves, so `next` is replaced with `node`
+        // to make each node link to another node
+        let next_level = EnvLen::new().next_level();
+        let node = context
+            .item_exprs
+            .get_level(next_level.next())
+            .unwrap()
+            .clone();
+        context.item_exprs.set_level(next_level, node);
+
+        // The node at 0 links to the node at 1, which links to itself
+        let format = Term::ItemVar(Span::Empty, next_level.next());
+        match context.read_entrypoint(&format) {
+            Err(ReadError::LinkCycle(_, chain)) => assert_eq!(chain, [1, 1]),
+            Err(err) => panic!("expected link cycle, found {err:?}"),
+            Ok(_) => panic!("expected link cycle"),
+        };
+    }
+
+    #[test]
+    fn read_limits() {
+        let scope = Scope::new();
+        let data = [0, 1, 2, 3];
+        let read = |source, limits| {
+            let format = elab_format(&scope, source);
+            let mut context = Context::new(&scope, Buffer::from(&data[..]));
+            context.set_limits(limits);
+            context.read_entrypoint(&format).map(|_| ())
+        };
+        let unlimited = ReadLimits::default();
+
+        let source = "repeat_len8 4 u8";
+        let limits = ReadLimits {
+            max_array_len: Some(3),
+            ..unlimited
+        };
+        assert!(matches!(
+            read(source, limits),
+            Err(ReadError::ArrayLimitExceeded(_, 4)),
+        ));
+        let limits = ReadLimits {
+            max_values: Some(4),
+            ..unlimited
+        };
+        assert!(matches!(
+            read(source, limits),
+            Err(ReadError::ValueLimitExceeded(_)),
+        ));
+        assert!(read(source, unlimited).is_ok());
+
+        let source = "{ x <- { y <- { z <- u8 } } }";
+        let limits = ReadLimits {
+            max_depth: Some(3),
+            ..unlimited
+        };
+        assert!(matches!(
+            read(source, limits),
+            Err(ReadError::DepthLimitExceeded(_)),
+        ));
+        let limits = ReadLimits {
+            max_depth: Some(4),
+            max_steps: Some(3),
+            ..unlimited
+        };
+        assert!(matches!(
+            read(source, limits),
+            Err(ReadError::StepLimitExceeded(_)),
+        ));
+
+        let source = "{
+            start <- stream_pos,
+            a <- link (pos_add_u8 start 1) u8,
+            b <- link (pos_add_u8 start 2) u8,
+        }";
+        let limits = ReadLimits {
+            max_pending_links: Some(1),
+            ..unlimited
+        };
+        assert!(matches!(
+            read(source, limits),
+            Err(ReadError::PendingLinkLimitExceeded(_)),
+        ));
+
+        // Limits are not recovered from, even in choices
+        let source = "{ x <- choice [repeat_len8 4 u8, u8] }";
+        let limits = ReadLimits {
+            max_array_len: Some(3),
+            ..unlimited
+        };
+        let format = elab_format(&scope, source);
+        let mut context = Context::new(&scope, Buffer::from(&data[..]));
+        context.set_limits(limits);
+        context.set_recover(true);
+        assert!(matches!(
+            context.read_entrypoint(&format),
+            Err(ReadError::ArrayLimitExceeded(_, 4)),
+        ));
+    }
+
+    #[test]
+    fn select() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{
+                start <- stream_pos,
+                len <- u8,
+                records <- repeat_len8 len { tag <- u8, padding <- skip 1 },
+                r <- link (pos_add_u8 start 1) { value <- u8 },
+            }",
+        );
+        let data = [2, 3, 0, 4, 0];
+        let mut context = Context::new(&scope, Buffer::from(&data[..]));
+        let refs = context.read_entrypoint(&format).unwrap();
+
+        let select = |query: &str| {
+            let query = query.parse().unwrap();
+            (select::select(&context, &refs, 0, &query).into_iter())
+                .map(|selection| (selection.label(), selection.expr))
+                .collect::<Vec<_>>()
+        };
+        let as_u8 = |expr: &ArcValue<'_>| match expr.as_ref() {
+            Value::ConstLit(Const::U8(value, _)) => *value,
+            _ => panic!("expected u8"),
+        };
+
+        let selections = select("records[1].tag");
+        assert_eq!(selections.len(), 1);
+        assert_eq!(selections[0].0, "records[1].tag");
+        assert_eq!(as_u8(&selections[0].1), 4);
+
+        let selections = select("records[*].tag");
+        let tags = selections.iter().map(|(_, expr)| as_u8(expr));
+        assert_eq!(tags.collect::<Vec<_>>(), [3, 4]);
+
+        // Skipped fields are not selected by wildcards
+        let labels = select("records[0].*").into_iter().map(|(label, _)| label);
+        assert_eq!(labels.collect::<Vec<_>>(), ["records[0].tag"]);
+
+        // References are followed
+        let selections = select(".r.value");
+        assert_eq!(selections[0].0, "r.value");
+        assert_eq!(as_u8(&selections[0].1), 3);
+
+        assert!(select("records[2]").is_empty());
+        assert!(select("missing").is_empty());
+        assert!("records[".parse::<select::Query>().is_err());
+        assert!("records..tag".parse::<select::Query>().is_err());
+        assert!("records tag".parse::<select::Query>().is_err());
+    }
+
+    #[test]
+    fn read_entrypoint_at() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{ start <- stream_pos, a <- u8, r <- link (pos_add_u8 start 2) u8 }",
+        );
+        let data = [0, 1, 2, 3, 4];
+
+        let refs = Context::new(&scope, Buffer::from(&data[..]))
+            .read_entrypoint_at(&format, 1, None)
+            .unwrap();
+        // Positions are relative to the start of the buffer
+        match refs[&1][0].expr.as_ref() {
+            Value::RecordLit(_, exprs) => {
+                assert!(
+                    matches!(exprs[0].as_ref(), Value::ConstLit(Const::Pos(pos)) if pos.offset() == 1)
+                );
+                assert!(matches!(
+                    exprs[1].as_ref(),
+                    Value::ConstLit(Const::U8(1, _))
+                ));
+            }
+            _ => panic!("expected record"),
+        }
+        assert!(matches!(
+            refs[&3][0].expr.as_ref(),
+            Value::ConstLit(Const::U8(3, _))
+        ));
+
+        // References can be read from outside of the length of the entrypoint
+        let refs = Context::new(&scope, Buffer::from(&data[..]))
+            .read_entrypoint_at(&format, 1, Some(1))
+            .unwrap();
+        assert!(matches!(
+            refs[&3][0].expr.as_ref(),
+            Value::ConstLit(Const::U8(3, _))
+        ));
+
+        let result =
+            Context::new(&scope, Buffer::from(&data[..])).read_entrypoint_at(&format, 1, Some(0));
+        assert!(matches!(
+            result,
+            Err(ReadError::BufferError(
+                _,
+                BufferError::UnexpectedEndOfBuffer
+            ))
+        ));
+    }
+
+    #[test]
+    fn read_repeat_pred_without_progress() {
+        let scope = Scope::new();
+        let format = elab_format(&scope, "repeat_until (succeed @U8 1) (fun x => u8_eq x 0)");
+
+        let result = Context::new(&scope, Buffer::from(&[0][..])).read_entrypoint(&format);
+        assert!(matches!(result, Err(ReadError::RepeatWithoutProgress(_))));
+    }
+}
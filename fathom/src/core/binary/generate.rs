@@ -0,0 +1,1595 @@
+//! Generation of random binary data from formats.
+//!
+//! Formats are walked in the same order that they would be read, choosing
+//! random values for primitive formats and writing them with a
+//! [`BufferWriter`]. Data is never changed once it has been generated, so
+//! formats that cover existing data, like the fields of overlap formats or
+//! links to data that was already generated, read their values from it.
+//!
+//! The predicates of conditional and repeat formats are satisfied by solving
+//! simple comparisons against constants, falling back to retrying with other
+//! values. Generated data is read back to check that it is valid, and is
+//! generated again if it is not. Random values are chosen with a generator
+//! seeded in [`Context::new`], so the same seed always produces the same data.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+
+use scoped_arena::Scope;
+
+use super::write::{self, BufferWriter, WriteError};
+use super::{Buffer, Compression, ReadError, TextEncoding};
+use crate::core::semantics::{
+    self, ArcValue, Closure, Elim, Head, SplitBranches, Telescope, Value,
+};
+use crate::core::{prim, Const, Item, Module, Plicity, Pos, Prim, Term, UIntStyle};
+use crate::env::{EnvLen, Level, SharedEnv, UniqueEnv};
+use crate::source::{Span, Spanned};
+
+/// The maximum length of arrays and strings whose length is chosen at random.
+const MAX_RANDOM_LEN: u64 = 16;
+/// The number of values to try when satisfying a predicate.
+const MAX_PRED_ATTEMPTS: usize = 16;
+/// The maximum number of formats to generate in a single attempt.
+const MAX_STEPS: usize = 1 << 20;
+
+#[derive(Clone, Debug)]
+pub enum GenerateError<'arena> {
+    InvalidFormat(Span),
+    InvalidValue(Span),
+    UnwrappedNone(Span),
+    GenerateFailFormat(Span),
+    /// No value could be found that satisfies the predicate of a conditional
+    /// or repeat format.
+    CondFailure(Span),
+    /// The generated data was longer than [`Context::set_max_len`], or took
+    /// too long to generate.
+    TooLarge(Span),
+    /// No alternative of a choice format could be generated that would be
+    /// read back as the same alternative.
+    ChoiceFailure(Span),
+    WriteError(WriteError<'arena>),
+    /// Data could not be read back, either when reading existing data or when
+    /// checking the data that was generated.
+    ReadError(ReadError<'arena>),
+}
+
+impl<'arena> fmt::Display for GenerateError<'arena> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenerateError::InvalidFormat(_) => f.write_str("invalid format"),
+            GenerateError::InvalidValue(_) => f.write_str("invalid value"),
+            GenerateError::UnwrappedNone(_) => f.write_str("unwrapped none"),
+            GenerateError::GenerateFailFormat(_) => f.write_str("generated a fail format"),
+            GenerateError::CondFailure(_) => f.write_str("could not satisfy predicate"),
+            GenerateError::TooLarge(_) => f.write_str("generated data is too large"),
+            GenerateError::ChoiceFailure(_) => f.write_str("could not generate choice"),
+            GenerateError::WriteError(err) => fmt::Display::fmt(err, f),
+            GenerateError::ReadError(err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+impl<'arena> std::error::Error for GenerateError<'arena> {}
+
+/// A reference to the data at a link, which is generated when it is first
+/// dereferenced, or once the rest of the data has been generated.
+struct GeneratedRef<'arena> {
+    format: ArcValue<'arena>,
+    expr: Option<ArcValue<'arena>>,
+}
+
+/// The state of the generator to return to when retrying a format.
+struct Checkpoint {
+    offset: usize,
+    bit_offset: u8,
+    len: usize,
+    pending_len: usize,
+    allocated_len: usize,
+    generated_len: usize,
+}
+
+pub struct Context<'arena> {
+    /// Scope used to allocate the strings that are generated.
+    scope: &'arena Scope<'arena>,
+    item_exprs: UniqueEnv<ArcValue<'arena>>,
+    local_exprs: SharedEnv<ArcValue<'arena>>,
+    rng: Rng,
+    /// Used to write the values chosen for primitive formats.
+    write_context: write::Context<'arena>,
+    refs: HashMap<usize, Vec<GeneratedRef<'arena>>>,
+    /// The positions and indices of the references that are yet to be
+    /// generated.
+    pending_refs: Vec<(usize, usize)>,
+    /// The positions of the references that were allocated, in the order that
+    /// they were allocated.
+    allocated_refs: Vec<usize>,
+    /// The positions and indices of the references that were generated, in
+    /// the order that they were generated.
+    generated_refs: Vec<(usize, usize)>,
+    /// The offset that alignment is relative to, which is the start of the
+    /// innermost limited format being generated.
+    buffer_start: usize,
+    /// The end of the innermost limited format being generated.
+    limit_end: Option<usize>,
+    steps: usize,
+    max_len: usize,
+    max_attempts: usize,
+}
+
+impl<'arena> Context<'arena> {
+    /// Create a context that generates data using random values chosen based
+    /// on `seed`.
+    pub fn new(scope: &'arena Scope<'arena>, seed: u64) -> Context<'arena> {
+        Context {
+            scope,
+            item_exprs: UniqueEnv::new(),
+            local_exprs: SharedEnv::new(),
+            rng: Rng::new(seed),
+            write_context: write::Context::new(),
+            refs: HashMap::new(),
+            pending_refs: Vec::new(),
+            allocated_refs: Vec::new(),
+            generated_refs: Vec::new(),
+            buffer_start: 0,
+            limit_end: None,
+            steps: 0,
+            max_len: 1 << 20,
+            max_attempts: 100,
+        }
+    }
+
+    fn eval_env(&mut self) -> semantics::EvalEnv<'arena, '_> {
+        let elim_env = semantics::ElimEnv::new(&self.item_exprs, [][..].into());
+        semantics::EvalEnv::new(elim_env, &mut self.local_exprs)
+    }
+
+    fn elim_env(&self) -> semantics::ElimEnv<'arena, '_> {
+        semantics::ElimEnv::new(&self.item_exprs, [][..].into())
+    }
+
+    pub fn add_module(&mut self, module: &Module<'arena>) {
+        self.item_exprs.reserve(module.items.len());
+        for item in module.items {
+            match item {
+                Item::Def { expr, .. } => {
+                    let expr = self.eval_env().eval(expr);
+                    self.item_exprs.push(expr);
+                }
+            }
+        }
+    }
+
+    /// Set the maximum length of the data that is generated. Defaults to
+    /// 1 MiB.
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
+    /// Set the number of times to try generating valid data before giving up.
+    /// Defaults to 100.
+    pub fn set_max_attempts(&mut self, max_attempts: usize) {
+        self.max_attempts = max_attempts;
+    }
+
+    /// Generate data that can be read by the entrypoint, returning the error
+    /// from the last attempt if no valid data could be generated.
+    pub fn generate_entrypoint(
+        &mut self,
+        format: &Term<'arena>,
+    ) -> Result<Vec<u8>, GenerateError<'arena>> {
+        let format_value = self.eval_env().eval(format);
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+            let error = match self.generate_data(&format_value) {
+                Ok(data) => match self.check_data(format, &data) {
+                    Ok(()) => return Ok(data),
+                    Err(err) => GenerateError::ReadError(err),
+                },
+                Err(err) => err,
+            };
+            if attempts >= self.max_attempts {
+                return Err(error);
+            }
+        }
+    }
+
+    fn generate_data(
+        &mut self,
+        format: &ArcValue<'arena>,
+    ) -> Result<Vec<u8>, GenerateError<'arena>> {
+        self.refs.clear();
+        self.pending_refs.clear();
+        self.allocated_refs.clear();
+        self.generated_refs.clear();
+        self.steps = 0;
+
+        let mut writer = BufferWriter::new();
+        let (index, _) = self.allocate_ref(0, format);
+        self.generate_ref(&mut writer, 0, index)?;
+
+        while let Some((pos, index)) = self.pending_refs.pop() {
+            self.generate_ref(&mut writer, pos, index)?;
+        }
+
+        Ok(writer.finish())
+    }
+
+    /// Check that the generated data can be read by the entrypoint.
+    fn check_data(&self, format: &Term<'arena>, data: &[u8]) -> Result<(), ReadError<'arena>> {
+        let mut context = super::Context::new(self.scope, Buffer::from(data));
+        context.item_exprs = self.item_exprs.clone();
+        context.read_entrypoint(format).map(|_| ())
+    }
+
+    fn checkpoint(&self, writer: &BufferWriter) -> Checkpoint {
+        Checkpoint {
+            offset: writer.offset(),
+            bit_offset: writer.bit_offset(),
+            len: writer.data().len(),
+            pending_len: self.pending_refs.len(),
+            allocated_len: self.allocated_refs.len(),
+            generated_len: self.generated_refs.len(),
+        }
+    }
+
+    /// Discard the data and references generated since a checkpoint. Data
+    /// that was overwritten, for example by bit formats, is not restored.
+    fn restore(&mut self, writer: &mut BufferWriter, checkpoint: Checkpoint) {
+        writer.truncate(checkpoint.len);
+        writer.set_bit_position(checkpoint.offset, checkpoint.bit_offset);
+        self.pending_refs.truncate(checkpoint.pending_len);
+        self.forget_refs(checkpoint.allocated_len);
+
+        // References generated since the checkpoint may have been truncated
+        for (pos, index) in self.generated_refs.drain(checkpoint.generated_len..) {
+            if let Some(generated_ref) = (self.refs.get_mut(&pos)).and_then(|r| r.get_mut(index)) {
+                generated_ref.expr = None;
+            }
+        }
+    }
+
+    fn check_len(&self, writer: &BufferWriter, span: Span) -> Result<(), GenerateError<'arena>> {
+        match writer.data().len() > self.max_len {
+            true => Err(GenerateError::TooLarge(span)),
+            false => Ok(()),
+        }
+    }
+
+    pub fn generate_format(
+        &mut self,
+        writer: &mut BufferWriter,
+        format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(GenerateError::TooLarge(format.span()));
+        }
+
+        match format.as_ref() {
+            Value::Stuck(Head::Prim(prim), slice) => {
+                self.generate_prim(writer, *prim, slice, format)
+            }
+            Value::FormatRecord(labels, formats) => {
+                let mut formats = formats.clone();
+                let mut exprs = Vec::with_capacity(formats.len());
+
+                while let Some((format, next_formats)) =
+                    self.elim_env().split_telescope(formats.clone())
+                {
+                    let expr = self.generate_field(writer, formats, &format)?;
+                    exprs.push(expr.clone());
+                    formats = next_formats(expr);
+                }
+
+                Ok(Spanned::new(
+                    format.span(),
+                    Arc::new(Value::RecordLit(labels, exprs)),
+                ))
+            }
+            Value::FormatCond(_label, elem_format, cond) => {
+                let pred = Predicate::Cond(cond);
+                self.generate_satisfying(writer, cond.span(), elem_format, pred, true)
+            }
+            Value::FormatOverlap(labels, formats) => {
+                let start_position = (writer.offset(), writer.bit_offset());
+                let mut max_position = start_position;
+                let mut formats = formats.clone();
+                let mut exprs = Vec::with_capacity(formats.len());
+
+                // Later fields read the data generated by earlier fields
+                while let Some((format, next_formats)) =
+                    self.elim_env().split_telescope(formats.clone())
+                {
+                    writer.set_bit_position(start_position.0, start_position.1);
+                    let expr = self.generate_field(writer, formats, &format)?;
+                    exprs.push(expr.clone());
+                    formats = next_formats(expr);
+
+                    max_position =
+                        std::cmp::max(max_position, (writer.offset(), writer.bit_offset()));
+                }
+
+                writer.set_bit_position(max_position.0, max_position.1);
+
+                Ok(Spanned::new(
+                    format.span(),
+                    Arc::new(Value::RecordLit(labels, exprs)),
+                ))
+            }
+
+            Value::Stuck(Head::LocalVar(_), _)
+            | Value::Stuck(Head::MetaVar(_), _)
+            | Value::Universe
+            | Value::FunType(..)
+            | Value::FunLit(..)
+            | Value::RecordType(_, _)
+            | Value::RecordLit(_, _)
+            | Value::ArrayLit(_)
+            | Value::ConstLit(_) => Err(GenerateError::InvalidFormat(format.span())),
+        }
+    }
+
+    /// Generate the first field of a telescope of formats. Integer fields that
+    /// later fields match on are usually set to one of the patterns that they
+    /// are matched against, so that each branch of the match is generated.
+    fn generate_field(
+        &mut self,
+        writer: &mut BufferWriter,
+        formats: Telescope<'arena>,
+        format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        if let Some(int_type) = IntType::from_format(format) {
+            let patterns = (self.match_patterns(formats).iter())
+                .filter_map(|pattern| int_type.const_to_int(pattern))
+                .collect::<Vec<_>>();
+
+            if !patterns.is_empty() && self.rng.below(4) != 0 {
+                let value = patterns[self.rng.below(patterns.len() as u64) as usize];
+                let expr = const_value(format.span(), int_type.to_const(value));
+                return self.generate_leaf(writer, format, |_| expr);
+            }
+        }
+
+        self.generate_format(writer, format)
+    }
+
+    /// Find the patterns that the first field of a telescope is matched
+    /// against by the formats of the later fields.
+    fn match_patterns(&self, formats: Telescope<'arena>) -> Vec<Const> {
+        let elim_env = self.elim_env();
+        let mut patterns = Vec::new();
+
+        // Bind each field to a different variable, so that the formats that
+        // match on the first field are stuck on its variable
+        let mut vars = crate::env::levels();
+        let var = vars.next().unwrap();
+        let mut formats = match elim_env.split_telescope(formats) {
+            Some((_, next_formats)) => {
+                next_formats(Spanned::empty(Arc::new(Value::local_var(var))))
+            }
+            None => return patterns,
+        };
+
+        while let Some((format, next_formats)) = elim_env.split_telescope(formats) {
+            if let Value::Stuck(Head::LocalVar(level), spine) = format.as_ref() {
+                if let (true, [Elim::ConstMatch(branches)]) = (*level == var, &spine[..]) {
+                    let mut branches = branches.clone();
+                    while let SplitBranches::Branch((pattern, _), next_branches) =
+                        elim_env.split_branches(branches)
+                    {
+                        patterns.push(pattern);
+                        branches = next_branches;
+                    }
+                }
+            }
+
+            let var = vars.next().unwrap();
+            formats = next_formats(Spanned::empty(Arc::new(Value::local_var(var))));
+        }
+
+        patterns
+    }
+
+    #[rustfmt::skip]
+    fn generate_prim(
+        &mut self,
+        writer: &mut BufferWriter,
+        prim: Prim,
+        slice: &[Elim<'arena>],
+        format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        use crate::core::semantics::Elim::FunApp;
+
+        let span = format.span();
+        if let (Some(int_type), []) = (IntType::from_format_prim(prim), slice) {
+            return self.generate_leaf(writer, format, |rng| const_value(span, int_type.to_const(int_type.random(rng))));
+        }
+
+        match (prim, slice) {
+            (Prim::FormatF32Be | Prim::FormatF32Le, []) => self.generate_leaf(writer, format, |rng| const_value(span, Const::F32(f32::from_bits(rng.next_u64() as u32)))),
+            (Prim::FormatF64Be | Prim::FormatF64Le, []) => self.generate_leaf(writer, format, |rng| const_value(span, Const::F64(f64::from_bits(rng.next_u64())))),
+            (Prim::FormatBitMsb | Prim::FormatBitLsb, []) => {
+                let expr = const_value(span, Const::Bool(self.rng.below(2) == 1));
+                self.write_leaf(writer, format, expr)
+            }
+            (Prim::FormatBits8Msb | Prim::FormatBits8Lsb, [FunApp(_, count)]) => self.generate_bits(writer, format, count, |bits| Const::U8(bits as u8, UIntStyle::Decimal)),
+            (Prim::FormatBits16Msb | Prim::FormatBits16Lsb, [FunApp(_, count)]) => self.generate_bits(writer, format, count, |bits| Const::U16(bits as u16, UIntStyle::Decimal)),
+            (Prim::FormatBits32Msb | Prim::FormatBits32Lsb, [FunApp(_, count)]) => self.generate_bits(writer, format, count, |bits| Const::U32(bits as u32, UIntStyle::Decimal)),
+            (Prim::FormatBits64Msb | Prim::FormatBits64Lsb, [FunApp(_, count)]) => self.generate_bits(writer, format, count, |bits| Const::U64(bits, UIntStyle::Decimal)),
+            (Prim::FormatByteAlign, []) => {
+                writer.align_to_byte();
+                Ok(unit_value(span))
+            }
+            (Prim::FormatStringNul, [FunApp(_, encoding)])
+            | (Prim::FormatStringPrefixed8, [FunApp(_, encoding)])
+            | (Prim::FormatStringPrefixed16Be, [FunApp(_, encoding)])
+            | (Prim::FormatStringPrefixed16Le, [FunApp(_, encoding)])
+            | (Prim::FormatStringPrefixed32Be, [FunApp(_, encoding)])
+            | (Prim::FormatStringPrefixed32Le, [FunApp(_, encoding)]) => {
+                text_encoding(encoding)?;
+                let scope = self.scope;
+                self.generate_leaf(writer, format, |rng| const_value(span, random_string(scope, rng, MAX_RANDOM_LEN)))
+            }
+            (Prim::FormatStringFixed8, [FunApp(_, len), FunApp(_, encoding)])
+            | (Prim::FormatStringFixed16, [FunApp(_, len), FunApp(_, encoding)])
+            | (Prim::FormatStringFixed32, [FunApp(_, len), FunApp(_, encoding)])
+            | (Prim::FormatStringFixed64, [FunApp(_, len), FunApp(_, encoding)]) => {
+                let max_len = uint_value(len)? / text_encoding(encoding)?.code_unit_len() as u64;
+                let scope = self.scope;
+                self.generate_leaf(writer, format, |rng| const_value(span, random_string(scope, rng, max_len)))
+            }
+            (Prim::FormatRepeatLen8, [FunApp(_, len), FunApp(_, format)]) => self.generate_repeat_len(writer, span, len, format),
+            (Prim::FormatRepeatLen16, [FunApp(_, len), FunApp(_, format)]) => self.generate_repeat_len(writer, span, len, format),
+            (Prim::FormatRepeatLen32, [FunApp(_, len), FunApp(_, format)]) => self.generate_repeat_len(writer, span, len, format),
+            (Prim::FormatRepeatLen64, [FunApp(_, len), FunApp(_, format)]) => self.generate_repeat_len(writer, span, len, format),
+            (Prim::FormatRepeatUntilEnd, [FunApp(_, format)]) => self.generate_repeat_until_end(writer, span, format),
+            (Prim::FormatRepeatUntil, [FunApp(_, format), FunApp(_, pred)]) => self.generate_repeat_until(writer, span, format, pred),
+            (Prim::FormatRepeatWhile, [FunApp(_, format), FunApp(_, pred)]) => self.generate_repeat_while(writer, span, format, pred),
+            (Prim::FormatLimit8, [FunApp(_, limit), FunApp(_, format)]) => self.generate_limit(writer, limit, format, false),
+            (Prim::FormatLimit16, [FunApp(_, limit), FunApp(_, format)]) => self.generate_limit(writer, limit, format, false),
+            (Prim::FormatLimit32, [FunApp(_, limit), FunApp(_, format)]) => self.generate_limit(writer, limit, format, false),
+            (Prim::FormatLimit64, [FunApp(_, limit), FunApp(_, format)]) => self.generate_limit(writer, limit, format, false),
+            (Prim::FormatLimitExact8, [FunApp(_, limit), FunApp(_, format)]) => self.generate_limit(writer, limit, format, true),
+            (Prim::FormatLimitExact16, [FunApp(_, limit), FunApp(_, format)]) => self.generate_limit(writer, limit, format, true),
+            (Prim::FormatLimitExact32, [FunApp(_, limit), FunApp(_, format)]) => self.generate_limit(writer, limit, format, true),
+            (Prim::FormatLimitExact64, [FunApp(_, limit), FunApp(_, format)]) => self.generate_limit(writer, limit, format, true),
+            (Prim::FormatDeflate, [_, _]) => Err(GenerateError::WriteError(WriteError::CompressedFormat(span, Compression::Deflate))),
+            (Prim::FormatZlib, [_, _]) => Err(GenerateError::WriteError(WriteError::CompressedFormat(span, Compression::Zlib))),
+            (Prim::FormatBrotli, [_, _]) => Err(GenerateError::WriteError(WriteError::CompressedFormat(span, Compression::Brotli))),
+            (Prim::FormatAlign, [FunApp(_, align)]) => {
+                let align = usize::try_from(uint_value(align)?).map_err(|_| GenerateError::TooLarge(span))?;
+                if align > 0 {
+                    let padding = (align - (writer.offset() - self.buffer_start) % align) % align;
+                    self.generate_skip(writer, span, padding)?;
+                }
+                Ok(unit_value(span))
+            }
+            (Prim::FormatSkip, [FunApp(_, len)]) => {
+                let len = usize::try_from(uint_value(len)?).map_err(|_| GenerateError::TooLarge(span))?;
+                self.generate_skip(writer, span, len)?;
+                Ok(unit_value(span))
+            }
+            (Prim::FormatReserved, [_, FunApp(_, expected)]) => self.generate_leaf(writer, format, |_| expected.clone()),
+            (Prim::FormatLink, [FunApp(_, pos), FunApp(_, format)]) => self.generate_link(span, pos, format),
+            (Prim::FormatDeref, [_, FunApp(_, r#ref)]) => self.generate_deref(writer, r#ref),
+            // The data that follows the end of the input is not known until
+            // it has all been generated, so this is checked afterwards
+            (Prim::FormatEndOfInput, []) => Ok(unit_value(span)),
+            (Prim::FormatChoice, [FunApp(_, formats)]) => self.generate_choice(writer, span, formats),
+            (Prim::FormatMap, [FunApp(_, format), _, FunApp(_, map_fn)]) => {
+                let expr = self.generate_format(writer, format)?;
+                Ok(self.elim_env().fun_app(Plicity::Explicit, map_fn.clone(), expr))
+            }
+            (Prim::FormatStreamPos, []) => match Pos::initial(writer.offset()) {
+                Some(pos) => Ok(const_value(span, Const::Pos(pos))),
+                None => Err(GenerateError::TooLarge(span)),
+            },
+            (Prim::FormatSucceed, [_, FunApp(_, elem)]) => Ok(elem.clone()),
+            (Prim::FormatFail, []) => Err(GenerateError::GenerateFailFormat(span)),
+            (Prim::FormatUnwrap, [_, FunApp(_, option)]) => match option.match_prim_spine() {
+                Some((Prim::OptionSome, [_, FunApp(_, elem)])) => Ok(elem.clone()),
+                Some((Prim::OptionNone, [_])) => Err(GenerateError::UnwrappedNone(span)),
+                _ => Err(GenerateError::InvalidValue(span)),
+            },
+            _ => Err(GenerateError::InvalidFormat(span)),
+        }
+    }
+
+    /// Generate a primitive format, using `random` to choose its value unless
+    /// the data that it covers has already been generated, in which case the
+    /// value is read from the existing data.
+    fn generate_leaf(
+        &mut self,
+        writer: &mut BufferWriter,
+        format: &ArcValue<'arena>,
+        random: impl FnOnce(&mut Rng) -> ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        if writer.is_byte_aligned() && writer.offset() < writer.data().len() {
+            let result = {
+                let buffer = Buffer::from(writer.data());
+                let mut context = super::Context::new(self.scope, buffer);
+                (buffer.reader_with_offset(writer.offset()))
+                    .map_err(ReadError::from)
+                    .and_then(|mut reader| {
+                        let expr = context.read_format(&mut reader, format)?;
+                        Ok((expr, reader.offset()?))
+                    })
+            };
+
+            match result {
+                Ok((expr, end)) => {
+                    writer.set_offset(end);
+                    return Ok(expr);
+                }
+                // Data that extends past the existing data is generated
+                Err(err) if err.is_unexpected_end_of_buffer() => {}
+                Err(err) => return Err(GenerateError::ReadError(err)),
+            }
+        }
+
+        let expr = random(&mut self.rng);
+        self.write_leaf(writer, format, expr)
+    }
+
+    fn write_leaf(
+        &mut self,
+        writer: &mut BufferWriter,
+        format: &ArcValue<'arena>,
+        expr: ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        (self.write_context)
+            .write_format(writer, format, &expr)
+            .map_err(GenerateError::WriteError)?;
+        self.check_len(writer, format.span())?;
+        Ok(expr)
+    }
+
+    fn generate_bits(
+        &mut self,
+        writer: &mut BufferWriter,
+        format: &ArcValue<'arena>,
+        count: &ArcValue<'arena>,
+        to_const: fn(u64) -> Const<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        let count = match count.as_ref() {
+            Value::ConstLit(Const::U8(count, _)) => *count,
+            _ => return Err(GenerateError::InvalidValue(count.span())),
+        };
+        let bits = match count {
+            0 => 0,
+            1..=63 => self.rng.next_u64() >> (64 - count),
+            _ => self.rng.next_u64(),
+        };
+
+        self.write_leaf(writer, format, const_value(format.span(), to_const(bits)))
+    }
+
+    fn generate_skip(
+        &mut self,
+        writer: &mut BufferWriter,
+        span: Span,
+        len: usize,
+    ) -> Result<(), GenerateError<'arena>> {
+        if !writer.is_byte_aligned() {
+            return Err(GenerateError::WriteError(WriteError::UnalignedWrite(span)));
+        }
+        if writer.offset().saturating_add(len) > self.max_len {
+            return Err(GenerateError::TooLarge(span));
+        }
+        writer.skip_bytes(len);
+        Ok(())
+    }
+
+    /// Generate a format whose value satisfies a predicate, solving the
+    /// predicate for integer formats, and otherwise generating values until
+    /// one satisfies it. Values that could not be generated are retried like
+    /// those that do not satisfy the predicate.
+    fn generate_satisfying(
+        &mut self,
+        writer: &mut BufferWriter,
+        span: Span,
+        format: &ArcValue<'arena>,
+        pred: Predicate<'_, 'arena>,
+        expected: bool,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        let var = EnvLen::new().next_level();
+        let result = pred.apply(
+            &self.elim_env(),
+            Spanned::empty(Arc::new(Value::local_var(var))),
+        );
+        let solution = match IntType::from_format(format) {
+            Some(int_type) => solve(int_type, var, &result, expected).map(Solution::Range),
+            None => solve_equal(var, &result, expected).map(Solution::Const),
+        };
+
+        let mut error = None;
+        for _ in 0..MAX_PRED_ATTEMPTS {
+            let checkpoint = self.checkpoint(writer);
+            let result = match &solution {
+                Some(Solution::Range(range)) => self.generate_leaf(writer, format, |rng| {
+                    const_value(format.span(), range.int_type.to_const(range.random(rng)))
+                }),
+                Some(Solution::Const(r#const)) => {
+                    self.generate_leaf(writer, format, |_| const_value(format.span(), *r#const))
+                }
+                None => self.generate_format(writer, format),
+            };
+            let expr = match result {
+                Ok(expr) => expr,
+                // Retrying would only exceed the limits again
+                Err(err @ GenerateError::TooLarge(_)) => return Err(err),
+                Err(err) => {
+                    self.restore(writer, checkpoint);
+                    error = Some(err);
+                    continue;
+                }
+            };
+
+            // Predicates can inspect the data that was generated
+            let elim_env = self.elim_env().with_binary_data(&*writer);
+            match pred.apply(&elim_env, expr.clone()).as_ref() {
+                Value::ConstLit(Const::Bool(result)) if *result == expected => return Ok(expr),
+                _ => {
+                    self.restore(writer, checkpoint);
+                    error = None;
+                }
+            }
+        }
+
+        // Report the error from the last attempt
+        Err(error.unwrap_or(GenerateError::CondFailure(span)))
+    }
+
+    fn generate_repeat_len(
+        &mut self,
+        writer: &mut BufferWriter,
+        span: Span,
+        len: &ArcValue<'arena>,
+        elem_format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        let len = uint_value(len)?;
+        if len > self.max_len as u64 {
+            return Err(GenerateError::TooLarge(span));
+        }
+
+        let elem_exprs = (0..len)
+            .map(|_| self.generate_format(writer, elem_format))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Spanned::new(span, Arc::new(Value::ArrayLit(elem_exprs))))
+    }
+
+    fn generate_repeat_until_end(
+        &mut self,
+        writer: &mut BufferWriter,
+        span: Span,
+        elem_format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        let mut elems = Vec::new();
+
+        match self.limit_end {
+            // Fill the limited format with as many elements as will fit
+            Some(end) => {
+                while writer.offset() < end {
+                    let checkpoint = self.checkpoint(writer);
+                    let elem = self.generate_format(writer, elem_format)?;
+                    if writer.offset() > end {
+                        self.restore(writer, checkpoint);
+                        break;
+                    }
+                    elems.push(elem);
+                }
+            }
+            None => {
+                for _ in 0..self.rng.below(MAX_RANDOM_LEN + 1) {
+                    elems.push(self.generate_format(writer, elem_format)?);
+                }
+            }
+        }
+
+        Ok(Spanned::new(span, Arc::new(Value::ArrayLit(elems))))
+    }
+
+    fn generate_repeat_until(
+        &mut self,
+        writer: &mut BufferWriter,
+        span: Span,
+        elem_format: &ArcValue<'arena>,
+        pred: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        let mut elems = Vec::new();
+
+        loop {
+            // The terminating element is included in the array
+            let is_last = elems.len() as u64 >= MAX_RANDOM_LEN || self.rng.below(4) == 0;
+            let pred = Predicate::Fun(pred);
+            let elem = self.generate_satisfying(writer, span, elem_format, pred, is_last)?;
+            elems.push(elem);
+
+            if is_last {
+                return Ok(Spanned::new(span, Arc::new(Value::ArrayLit(elems))));
+            }
+        }
+    }
+
+    fn generate_repeat_while(
+        &mut self,
+        writer: &mut BufferWriter,
+        span: Span,
+        elem_format: &ArcValue<'arena>,
+        pred: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        let len = self.rng.below(MAX_RANDOM_LEN + 1);
+        let mut elems = Vec::new();
+
+        for _ in 0..len {
+            let pred = Predicate::Fun(pred);
+            elems.push(self.generate_satisfying(writer, span, elem_format, pred, true)?);
+        }
+
+        // The terminating element is excluded from the array, but is still
+        // read, so we generate it and leave the writer at the start of it
+        let position = (writer.offset(), writer.bit_offset());
+        let pred = Predicate::Fun(pred);
+        self.generate_satisfying(writer, span, elem_format, pred, false)?;
+        writer.set_bit_position(position.0, position.1);
+
+        Ok(Spanned::new(span, Arc::new(Value::ArrayLit(elems))))
+    }
+
+    fn generate_limit(
+        &mut self,
+        writer: &mut BufferWriter,
+        len: &ArcValue<'arena>,
+        elem_format: &ArcValue<'arena>,
+        exact: bool,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        let len_span = len.span();
+        let len =
+            usize::try_from(uint_value(len)?).map_err(|_| GenerateError::TooLarge(len_span))?;
+
+        let start_offset = writer.offset();
+        let end_offset = (start_offset.checked_add(len))
+            .filter(|end_offset| *end_offset <= self.max_len)
+            .ok_or(GenerateError::TooLarge(len_span))?;
+
+        let buffer_start = std::mem::replace(&mut self.buffer_start, start_offset);
+        let limit_end = self.limit_end.replace(end_offset);
+        let result = self.generate_format(writer, elem_format);
+        self.buffer_start = buffer_start;
+        self.limit_end = limit_end;
+        let expr = result?;
+
+        if writer.offset() > end_offset {
+            return Err(GenerateError::WriteError(WriteError::LimitExceeded {
+                span: len_span,
+                limit: len,
+                len: writer.offset() - start_offset,
+            }));
+        }
+        // A partially written byte is considered to be filled, like when reading
+        let written_end = writer.offset() + usize::from(!writer.is_byte_aligned());
+        if exact && written_end < end_offset {
+            return Err(GenerateError::WriteError(WriteError::LimitNotFilled {
+                span: len_span,
+                limit: len,
+                len: written_end - start_offset,
+            }));
+        }
+
+        // The reader does not advance past limited formats, so neither do we
+        writer.extend_to(end_offset);
+        writer.set_offset(start_offset);
+
+        Ok(expr)
+    }
+
+    /// Generate a random alternative of a choice format. The first
+    /// alternative that succeeds is the one that is read, so an alternative is
+    /// only kept if none of the alternatives before it can be read from the
+    /// data that was generated for it.
+    fn generate_choice(
+        &mut self,
+        writer: &mut BufferWriter,
+        span: Span,
+        formats: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        let formats = match formats.as_ref() {
+            Value::ArrayLit(formats) if formats.is_empty() => {
+                return Err(GenerateError::GenerateFailFormat(span));
+            }
+            Value::ArrayLit(formats) => formats,
+            _ => return Err(GenerateError::InvalidValue(formats.span())),
+        };
+
+        let mut error = GenerateError::ChoiceFailure(span);
+        for _ in 0..MAX_PRED_ATTEMPTS {
+            let checkpoint = self.checkpoint(writer);
+            let index = self.rng.below(formats.len() as u64);
+            let earlier_formats = &formats[..index as usize];
+
+            match self.generate_format(writer, &formats[index as usize]) {
+                Ok(expr) if !self.is_readable(writer, &checkpoint, earlier_formats) => {
+                    let index = const_value(Span::Empty, Const::U64(index, UIntStyle::Decimal));
+                    return Ok(Spanned::new(
+                        span,
+                        Arc::new(Value::RecordLit(prim::choice_labels(), vec![index, expr])),
+                    ));
+                }
+                Ok(_) => error = GenerateError::ChoiceFailure(span),
+                // Retrying would only exceed the limits again
+                Err(err @ GenerateError::TooLarge(_)) => return Err(err),
+                Err(err) => error = err,
+            }
+            self.restore(writer, checkpoint);
+        }
+
+        // Report the error from the last attempt
+        Err(error)
+    }
+
+    /// Check if any of the formats might be read from the data generated
+    /// since a checkpoint. Formats that extend past the data that has been
+    /// generated so far might be completed by later data, so are assumed to
+    /// be readable.
+    fn is_readable(
+        &self,
+        writer: &BufferWriter,
+        checkpoint: &Checkpoint,
+        formats: &[ArcValue<'arena>],
+    ) -> bool {
+        if formats.is_empty() {
+            return false;
+        }
+        // Data is only read back from byte boundaries
+        if checkpoint.bit_offset != 0 {
+            return true;
+        }
+
+        let buffer = Buffer::from(writer.data());
+        formats.iter().any(|format| {
+            let mut context = super::Context::new(self.scope, buffer);
+            let result = (buffer.reader_with_offset(checkpoint.offset))
+                .map_err(ReadError::from)
+                .and_then(|mut reader| context.read_format(&mut reader, format));
+
+            match result {
+                Ok(_) => true,
+                Err(err) => err.is_unexpected_end_of_buffer(),
+            }
+        })
+    }
+
+    fn generate_link(
+        &mut self,
+        span: Span,
+        pos_value: &ArcValue<'arena>,
+        elem_format: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        // Only the initial buffer is generated
+        let pos = match pos_value.as_ref() {
+            Value::ConstLit(Const::Pos(pos)) if pos.buffer() == 0 => *pos,
+            _ => return Err(GenerateError::InvalidValue(pos_value.span())),
+        };
+
+        let (index, is_new) = self.allocate_ref(pos.offset(), elem_format);
+        if is_new {
+            self.pending_refs.push((pos.offset(), index));
+        }
+        let index = u32::try_from(index).map_err(|_| GenerateError::InvalidValue(span))?;
+
+        Ok(const_value(span, Const::Ref(pos, index)))
+    }
+
+    fn generate_deref(
+        &mut self,
+        writer: &mut BufferWriter,
+        r#ref: &ArcValue<'arena>,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        match r#ref.as_ref() {
+            Value::ConstLit(Const::Ref(pos, index)) if pos.buffer() == 0 => {
+                self.generate_ref(writer, pos.offset(), *index as usize)
+            }
+            _ => Err(GenerateError::InvalidValue(r#ref.span())),
+        }
+    }
+
+    /// Generate the data at a reference, if it has yet to be generated.
+    fn generate_ref(
+        &mut self,
+        writer: &mut BufferWriter,
+        pos: usize,
+        index: usize,
+    ) -> Result<ArcValue<'arena>, GenerateError<'arena>> {
+        let generated_ref = &self.refs[&pos][index];
+        if let Some(expr) = &generated_ref.expr {
+            return Ok(expr.clone());
+        }
+        let format = generated_ref.format.clone();
+        if pos > self.max_len {
+            return Err(GenerateError::TooLarge(format.span()));
+        }
+
+        // References are generated relative to the start of the data
+        let position = (writer.offset(), writer.bit_offset());
+        let buffer_start = std::mem::replace(&mut self.buffer_start, 0);
+        let limit_end = self.limit_end.take();
+        writer.set_offset(pos);
+
+        let result = self.generate_format(writer, &format);
+
+        writer.set_bit_position(position.0, position.1);
+        self.buffer_start = buffer_start;
+        self.limit_end = limit_end;
+        let expr = result?;
+
+        self.refs.get_mut(&pos).unwrap()[index].expr = Some(expr.clone());
+        self.generated_refs.push((pos, index));
+
+        Ok(expr)
+    }
+
+    /// Find the index of the reference to `format` at `pos`, allocating a new
+    /// reference if one has yet to be allocated. Formats are compared in the
+    /// same way as when reading. Returns `true` alongside the index if the
+    /// reference is new.
+    fn allocate_ref(&mut self, pos: usize, format: &ArcValue<'arena>) -> (usize, bool) {
+        let refs = self.refs.get(&pos).map_or(&[][..], Vec::as_slice);
+        let is_equal = |generated_ref: &GeneratedRef<'_>| {
+            std::ptr::eq(generated_ref.format.as_ref(), format.as_ref())
+                || (self.elim_env().conversion_env(EnvLen::new()))
+                    .is_equal(&generated_ref.format, format)
+        };
+        if let Some(index) = refs.iter().position(is_equal) {
+            return (index, false);
+        }
+
+        let refs = self.refs.entry(pos).or_default();
+        refs.push(GeneratedRef {
+            format: format.clone(),
+            expr: None,
+        });
+        self.allocated_refs.push(pos);
+
+        (refs.len() - 1, true)
+    }
+
+    /// Forget the references allocated after the first `len` references.
+    fn forget_refs(&mut self, len: usize) {
+        for pos in self.allocated_refs.drain(len..).rev() {
+            if let Some(refs) = self.refs.get_mut(&pos) {
+                refs.pop();
+                if refs.is_empty() {
+                    self.refs.remove(&pos);
+                }
+            }
+        }
+    }
+}
+
+/// A predicate that a generated value should satisfy.
+enum Predicate<'a, 'arena> {
+    /// The predicate of a conditional format
+    Cond(&'a Closure<'arena>),
+    /// The predicate of a repeat format, which is a function
+    Fun(&'a ArcValue<'arena>),
+}
+
+impl<'a, 'arena> Predicate<'a, 'arena> {
+    fn apply(
+        &self,
+        elim_env: &semantics::ElimEnv<'arena, '_>,
+        expr: ArcValue<'arena>,
+    ) -> ArcValue<'arena> {
+        match self {
+            Predicate::Cond(cond) => elim_env.apply_closure(cond, expr),
+            Predicate::Fun(pred) => elim_env.fun_app(Plicity::Explicit, (*pred).clone(), expr),
+        }
+    }
+}
+
+/// Values that satisfy a predicate.
+enum Solution<'arena> {
+    Range(IntRange),
+    Const(Const<'arena>),
+}
+
+/// A small random number generator ([SplitMix64]), so that the data generated
+/// from a seed does not depend on the platform or on other crates.
+///
+/// [SplitMix64]: https://prng.di.unimi.it/splitmix64.c
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A random number less than `bound`, which must not be zero.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum IntType {
+    U8,
+    U16,
+    U32,
+    U64,
+    S8,
+    S16,
+    S32,
+    S64,
+}
+
+impl IntType {
+    fn from_format(format: &ArcValue<'_>) -> Option<IntType> {
+        match format.match_prim_spine()? {
+            (prim, []) => IntType::from_format_prim(prim),
+            _ => None,
+        }
+    }
+
+    fn from_format_prim(prim: Prim) -> Option<IntType> {
+        match prim {
+            Prim::FormatU8 => Some(IntType::U8),
+            Prim::FormatU16Be | Prim::FormatU16Le => Some(IntType::U16),
+            Prim::FormatU32Be | Prim::FormatU32Le => Some(IntType::U32),
+            Prim::FormatU64Be | Prim::FormatU64Le => Some(IntType::U64),
+            Prim::FormatUleb128 | Prim::FormatVlqBe => Some(IntType::U64),
+            Prim::FormatS8 => Some(IntType::S8),
+            Prim::FormatS16Be | Prim::FormatS16Le => Some(IntType::S16),
+            Prim::FormatS32Be | Prim::FormatS32Le => Some(IntType::S32),
+            Prim::FormatS64Be | Prim::FormatS64Le => Some(IntType::S64),
+            Prim::FormatSleb128 | Prim::FormatVarintZigzag => Some(IntType::S64),
+            _ => None,
+        }
+    }
+
+    fn bounds(self) -> (i128, i128) {
+        match self {
+            IntType::U8 => (0, u8::MAX.into()),
+            IntType::U16 => (0, u16::MAX.into()),
+            IntType::U32 => (0, u32::MAX.into()),
+            IntType::U64 => (0, u64::MAX.into()),
+            IntType::S8 => (i8::MIN.into(), i8::MAX.into()),
+            IntType::S16 => (i16::MIN.into(), i16::MAX.into()),
+            IntType::S32 => (i32::MIN.into(), i32::MAX.into()),
+            IntType::S64 => (i64::MIN.into(), i64::MAX.into()),
+        }
+    }
+
+    /// Choose a random value, favouring small values and the bounds of the
+    /// type, as these are more likely to be meaningful as lengths, offsets and
+    /// flags.
+    fn random(self, rng: &mut Rng) -> i128 {
+        let (min, max) = self.bounds();
+        let bits = rng.next_u64();
+        match rng.below(8) {
+            0..=3 if min < 0 => i128::from(rng.below(17)) - 8,
+            0..=3 => i128::from(rng.below(17)),
+            4 => [min, max, 0, 1][rng.below(4) as usize],
+            _ => match self {
+                IntType::U8 => i128::from(bits as u8),
+                IntType::U16 => i128::from(bits as u16),
+                IntType::U32 => i128::from(bits as u32),
+                IntType::U64 => i128::from(bits),
+                IntType::S8 => i128::from(bits as i8),
+                IntType::S16 => i128::from(bits as i16),
+                IntType::S32 => i128::from(bits as i32),
+                IntType::S64 => i128::from(bits as i64),
+            },
+        }
+    }
+
+    fn to_const(self, value: i128) -> Const<'static> {
+        match self {
+            IntType::U8 => Const::U8(value as u8, UIntStyle::Decimal),
+            IntType::U16 => Const::U16(value as u16, UIntStyle::Decimal),
+            IntType::U32 => Const::U32(value as u32, UIntStyle::Decimal),
+            IntType::U64 => Const::U64(value as u64, UIntStyle::Decimal),
+            IntType::S8 => Const::S8(value as i8),
+            IntType::S16 => Const::S16(value as i16),
+            IntType::S32 => Const::S32(value as i32),
+            IntType::S64 => Const::S64(value as i64),
+        }
+    }
+
+    fn const_to_int(self, r#const: &Const<'_>) -> Option<i128> {
+        match (self, r#const) {
+            (IntType::U8, Const::U8(value, _)) => Some((*value).into()),
+            (IntType::U16, Const::U16(value, _)) => Some((*value).into()),
+            (IntType::U32, Const::U32(value, _)) => Some((*value).into()),
+            (IntType::U64, Const::U64(value, _)) => Some((*value).into()),
+            (IntType::S8, Const::S8(value)) => Some((*value).into()),
+            (IntType::S16, Const::S16(value)) => Some((*value).into()),
+            (IntType::S32, Const::S32(value)) => Some((*value).into()),
+            (IntType::S64, Const::S64(value)) => Some((*value).into()),
+            (_, _) => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+impl Comparison {
+    fn from_prim(prim: Prim) -> Option<(IntType, Comparison)> {
+        let int_type = match prim {
+            Prim::U8Eq | Prim::U8Neq | Prim::U8Lt | Prim::U8Lte | Prim::U8Gt | Prim::U8Gte => {
+                IntType::U8
+            }
+            Prim::U16Eq
+            | Prim::U16Neq
+            | Prim::U16Lt
+            | Prim::U16Lte
+            | Prim::U16Gt
+            | Prim::U16Gte => IntType::U16,
+            Prim::U32Eq
+            | Prim::U32Neq
+            | Prim::U32Lt
+            | Prim::U32Lte
+            | Prim::U32Gt
+            | Prim::U32Gte => IntType::U32,
+            Prim::U64Eq
+            | Prim::U64Neq
+            | Prim::U64Lt
+            | Prim::U64Lte
+            | Prim::U64Gt
+            | Prim::U64Gte => IntType::U64,
+            Prim::S8Eq | Prim::S8Neq | Prim::S8Lt | Prim::S8Lte | Prim::S8Gt | Prim::S8Gte => {
+                IntType::S8
+            }
+            Prim::S16Eq
+            | Prim::S16Neq
+            | Prim::S16Lt
+            | Prim::S16Lte
+            | Prim::S16Gt
+            | Prim::S16Gte => IntType::S16,
+            Prim::S32Eq
+            | Prim::S32Neq
+            | Prim::S32Lt
+            | Prim::S32Lte
+            | Prim::S32Gt
+            | Prim::S32Gte => IntType::S32,
+            Prim::S64Eq
+            | Prim::S64Neq
+            | Prim::S64Lt
+            | Prim::S64Lte
+            | Prim::S64Gt
+            | Prim::S64Gte => IntType::S64,
+            _ => return None,
+        };
+        let comparison = match prim {
+            Prim::U8Eq | Prim::U16Eq | Prim::U32Eq | Prim::U64Eq => Comparison::Eq,
+            Prim::S8Eq | Prim::S16Eq | Prim::S32Eq | Prim::S64Eq => Comparison::Eq,
+            Prim::U8Neq | Prim::U16Neq | Prim::U32Neq | Prim::U64Neq => Comparison::Neq,
+            Prim::S8Neq | Prim::S16Neq | Prim::S32Neq | Prim::S64Neq => Comparison::Neq,
+            Prim::U8Lt | Prim::U16Lt | Prim::U32Lt | Prim::U64Lt => Comparison::Lt,
+            Prim::S8Lt | Prim::S16Lt | Prim::S32Lt | Prim::S64Lt => Comparison::Lt,
+            Prim::U8Lte | Prim::U16Lte | Prim::U32Lte | Prim::U64Lte => Comparison::Lte,
+            Prim::S8Lte | Prim::S16Lte | Prim::S32Lte | Prim::S64Lte => Comparison::Lte,
+            Prim::U8Gt | Prim::U16Gt | Prim::U32Gt | Prim::U64Gt => Comparison::Gt,
+            Prim::S8Gt | Prim::S16Gt | Prim::S32Gt | Prim::S64Gt => Comparison::Gt,
+            _ => Comparison::Gte,
+        };
+
+        Some((int_type, comparison))
+    }
+
+    /// The comparison that holds when this comparison does not.
+    fn negate(self) -> Comparison {
+        match self {
+            Comparison::Eq => Comparison::Neq,
+            Comparison::Neq => Comparison::Eq,
+            Comparison::Lt => Comparison::Gte,
+            Comparison::Lte => Comparison::Gt,
+            Comparison::Gt => Comparison::Lte,
+            Comparison::Gte => Comparison::Lt,
+        }
+    }
+
+    /// The comparison that holds when the operands are swapped.
+    fn flip(self) -> Comparison {
+        match self {
+            Comparison::Eq | Comparison::Neq => self,
+            Comparison::Lt => Comparison::Gt,
+            Comparison::Lte => Comparison::Gte,
+            Comparison::Gt => Comparison::Lt,
+            Comparison::Gte => Comparison::Lte,
+        }
+    }
+}
+
+/// A range of integers that satisfy a predicate.
+struct IntRange {
+    int_type: IntType,
+    min: i128,
+    max: i128,
+    excluded: Vec<i128>,
+}
+
+impl IntRange {
+    fn new(int_type: IntType) -> IntRange {
+        let (min, max) = int_type.bounds();
+        IntRange {
+            int_type,
+            min,
+            max,
+            excluded: Vec::new(),
+        }
+    }
+
+    /// Narrow the range to the values `x` where `x <comparison> value`.
+    fn constrain(&mut self, comparison: Comparison, value: i128) {
+        match comparison {
+            Comparison::Eq => {
+                self.min = std::cmp::max(self.min, value);
+                self.max = std::cmp::min(self.max, value);
+            }
+            Comparison::Neq => self.excluded.push(value),
+            Comparison::Lt => self.max = std::cmp::min(self.max, value - 1),
+            Comparison::Lte => self.max = std::cmp::min(self.max, value),
+            Comparison::Gt => self.min = std::cmp::max(self.min, value + 1),
+            Comparison::Gte => self.min = std::cmp::max(self.min, value),
+        }
+    }
+
+    /// Choose a random value in the range, favouring its bounds.
+    fn random(&self, rng: &mut Rng) -> i128 {
+        let span = (self.max - self.min) as u128;
+        let mut value = match rng.below(4) {
+            0 => self.min,
+            1 => self.max,
+            _ if span >= u128::from(u64::MAX) => self.min + i128::from(rng.next_u64()),
+            _ => self.min + i128::from(rng.below(span as u64 + 1)),
+        };
+
+        // Step past any excluded values, wrapping around within the range
+        for _ in 0..self.excluded.len() {
+            if !self.excluded.contains(&value) {
+                break;
+            }
+            value = if value >= self.max {
+                self.min
+            } else {
+                value + 1
+            };
+        }
+
+        value
+    }
+}
+
+/// Find the integers that the variable `var` can be set to for `value`, the
+/// result of applying a predicate to the variable, to evaluate to `expected`.
+/// Returns `None` if the predicate is not made up of comparisons between the
+/// variable and constants, or if it cannot be satisfied.
+fn solve(int_type: IntType, var: Level, value: &ArcValue<'_>, expected: bool) -> Option<IntRange> {
+    let mut range = IntRange::new(int_type);
+    (constrain(&mut range, var, value, expected) && range.min <= range.max).then_some(range)
+}
+
+/// Find a constant that the variable `var` can be set to for `value`, the
+/// result of applying a predicate to the variable, to evaluate to `expected`,
+/// for predicates that compare non-integer values for equality.
+fn solve_equal<'arena>(
+    var: Level,
+    value: &ArcValue<'arena>,
+    expected: bool,
+) -> Option<Const<'arena>> {
+    use crate::core::semantics::Elim::FunApp;
+
+    let is_var = |value: &ArcValue<'_>| matches!(value.as_ref(), Value::Stuck(Head::LocalVar(level), spine) if *level == var && spine.is_empty());
+    let equal_const =
+        |lhs: &ArcValue<'arena>, rhs: &ArcValue<'arena>| match (lhs.as_ref(), rhs.as_ref()) {
+            (_, Value::ConstLit(r#const)) if is_var(lhs) => Some(*r#const),
+            (Value::ConstLit(r#const), _) if is_var(rhs) => Some(*r#const),
+            (_, _) => None,
+        };
+
+    match value.match_prim_spine()? {
+        (Prim::BoolNot, [FunApp(_, value)]) => solve_equal(var, value, !expected),
+        (Prim::BoolAnd, [FunApp(_, lhs), FunApp(_, rhs)]) if expected => {
+            solve_equal(var, lhs, expected).or_else(|| solve_equal(var, rhs, expected))
+        }
+        (Prim::BoolEq | Prim::StringEq, [FunApp(_, lhs), FunApp(_, rhs)]) if expected => {
+            equal_const(lhs, rhs)
+        }
+        (Prim::BoolNeq | Prim::StringNeq, [FunApp(_, lhs), FunApp(_, rhs)]) if !expected => {
+            equal_const(lhs, rhs)
+        }
+        (_, _) => None,
+    }
+}
+
+fn constrain(range: &mut IntRange, var: Level, value: &ArcValue<'_>, expected: bool) -> bool {
+    use crate::core::semantics::Elim::FunApp;
+
+    let is_var = |value: &ArcValue<'_>| matches!(value.as_ref(), Value::Stuck(Head::LocalVar(level), spine) if *level == var && spine.is_empty());
+    let int_const = |value: &ArcValue<'_>| match value.as_ref() {
+        Value::ConstLit(r#const) => range.int_type.const_to_int(r#const),
+        _ => None,
+    };
+
+    let (prim, spine) = match value.as_ref() {
+        Value::ConstLit(Const::Bool(value)) => return *value == expected,
+        Value::Stuck(Head::Prim(prim), spine) => (*prim, &spine[..]),
+        _ => return false,
+    };
+
+    match (prim, spine) {
+        (Prim::BoolNot, [FunApp(_, value)]) => constrain(range, var, value, !expected),
+        (Prim::BoolAnd, [FunApp(_, lhs), FunApp(_, rhs)]) if expected => {
+            constrain(range, var, lhs, expected) && constrain(range, var, rhs, expected)
+        }
+        (Prim::BoolOr, [FunApp(_, lhs), FunApp(_, rhs)]) if !expected => {
+            constrain(range, var, lhs, expected) && constrain(range, var, rhs, expected)
+        }
+        (_, [FunApp(_, lhs), FunApp(_, rhs)]) => {
+            let comparison = match Comparison::from_prim(prim) {
+                Some((int_type, comparison)) if int_type == range.int_type => comparison,
+                Some(_) | None => return false,
+            };
+            let comparison = if expected {
+                comparison
+            } else {
+                comparison.negate()
+            };
+
+            match (is_var(lhs), int_const(lhs), is_var(rhs), int_const(rhs)) {
+                (true, _, _, Some(value)) => range.constrain(comparison, value),
+                (_, Some(value), true, _) => range.constrain(comparison.flip(), value),
+                (_, _, _, _) => return false,
+            }
+            true
+        }
+        (_, _) => false,
+    }
+}
+
+fn const_value<'arena>(span: Span, r#const: Const<'arena>) -> ArcValue<'arena> {
+    Spanned::new(span, Arc::new(Value::ConstLit(r#const)))
+}
+
+fn unit_value<'arena>(span: Span) -> ArcValue<'arena> {
+    Spanned::new(span, Arc::new(Value::RecordLit(&[], Vec::new())))
+}
+
+fn uint_value<'arena>(value: &ArcValue<'arena>) -> Result<u64, GenerateError<'arena>> {
+    match value.as_ref() {
+        Value::ConstLit(Const::U8(value, _)) => Ok(u64::from(*value)),
+        Value::ConstLit(Const::U16(value, _)) => Ok(u64::from(*value)),
+        Value::ConstLit(Const::U32(value, _)) => Ok(u64::from(*value)),
+        Value::ConstLit(Const::U64(value, _)) => Ok(*value),
+        _ => Err(GenerateError::InvalidValue(value.span())),
+    }
+}
+
+fn text_encoding<'arena>(value: &ArcValue<'arena>) -> Result<TextEncoding, GenerateError<'arena>> {
+    TextEncoding::from_value(value).ok_or_else(|| GenerateError::InvalidValue(value.span()))
+}
+
+/// A random string of up to `max_len` characters, made up of printable ASCII
+/// characters so that it can be encoded in any text encoding.
+fn random_string<'arena>(
+    scope: &'arena Scope<'arena>,
+    rng: &mut Rng,
+    max_len: u64,
+) -> Const<'arena> {
+    let len = rng.below(std::cmp::min(max_len, MAX_RANDOM_LEN) + 1);
+    let string = (0..len)
+        .map(|_| char::from(b' ' + rng.below(95) as u8))
+        .collect::<String>();
+
+    Const::string(scope, &string)
+}
+
+#[cfg(test)]
+mod tests {
+    use scoped_arena::Scope;
+
+    use super::*;
+    use crate::core::binary;
+    use crate::core::binary::tests::elab_format;
+
+    fn generate(format_source: &str, seed: u64) -> Vec<u8> {
+        let scope = Scope::new();
+        let format = elab_format(&scope, format_source);
+        let data = Context::new(&scope, seed)
+            .generate_entrypoint(&format)
+            .unwrap();
+        data
+    }
+
+    fn read<'arena>(
+        scope: &'arena Scope<'arena>,
+        format: &Term<'arena>,
+        data: &[u8],
+    ) -> ArcValue<'arena> {
+        let refs = (binary::Context::new(scope, Buffer::from(data)))
+            .read_entrypoint(format)
+            .unwrap();
+        refs[&0][0].expr.clone()
+    }
+
+    #[test]
+    fn generate_is_deterministic() {
+        let format = "{ len <- u8, data <- repeat_len8 len u16be, name <- string_nul ascii }";
+
+        assert_eq!(generate(format, 7), generate(format, 7));
+        assert!((0..8).any(|seed| generate(format, seed) != generate(format, 7)));
+    }
+
+    #[test]
+    fn generate_solves_conds() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{ magic <- u32be where u32_eq magic 0x4f54544f, version <- u16be where u16_lt version 3 }",
+        );
+
+        for seed in 0..16 {
+            let data = Context::new(&scope, seed)
+                .generate_entrypoint(&format)
+                .unwrap();
+            assert_eq!(data[..4], *b"OTTO");
+            assert!(u16::from_be_bytes([data[4], data[5]]) < 3);
+        }
+    }
+
+    #[test]
+    fn generate_repeat_until_and_while() {
+        let format = "{
+            a <- repeat_until u8 (fun x => u8_eq x 0),
+            b <- repeat_while u8 (fun x => u8_neq x 0),
+            end <- u8,
+        }";
+
+        for seed in 0..16 {
+            let data = generate(format, seed);
+            assert_eq!(data.iter().filter(|byte| **byte == 0).count(), 2);
+        }
+    }
+
+    #[test]
+    fn generate_links() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "{
+                start <- stream_pos,
+                offset <- u8 where u8_lt offset 32,
+                data <- link (pos_add_u8 start offset) u32be,
+                value <- deref data,
+            }",
+        );
+
+        for seed in 0..16 {
+            let data = Context::new(&scope, seed)
+                .generate_entrypoint(&format)
+                .unwrap();
+            let offset = usize::from(data[0]);
+            assert!(data.len() >= offset + 4);
+
+            match read(&scope, &format, &data).as_ref() {
+                Value::RecordLit(_, exprs) => match exprs[3].as_ref() {
+                    Value::ConstLit(Const::U32(value, _)) => {
+                        assert_eq!(value.to_be_bytes(), data[offset..][..4]);
+                    }
+                    _ => panic!("expected a u32"),
+                },
+                _ => panic!("expected a record"),
+            }
+        }
+    }
+
+    #[test]
+    fn generate_limits() {
+        let format = "{
+            len <- u8 where u8_lte len 8,
+            data <- limit8 len (repeat_until_end u16be),
+            exact <- limit_exact8 4 (repeat_len8 2 u16le),
+        }";
+
+        for seed in 0..16 {
+            let data = generate(format, seed);
+            // Limits do not advance the reader, so both are read from offset 1
+            assert_eq!(data.len(), 1 + std::cmp::max(usize::from(data[0]), 4));
+        }
+    }
+
+    #[test]
+    fn generate_retries_failed_values() {
+        let scope = Scope::new();
+        // Half of the records fail to generate, but are retried by the condition
+        let format = elab_format(
+            &scope,
+            "{
+                r <- {
+                    x <- u8,
+                    y <- match (u8_and x 1) { 0 => fail, _ => u8 },
+                } where u8_gte r.x 0,
+            }",
+        );
+
+        for seed in 0..16 {
+            let mut context = Context::new(&scope, seed);
+            context.set_max_attempts(1);
+            let data = context.generate_entrypoint(&format).unwrap();
+            assert_eq!(data[0] & 1, 1);
+        }
+    }
+
+    #[test]
+    fn generate_choice_reads_back() {
+        let scope = Scope::new();
+        let format = elab_format(
+            &scope,
+            "choice [
+                { tag <- u8 where u8_lt tag 128 },
+                { tag <- u8 },
+            ]",
+        );
+
+        let choice_index = |expr: &ArcValue<'_>| match expr.as_ref() {
+            Value::RecordLit(_, exprs) => match exprs[0].as_ref() {
+                Value::ConstLit(Const::U64(index, _)) => *index,
+                _ => panic!("expected an index"),
+            },
+            _ => panic!("expected a record"),
+        };
+
+        let mut indices = Vec::new();
+        for seed in 0..16 {
+            let mut context = Context::new(&scope, seed);
+            let format_value = context.eval_env().eval(&format);
+            let mut writer = BufferWriter::new();
+            let expr = context.generate_format(&mut writer, &format_value).unwrap();
+            let data = writer.finish();
+
+            // The alternative that was generated is the one that is read
+            let index = choice_index(&expr);
+            assert_eq!(index, choice_index(&read(&scope, &format, &data)));
+            indices.push(index);
+        }
+        assert!(indices.contains(&0) && indices.contains(&1));
+    }
+
+    #[test]
+    fn generate_too_large() {
+        let scope = Scope::new();
+        let format = elab_format(&scope, "repeat_len8 255 u32be");
+
+        let mut context = Context::new(&scope, 0);
+        context.set_max_len(16);
+        context.set_max_attempts(1);
+        assert!(matches!(
+            context.generate_entrypoint(&format),
+            Err(GenerateError::TooLarge(_)),
+        ));
+    }
+}
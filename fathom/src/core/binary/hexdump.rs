@@ -0,0 +1,347 @@
+//! Annotated hexdumps of binary data.
+//!
+//! Each byte of the dump is labelled with a key identifying the field that
+//! read it, and a legend mapping the keys to the paths of the fields is
+//! printed after the dump. When the writer supports colours each field is
+//! also given a colour, and bytes that were not read by any format are
+//! dimmed.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+use codespan_reporting::term::termcolor::{Color, ColorSpec, WriteColor};
+
+use crate::core::binary::{
+    Buffer, BufferError, Context, PathSegment, ProvenanceMap, ValuePath, ValueRange,
+};
+
+/// The number of bytes to display on each row of the dump.
+const ROW_LEN: usize = 16;
+
+/// The colours that are cycled through when colouring fields.
+const COLORS: [Color; 6] = [
+    Color::Cyan,
+    Color::Yellow,
+    Color::Magenta,
+    Color::Green,
+    Color::Blue,
+    Color::Red,
+];
+
+/// An error that occurred while writing a hexdump.
+#[derive(Debug)]
+pub enum HexdumpError {
+    /// The binary data could not be read.
+    Read(BufferError),
+    /// The dump could not be written.
+    Write(io::Error),
+}
+
+impl From<BufferError> for HexdumpError {
+    fn from(err: BufferError) -> HexdumpError {
+        HexdumpError::Read(err)
+    }
+}
+
+impl From<io::Error> for HexdumpError {
+    fn from(err: io::Error) -> HexdumpError {
+        HexdumpError::Write(err)
+    }
+}
+
+impl fmt::Display for HexdumpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HexdumpError::Read(err) => err.fmt(f),
+            HexdumpError::Write(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for HexdumpError {}
+
+/// A run of contiguous binary data to dump.
+struct Section<'data> {
+    /// The name of the section, if it is a derived buffer
+    name: Option<String>,
+    buffer: Buffer<'data>,
+    /// The index of the field that read each byte in the section, stored as
+    /// disjoint ranges of offsets from the start of the section, keyed by the
+    /// start of each range
+    fields: BTreeMap<usize, (usize, usize)>,
+}
+
+impl<'data> Section<'data> {
+    fn new(name: Option<String>, buffer: Buffer<'data>) -> Section<'data> {
+        Section {
+            name,
+            buffer,
+            fields: BTreeMap::new(),
+        }
+    }
+
+    fn start_pos(&self) -> usize {
+        self.buffer.start_offset()
+    }
+
+    fn len(&self) -> usize {
+        self.buffer.remaining_len()
+    }
+
+    fn contains(&self, pos: usize) -> bool {
+        self.start_pos() <= pos && pos < self.start_pos() + self.len()
+    }
+
+    /// Label the bytes in a range of offsets with a field, replacing the
+    /// fields that they were previously labelled with.
+    fn set_field(&mut self, start: usize, end: usize, field: usize) {
+        // Split the range that overlaps the start, if any
+        if let Some((&prev_start, &(prev_end, prev_field))) = self.fields.range(..start).next_back()
+        {
+            if prev_end > start {
+                self.fields.insert(prev_start, (start, prev_field));
+                if prev_end > end {
+                    self.fields.insert(end, (prev_end, prev_field));
+                }
+            }
+        }
+        // Remove the ranges that start within the range, keeping the part of
+        // the last one that extends past the end
+        let overlapping = (self.fields.range(start..end))
+            .map(|(start, _)| *start)
+            .collect::<Vec<_>>();
+        for overlapping_start in overlapping {
+            let (overlapping_end, overlapping_field) =
+                self.fields.remove(&overlapping_start).unwrap();
+            if overlapping_end > end {
+                self.fields
+                    .insert(end, (overlapping_end, overlapping_field));
+            }
+        }
+        self.fields.insert(start, (end, field));
+    }
+
+    /// The field that read the byte at an offset, if any.
+    fn field(&self, offset: usize) -> Option<usize> {
+        let (_, (end, field)) = self.fields.range(..=offset).next_back()?;
+        (offset < *end).then_some(*field)
+    }
+}
+
+/// A field that was read from the binary data.
+struct Field {
+    label: String,
+    range: ValueRange,
+    /// The key used to identify the field in the dump, if it read any bytes
+    /// that were not read by a more specific field
+    key: Option<usize>,
+}
+
+/// Write an annotated hexdump of the initial binary data, along with the data
+/// decompressed while reading it. Provenance must have been recorded while
+/// reading the data, using [`Context::set_record_provenance`].
+///
+/// The initial data is read from the buffer a row at a time, so streamed
+/// input is not loaded into memory all at once.
+pub fn write_hexdump(
+    writer: &mut dyn WriteColor,
+    context: &Context<'_, '_>,
+    initial_buffer: Buffer<'_>,
+) -> Result<(), HexdumpError> {
+    let initial_start_pos = initial_buffer.start_offset();
+    let mut sections = vec![Section::new(None, initial_buffer)];
+    for derived in context.derived_buffers() {
+        let name = format!(
+            "{}[{}]",
+            derived.compression.prim().name(),
+            context.pos_to_string(derived.source_pos),
+        );
+        let buffer = Buffer::new(derived.start_pos, derived.data());
+        sections.push(Section::new(Some(name), buffer));
+    }
+
+    let empty_provenance = ProvenanceMap::default();
+    let provenance = context.provenance().unwrap_or(&empty_provenance);
+    let mut fields = Vec::new();
+
+    // Values are visited before the values contained within them, so bytes
+    // end up labelled with the most specific field that read them.
+    for (path, range) in provenance.iter() {
+        if !matches!(path.segments.last(), None | Some(PathSegment::Field(_))) {
+            continue;
+        }
+        let section = match sections.iter_mut().find(|s| s.contains(range.start)) {
+            Some(section) => section,
+            None => continue,
+        };
+
+        let start = range.start - section.start_pos();
+        let end = std::cmp::min(range.end - section.start_pos(), section.len());
+        if start < end {
+            section.set_field(start, end, fields.len());
+        }
+        fields.push(Field {
+            label: field_label(context, initial_start_pos, path),
+            range: *range,
+            key: None,
+        });
+    }
+
+    // Keys are assigned to fields in the order that they first label a byte
+    let mut next_key = 0;
+    for section in &sections {
+        if let Some(name) = &section.name {
+            writeln!(writer)?;
+            writeln!(writer, "{name}:")?;
+        }
+        let mut reader = section.buffer.reader();
+        for (row, row_start) in (0..section.len()).step_by(ROW_LEN).enumerate() {
+            let row_end = std::cmp::min(row_start + ROW_LEN, section.len());
+            let bytes = reader.read_byte_vec(row_end - row_start)?;
+            let keys = (row_start..row_end)
+                .map(|offset| {
+                    let field = &mut fields[section.field(offset)?];
+                    Some(*field.key.get_or_insert_with(|| {
+                        next_key += 1;
+                        next_key - 1
+                    }))
+                })
+                .collect::<Vec<_>>();
+            let offset = match section.name {
+                None => section.start_pos() + row * ROW_LEN,
+                Some(_) => row * ROW_LEN,
+            };
+            write_row(writer, offset, &bytes, &keys)?;
+        }
+    }
+
+    writeln!(writer)?;
+    writeln!(writer, "legend:")?;
+    let mut legend = fields
+        .iter()
+        .filter(|field| field.key.is_some())
+        .collect::<Vec<_>>();
+    legend.sort_by_key(|field| field.key);
+    for field in legend {
+        let key = field.key.unwrap();
+        write!(writer, "  ")?;
+        writer.set_color(&key_color(Some(key)))?;
+        write!(writer, "{:<2}", key_name(key))?;
+        writer.reset()?;
+        let range = format!(
+            "{}..{}",
+            context.pos_to_string(field.range.start),
+            context.pos_to_string(field.range.end),
+        );
+        writeln!(writer, "  {range:<12}  {}", field.label)?;
+    }
+    write!(writer, "  ")?;
+    writer.set_color(&key_color(None))?;
+    write!(writer, "..")?;
+    writer.reset()?;
+    writeln!(writer, "  unread")?;
+
+    Ok(writer.flush()?)
+}
+
+/// Write a row of the dump, followed by the keys of the fields that read each
+/// byte in the row.
+fn write_row(
+    writer: &mut dyn WriteColor,
+    offset: usize,
+    bytes: &[u8],
+    keys: &[Option<usize>],
+) -> io::Result<()> {
+    write!(writer, "{offset:08x}")?;
+    for (index, (byte, key)) in Iterator::zip(bytes.iter(), keys.iter()).enumerate() {
+        write!(writer, "{}", if index % 8 == 0 { "  " } else { " " })?;
+        writer.set_color(&key_color(*key))?;
+        write!(writer, "{byte:02x}")?;
+        writer.reset()?;
+    }
+    // Align the ASCII column on short rows
+    for index in bytes.len()..ROW_LEN {
+        write!(writer, "{}", if index % 8 == 0 { "    " } else { "   " })?;
+    }
+    write!(writer, "  |")?;
+    for (byte, key) in Iterator::zip(bytes.iter(), keys.iter()) {
+        writer.set_color(&key_color(*key))?;
+        match byte {
+            0x20..=0x7e => write!(writer, "{}", char::from(*byte))?,
+            _ => write!(writer, ".")?,
+        }
+        writer.reset()?;
+    }
+    writeln!(writer, "|")?;
+
+    write!(writer, "        ")?;
+    for (index, key) in keys.iter().enumerate() {
+        write!(writer, "{}", if index % 8 == 0 { "  " } else { " " })?;
+        writer.set_color(&key_color(*key))?;
+        match key {
+            // Label the start of each field, and the start of each row
+            Some(key) if index == 0 || keys[index - 1] != Some(*key) => {
+                write!(writer, "{:<2}", key_name(*key))?
+            }
+            Some(_) => write!(writer, "--")?,
+            None => write!(writer, "..")?,
+        }
+        writer.reset()?;
+    }
+    writeln!(writer)
+}
+
+/// The label used for a field in the legend. Fields in the entrypoint are
+/// labelled by their path, and fields in other references are prefixed with
+/// the position of the reference.
+fn field_label(context: &Context<'_, '_>, initial_start_pos: usize, path: &ValuePath) -> String {
+    match (
+        path.ref_pos == initial_start_pos && path.ref_index == 0,
+        &path.segments[..],
+    ) {
+        (true, []) => "<entrypoint>".to_owned(),
+        (true, _) => path.to_string(),
+        (false, []) => format!("@{}", context.pos_to_string(path.ref_pos)),
+        (false, _) => format!("@{}.{path}", context.pos_to_string(path.ref_pos)),
+    }
+}
+
+/// The name of a key, using one or two letters. Keys are reused once the
+/// names run out.
+fn key_name(key: usize) -> String {
+    let letter = |index: usize| char::from(b'a' + (index % 26) as u8);
+    match key % (26 + 26 * 26) {
+        key @ 0..=25 => letter(key).to_string(),
+        key => format!("{}{}", letter((key - 26) / 26), letter(key - 26)),
+    }
+}
+
+fn key_color(key: Option<usize>) -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    match key {
+        Some(key) => spec.set_fg(Some(COLORS[key % COLORS.len()])),
+        None => spec.set_dimmed(true),
+    };
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_field_splits_ranges() {
+        let mut section = Section::new(None, Buffer::new(0, &[0; 8]));
+        section.set_field(0, 8, 0);
+        section.set_field(2, 4, 1);
+        section.set_field(3, 6, 2);
+
+        let fields = (0..8)
+            .map(|offset| section.field(offset))
+            .collect::<Vec<_>>();
+        let expected = [0, 0, 1, 2, 2, 2, 0, 0].map(Some);
+        assert_eq!(fields, expected);
+        assert_eq!(section.field(8), None);
+    }
+}
@@ -0,0 +1,129 @@
+//! Conversion of binary data that was read to JSON.
+//!
+//! Records are converted to objects with their fields in order, and arrays
+//! are converted to arrays. Integers are converted to numbers, unless they
+//! are too large to be represented exactly by a double precision float, in
+//! which case they are converted to strings. Positions and references are
+//! converted to objects of the form `{ "pos": 12 }` and `{ "ref": 12 }`, and
+//! the references that were read are keyed by their position. Positions in
+//! decompressed data are converted to strings such as `"zlib[4]+12"`, as in
+//! the output of `fathom data`.
+
+use std::collections::HashMap;
+
+use scoped_arena::Scope;
+use serde_json::{Map, Number, Value as JsonValue};
+
+use crate::core::binary::{Context, ParsedRef};
+use crate::core::semantics::{Elim, Head, Value};
+use crate::core::{Const, Pos, Prim};
+
+/// The largest integer that can be represented exactly by JSON numbers, when
+/// they are parsed as double precision floats.
+const MAX_SAFE_INTEGER: u64 = 1 << 53;
+
+/// Convert the references read with [`Context::read_entrypoint`] to a JSON
+/// object, mapping the position of each reference to an array of the values
+/// that were read at that position. Data that was skipped over while reading
+/// is omitted.
+pub fn refs_to_json<'arena>(
+    scope: &'arena Scope<'arena>,
+    context: &Context<'arena, '_>,
+    refs: &HashMap<usize, Vec<ParsedRef<'arena>>>,
+) -> JsonValue {
+    let mut positions = refs.keys().copied().collect::<Vec<_>>();
+    positions.sort_unstable();
+
+    let mut object = Map::with_capacity(positions.len());
+    for pos in positions {
+        let exprs = (refs[&pos].iter())
+            .map(|parsed_ref| {
+                let expr = context.hide_skipped(scope, &parsed_ref.format, &parsed_ref.expr);
+                value_to_json(context, &expr)
+            })
+            .collect();
+        object.insert(context.pos_to_string(pos), JsonValue::Array(exprs));
+    }
+
+    JsonValue::Object(object)
+}
+
+/// Convert a value that was read to JSON. Values that could not be read when
+/// recovering from errors are converted to `null`.
+pub fn value_to_json(context: &Context<'_, '_>, value: &Value<'_>) -> JsonValue {
+    match value {
+        Value::RecordLit(labels, exprs) => {
+            let fields = Iterator::zip(labels.iter(), exprs.iter())
+                .map(|(label, expr)| (label.resolve().to_owned(), value_to_json(context, expr)));
+            JsonValue::Object(fields.collect())
+        }
+        Value::ArrayLit(exprs) => JsonValue::Array(
+            exprs
+                .iter()
+                .map(|expr| value_to_json(context, expr))
+                .collect(),
+        ),
+        Value::ConstLit(r#const) => const_to_json(context, r#const),
+        Value::Stuck(Head::Prim(Prim::OptionSome), spine) => match &spine[..] {
+            [_, Elim::FunApp(_, expr)] => value_to_json(context, expr),
+            _ => JsonValue::Null,
+        },
+        Value::Stuck(Head::Prim(Prim::OptionNone), _) => JsonValue::Null,
+        Value::Stuck(..)
+        | Value::Universe
+        | Value::FunType(..)
+        | Value::FunLit(..)
+        | Value::RecordType(..)
+        | Value::FormatRecord(..)
+        | Value::FormatCond(..)
+        | Value::FormatOverlap(..) => JsonValue::Null,
+    }
+}
+
+fn const_to_json(context: &Context<'_, '_>, r#const: &Const) -> JsonValue {
+    match *r#const {
+        Const::Bool(value) => JsonValue::Bool(value),
+        Const::U8(value, _) => JsonValue::from(value),
+        Const::U16(value, _) => JsonValue::from(value),
+        Const::U32(value, _) => JsonValue::from(value),
+        Const::U64(value, _) => match value <= MAX_SAFE_INTEGER {
+            true => JsonValue::from(value),
+            false => JsonValue::String(value.to_string()),
+        },
+        Const::S8(value) => JsonValue::from(value),
+        Const::S16(value) => JsonValue::from(value),
+        Const::S32(value) => JsonValue::from(value),
+        Const::S64(value) => match value.unsigned_abs() <= MAX_SAFE_INTEGER {
+            true => JsonValue::from(value),
+            false => JsonValue::String(value.to_string()),
+        },
+        Const::F32(value) => float_to_json(f64::from(value)),
+        Const::F64(value) => float_to_json(value),
+        Const::Pos(pos) => tagged("pos", pos_to_json(context, pos)),
+        Const::Ref(pos, _) => tagged("ref", pos_to_json(context, pos)),
+        Const::String(value) => JsonValue::String(value.to_string()),
+    }
+}
+
+/// Convert a position to a number, or to a string if it is in decompressed
+/// data.
+fn pos_to_json(context: &Context<'_, '_>, pos: Pos) -> JsonValue {
+    match pos.buffer() {
+        0 => JsonValue::from(pos.offset()),
+        _ => JsonValue::String(context.pos_value_to_string(pos)),
+    }
+}
+
+/// Convert a float to a number, or to a string if it is not finite.
+fn float_to_json(value: f64) -> JsonValue {
+    match Number::from_f64(value) {
+        Some(number) => JsonValue::Number(number),
+        None => JsonValue::String(value.to_string()),
+    }
+}
+
+fn tagged(tag: &str, pos: JsonValue) -> JsonValue {
+    let mut object = Map::with_capacity(1);
+    object.insert(tag.to_owned(), pos);
+    JsonValue::Object(object)
+}
@@ -0,0 +1,251 @@
+//! Selection of values within binary data that was read.
+//!
+//! Values are selected using paths made up of field projections, like
+//! `header.num_tables`, and array indices, like `table_records[3]`. The
+//! wildcards `*` and `[*]` select every field of a record and every element
+//! of an array respectively. References encountered along a path are followed
+//! to the values that they refer to.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::core::binary::{fmt_segments, Context, ParsedRef, PathSegment, ValuePath};
+use crate::core::semantics::{ArcValue, Value};
+use crate::core::Const;
+use crate::symbol::Symbol;
+
+/// A step in a path used to select values.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum QuerySegment {
+    /// A field of a record, eg. `.magic`
+    Field(Symbol),
+    /// An element of an array, eg. `[3]`
+    Index(usize),
+    /// Every field of a record, ie. `.*`
+    AnyField,
+    /// Every element of an array, ie. `[*]`
+    AnyIndex,
+}
+
+/// A path used to select values, starting from the entrypoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Query {
+    pub segments: Vec<QuerySegment>,
+}
+
+impl Query {
+    /// Remove a leading field from the query if it matches `label`, allowing
+    /// paths to begin with the name of the entrypoint, eg. `main.font`.
+    pub fn strip_prefix(&self, label: Symbol) -> Option<Query> {
+        match self.segments.split_first() {
+            Some((QuerySegment::Field(first), segments)) if *first == label => Some(Query {
+                segments: segments.to_vec(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// An error encountered when parsing a query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryError {
+    /// The byte offset in the query where the error was encountered
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(src: &str) -> Result<Query, QueryError> {
+        let is_label_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let mut segments = Vec::new();
+        let mut offset = 0;
+
+        while offset < src.len() {
+            let rest = &src[offset..];
+            let error = |message| QueryError { offset, message };
+
+            if let Some(rest) = rest.strip_prefix('[') {
+                let len = rest.find(']').ok_or(error("unclosed `[`"))?;
+                segments.push(match &rest[..len] {
+                    "*" => QuerySegment::AnyIndex,
+                    index => {
+                        QuerySegment::Index(index.parse().map_err(|_| error("invalid index"))?)
+                    }
+                });
+                offset += len + 2;
+                continue;
+            }
+
+            // The first field of a query may omit the leading `.`
+            let label_start = match rest.strip_prefix('.') {
+                Some(_) => offset + 1,
+                None if offset == 0 => offset,
+                None => return Err(error("expected `.` or `[`")),
+            };
+            let label = &src[label_start..];
+            let len = match label.starts_with('*') {
+                true => 1,
+                false => label.find(|c| !is_label_char(c)).unwrap_or(label.len()),
+            };
+            segments.push(match &label[..len] {
+                "*" => QuerySegment::AnyField,
+                "" => return Err(error("expected a field label")),
+                label => QuerySegment::Field(Symbol::intern(label)),
+            });
+            offset = label_start + len;
+        }
+
+        Ok(Query { segments })
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, segment) in self.segments.iter().enumerate() {
+            let dot = if index == 0 { "" } else { "." };
+            match segment {
+                QuerySegment::Field(label) => write!(f, "{dot}{}", label.resolve())?,
+                QuerySegment::Index(index) => write!(f, "[{index}]")?,
+                QuerySegment::AnyField => write!(f, "{dot}*")?,
+                QuerySegment::AnyIndex => write!(f, "[*]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A value that was selected by a query.
+pub struct Selection<'arena> {
+    /// The fields and elements leading from the entrypoint to the value,
+    /// including those in references that were followed
+    pub segments: Vec<PathSegment>,
+    /// The location of the value in the references that were read
+    pub path: ValuePath,
+    /// The format that the value was read with
+    // Invariant: `format : Format`
+    pub format: ArcValue<'arena>,
+    /// The value that was selected
+    // Invariant: `expr : Repr format`
+    pub expr: ArcValue<'arena>,
+}
+
+impl<'arena> Selection<'arena> {
+    /// The path leading from the entrypoint to the value, eg. `a.b[3]`.
+    pub fn label(&self) -> String {
+        struct Label<'a>(&'a [PathSegment]);
+
+        impl fmt::Display for Label<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt_segments(f, self.0)
+            }
+        }
+
+        Label(&self.segments).to_string()
+    }
+}
+
+/// Select the values matching a query in the references read with
+/// [`Context::read_entrypoint`], starting from the entrypoint that was read at
+/// `entrypoint_pos`. Values are returned in the order that they appear in the
+/// data that was read.
+pub fn select<'arena>(
+    context: &Context<'arena, '_>,
+    refs: &HashMap<usize, Vec<ParsedRef<'arena>>>,
+    entrypoint_pos: usize,
+    query: &Query,
+) -> Vec<Selection<'arena>> {
+    let entrypoint = match refs.get(&entrypoint_pos).and_then(|r| r.first()) {
+        Some(entrypoint) => entrypoint,
+        None => return Vec::new(),
+    };
+
+    let mut selections = Vec::new();
+    let selection = Selection {
+        segments: Vec::new(),
+        path: ValuePath::new(entrypoint_pos, 0),
+        format: entrypoint.format.clone(),
+        expr: entrypoint.expr.clone(),
+    };
+    select_from(context, refs, selection, &query.segments, &mut selections);
+
+    selections
+}
+
+fn select_from<'arena>(
+    context: &Context<'arena, '_>,
+    refs: &HashMap<usize, Vec<ParsedRef<'arena>>>,
+    selection: Selection<'arena>,
+    query: &[QuerySegment],
+    selections: &mut Vec<Selection<'arena>>,
+) {
+    let (query_segment, query) = match query.split_first() {
+        Some(split) => split,
+        None => return selections.push(selection),
+    };
+
+    // Follow references to the values that they refer to
+    let selection = match follow_ref(context, refs, &selection) {
+        Some((path, parsed_ref)) => Selection {
+            segments: selection.segments,
+            path,
+            format: parsed_ref.format.clone(),
+            expr: parsed_ref.expr.clone(),
+        },
+        None => selection,
+    };
+
+    for child in context.value_children(&selection.format, &selection.expr) {
+        let is_match = match (query_segment, child.segment) {
+            (QuerySegment::Field(label), PathSegment::Field(child_label)) => *label == child_label,
+            (QuerySegment::Index(index), PathSegment::Index(child_index)) => *index == child_index,
+            (QuerySegment::AnyField, PathSegment::Field(_))
+            | (QuerySegment::AnyIndex, PathSegment::Index(_)) => true,
+            (_, _) => false,
+        };
+        if !is_match {
+            continue;
+        }
+
+        let mut segments = selection.segments.clone();
+        segments.push(child.segment);
+        let mut path = selection.path.clone();
+        path.segments.push(child.segment);
+
+        let child = Selection {
+            segments,
+            path,
+            format: child.format,
+            expr: child.expr,
+        };
+        select_from(context, refs, child, query, selections);
+    }
+}
+
+/// Look up the reference that a selected value refers to, if it is a
+/// reference that was read.
+fn follow_ref<'a, 'arena>(
+    context: &Context<'arena, '_>,
+    refs: &'a HashMap<usize, Vec<ParsedRef<'arena>>>,
+    selection: &Selection<'arena>,
+) -> Option<(ValuePath, &'a ParsedRef<'arena>)> {
+    match selection.expr.as_ref() {
+        Value::ConstLit(Const::Ref(pos, index)) => {
+            let pos = context.pos_to_offset(*pos)?;
+            let index = *index as usize;
+            let parsed_ref = refs.get(&pos)?.get(index)?;
+            Some((ValuePath::new(pos, index), parsed_ref))
+        }
+        _ => None,
+    }
+}
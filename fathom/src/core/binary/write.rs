@@ -0,0 +1,1374 @@
+//! Serialisation of parsed data back into binary data.
+//!
+//! This is the inverse of [reading][super::Context]: given a format and a
+//! value of type `Repr format`, we produce the bytes that the format would
+//! have parsed that value from.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use super::{BitOrder, Compression, ParsedRef, ProvenanceMap, TextEncoding, ValuePath};
+use crate::core::semantics::{self, ArcValue, Elim, Head, Value};
+use crate::core::{Const, Item, Module, Plicity, Pos, Prim, Term};
+use crate::env::{EnvLen, SharedEnv, UniqueEnv};
+use crate::source::Span;
+
+#[derive(Clone, Debug)]
+pub enum WriteError<'arena> {
+    InvalidFormat(Span),
+    InvalidValue(Span),
+    UnwrappedNone(Span),
+    WriteFailFormat(Span),
+    CondFailure(Span, ArcValue<'arena>),
+    LenMismatch {
+        span: Span,
+        expected: u64,
+        found: usize,
+    },
+    LimitExceeded {
+        span: Span,
+        limit: usize,
+        len: usize,
+    },
+    LimitNotFilled {
+        span: Span,
+        limit: usize,
+        len: usize,
+    },
+    UnknownRef(Span, usize),
+    InvalidBitCount(Span, u8),
+    UnalignedWrite(Span),
+    UnencodableString(Span, TextEncoding),
+    CompressedFormat(Span, Compression),
+    MapFormat(Span),
+    PositionOverflow(Span),
+}
+
+impl<'arena> fmt::Display for WriteError<'arena> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::InvalidFormat(_) => f.write_str("invalid format"),
+            WriteError::InvalidValue(_) => f.write_str("invalid value"),
+            WriteError::UnwrappedNone(_) => f.write_str("unwrapped none"),
+            WriteError::WriteFailFormat(_) => f.write_str("wrote a fail format"),
+            WriteError::CondFailure(_, _) => f.write_str("conditional format failed"),
+            WriteError::LenMismatch { .. } => f.write_str("array length mismatch"),
+            WriteError::LimitExceeded { .. } => f.write_str("limit exceeded"),
+            WriteError::LimitNotFilled { .. } => f.write_str("exact limit not filled"),
+            WriteError::UnknownRef(_, _) => f.write_str("unknown reference"),
+            WriteError::InvalidBitCount(_, _) => f.write_str("invalid bit count"),
+            WriteError::UnalignedWrite(_) => {
+                f.write_str("attempt to write bytes part way through a byte")
+            }
+            WriteError::UnencodableString(_, encoding) => {
+                write!(f, "string cannot be encoded as {encoding}")
+            }
+            WriteError::CompressedFormat(_, compression) => {
+                write!(f, "cannot write {compression} compressed data")
+            }
+            WriteError::MapFormat(_) => f.write_str("cannot write mapped format"),
+            WriteError::PositionOverflow(_) => f.write_str("position overflow"),
+        }
+    }
+}
+
+impl<'arena> std::error::Error for WriteError<'arena> {}
+
+/// A growable buffer of binary data, with a cursor that can be moved to
+/// arbitrary offsets.
+///
+/// Writing past the end of the data extends it, filling any gaps with zeros.
+/// Like [`super::BufferReader`], individual bits can also be written.
+pub struct BufferWriter {
+    /// Offset from the start of the data.
+    offset: usize,
+    /// Number of bits that have been written to the byte at the current offset.
+    // Invariant: self.bit_offset < 8
+    bit_offset: u8,
+    /// The data written so far.
+    data: Vec<u8>,
+}
+
+impl BufferWriter {
+    /// Create a new, empty writer.
+    pub fn new() -> BufferWriter {
+        BufferWriter {
+            offset: 0,
+            bit_offset: 0,
+            data: Vec::new(),
+        }
+    }
+
+    /// The offset from the start of the data.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The number of bits that have been written to the current byte.
+    pub fn bit_offset(&self) -> u8 {
+        self.bit_offset
+    }
+
+    /// Returns `true` if the writer is not part way through writing a byte.
+    pub fn is_byte_aligned(&self) -> bool {
+        self.bit_offset == 0
+    }
+
+    /// Set the offset of the writer.
+    pub fn set_offset(&mut self, offset: usize) {
+        self.offset = offset;
+        self.bit_offset = 0;
+    }
+
+    /// Set the offset of the writer, part way through a byte.
+    pub fn set_bit_position(&mut self, offset: usize, bit_offset: u8) {
+        debug_assert!(bit_offset < 8);
+        self.offset = offset;
+        self.bit_offset = bit_offset;
+    }
+
+    /// The data that has been written so far.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Discard any data after the first `len` bytes.
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+    }
+
+    /// Extend the data with zeros so that it is at least `len` bytes long.
+    pub fn extend_to(&mut self, len: usize) {
+        if self.data.len() < len {
+            self.data.resize(len, 0);
+        }
+    }
+
+    /// Advance the writer by a number of bytes, leaving any data that was
+    /// already written in place and filling new data with zeros.
+    pub fn skip_bytes(&mut self, len: usize) {
+        debug_assert!(self.is_byte_aligned());
+
+        let end = self.offset + len;
+        self.extend_to(end);
+        self.offset = end;
+    }
+
+    /// Write some bytes and advance the writer.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        debug_assert!(self.is_byte_aligned());
+
+        let end = self.offset + bytes.len();
+        self.extend_to(end);
+        self.data[self.offset..end].copy_from_slice(bytes);
+        self.offset = end;
+    }
+
+    /// Write a single bit and advance the writer.
+    pub fn write_bit(&mut self, order: BitOrder, bit: bool) {
+        self.extend_to(self.offset + 1);
+
+        let shift = match order {
+            BitOrder::MsbFirst => 7 - self.bit_offset,
+            BitOrder::LsbFirst => self.bit_offset,
+        };
+        let mask = 1 << shift;
+        self.data[self.offset] = match bit {
+            true => self.data[self.offset] | mask,
+            false => self.data[self.offset] & !mask,
+        };
+
+        self.bit_offset += 1;
+        if self.bit_offset == 8 {
+            self.bit_offset = 0;
+            self.offset += 1;
+        }
+    }
+
+    /// Write the lower `count` bits of `value`, in the same order that
+    /// [`super::BufferReader::read_bits`] would read them.
+    pub fn write_bits(&mut self, order: BitOrder, count: u8, value: u64) {
+        debug_assert!(count <= 64);
+
+        for index in 0..count {
+            let shift = match order {
+                BitOrder::MsbFirst => count - 1 - index,
+                BitOrder::LsbFirst => index,
+            };
+            self.write_bit(order, (value >> shift) & 1 == 1);
+        }
+    }
+
+    /// Skip the remaining bits of a partially written byte, if any.
+    pub fn align_to_byte(&mut self) {
+        if !self.is_byte_aligned() {
+            self.bit_offset = 0;
+            self.offset += 1;
+        }
+    }
+
+    /// Return the data that has been written.
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl semantics::BinaryData for BufferWriter {
+    fn for_each_chunk(&self, pos: Pos, len: usize, on_chunk: &mut dyn FnMut(&[u8])) -> Option<()> {
+        // Only the initial buffer is written
+        if pos.buffer() != 0 {
+            return None;
+        }
+        let pos = pos.offset();
+        let end = usize::checked_add(pos, len).filter(|end| *end <= self.data.len())?;
+        on_chunk(&self.data[pos..end]);
+        Some(())
+    }
+}
+
+impl Default for BufferWriter {
+    fn default() -> BufferWriter {
+        BufferWriter::new()
+    }
+}
+
+/// A reference that needs to be written once the current format is finished.
+struct PendingRef<'arena> {
+    span: Span,
+    pos: usize,
+    format: ArcValue<'arena>,
+    /// The expression to write, if it is known. Expressions for links are
+    /// looked up in the cached references.
+    expr: Option<ArcValue<'arena>>,
+}
+
+pub struct Context<'arena> {
+    item_exprs: UniqueEnv<ArcValue<'arena>>,
+    local_exprs: SharedEnv<ArcValue<'arena>>,
+    cached_refs: HashMap<usize, Vec<ParsedRef<'arena>>>,
+    pending_refs: Vec<PendingRef<'arena>>,
+    written_refs: Vec<(usize, ArcValue<'arena>)>,
+    /// The offset that alignment is relative to, which is the start of the
+    /// innermost limited format being written.
+    buffer_start: usize,
+}
+
+impl<'arena> Context<'arena> {
+    pub fn new() -> Context<'arena> {
+        Context {
+            item_exprs: UniqueEnv::new(),
+            local_exprs: SharedEnv::new(),
+            cached_refs: HashMap::new(),
+            pending_refs: Vec::new(),
+            written_refs: Vec::new(),
+            buffer_start: 0,
+        }
+    }
+
+    fn eval_env(&mut self) -> semantics::EvalEnv<'arena, '_> {
+        let elim_env = semantics::ElimEnv::new(&self.item_exprs, [][..].into());
+        semantics::EvalEnv::new(elim_env, &mut self.local_exprs)
+    }
+
+    fn elim_env(&self) -> semantics::ElimEnv<'arena, '_> {
+        semantics::ElimEnv::new(&self.item_exprs, [][..].into())
+    }
+
+    pub fn add_module(&mut self, module: &Module<'arena>) {
+        self.item_exprs.reserve(module.items.len());
+        for item in module.items {
+            match item {
+                Item::Def { expr, .. } => {
+                    let expr = self.eval_env().eval(expr);
+                    self.item_exprs.push(expr);
+                }
+            }
+        }
+    }
+
+    /// Supply the references that links will be written from, for example the
+    /// references returned by [`super::Context::read_entrypoint`].
+    pub fn set_refs(&mut self, refs: HashMap<usize, Vec<ParsedRef<'arena>>>) {
+        self.cached_refs = refs;
+    }
+
+    pub fn write_entrypoint(
+        mut self,
+        format: &Term<'arena>,
+        expr: &ArcValue<'arena>,
+    ) -> Result<Vec<u8>, WriteError<'arena>> {
+        let mut writer = BufferWriter::new();
+        let format = self.eval_env().eval(format);
+        self.write_format(&mut writer, &format, expr)?;
+
+        while let Some(pending_ref) = self.pending_refs.pop() {
+            self.write_pending_ref(&mut writer, pending_ref)?;
+        }
+
+        Ok(writer.finish())
+    }
+
+    pub fn write_format(
+        &mut self,
+        writer: &mut BufferWriter,
+        format: &ArcValue<'arena>,
+        expr: &ArcValue<'arena>,
+    ) -> Result<(), WriteError<'arena>> {
+        match format.as_ref() {
+            Value::Stuck(Head::Prim(prim), slice) => {
+                self.write_prim(writer, *prim, slice, format.span(), expr)
+            }
+            Value::FormatRecord(labels, formats) => {
+                let exprs = record_exprs(labels, expr)?;
+                let mut formats = formats.clone();
+
+                for expr in exprs {
+                    let (format, next_formats) = match self.elim_env().split_telescope(formats) {
+                        Some(split) => split,
+                        None => return Err(WriteError::InvalidValue(expr.span())),
+                    };
+                    self.write_format(writer, &format, expr)?;
+                    formats = next_formats(expr.clone());
+                }
+
+                Ok(())
+            }
+            Value::FormatCond(_label, format, cond) => {
+                // Write the data before checking the predicate, so that it can
+                // inspect the data that was written, for example with checksums
+                self.write_format(writer, format, expr)?;
+
+                let elim_env = self.elim_env().with_binary_data(&*writer);
+                let cond_res = elim_env.apply_closure(cond, expr.clone());
+
+                match cond_res.as_ref() {
+                    Value::ConstLit(Const::Bool(true)) => Ok(()),
+                    _ => Err(WriteError::CondFailure(cond.span(), expr.clone())),
+                }
+            }
+            Value::FormatOverlap(labels, formats) => {
+                let exprs = record_exprs(labels, expr)?;
+                let start_position = (writer.offset(), writer.bit_offset());
+                let mut max_position = start_position;
+                let mut formats = formats.clone();
+
+                for expr in exprs {
+                    let (format, next_formats) = match self.elim_env().split_telescope(formats) {
+                        Some(split) => split,
+                        None => return Err(WriteError::InvalidValue(expr.span())),
+                    };
+                    writer.set_bit_position(start_position.0, start_position.1);
+                    self.write_format(writer, &format, expr)?;
+                    formats = next_formats(expr.clone());
+
+                    max_position =
+                        std::cmp::max(max_position, (writer.offset(), writer.bit_offset()));
+                }
+
+                writer.set_bit_position(max_position.0, max_position.1);
+
+                Ok(())
+            }
+
+            Value::Stuck(Head::LocalVar(_), _)
+            | Value::Stuck(Head::MetaVar(_), _)
+            | Value::Universe
+            | Value::FunType(..)
+            | Value::FunLit(..)
+            | Value::RecordType(_, _)
+            | Value::RecordLit(_, _)
+            | Value::ArrayLit(_)
+            | Value::ConstLit(_) => Err(WriteError::InvalidFormat(format.span())),
+        }
+    }
+
+    #[rustfmt::skip]
+    fn write_prim(
+        &mut self,
+        writer: &mut BufferWriter,
+        prim: Prim,
+        slice: &[Elim<'arena>],
+        span: Span,
+        expr: &ArcValue<'arena>,
+    ) -> Result<(), WriteError<'arena>> {
+        use crate::core::semantics::Elim::FunApp;
+
+        match (prim, slice) {
+            (Prim::FormatU8, []) => write_const(writer, span, expr, const_u8, u8::to_be_bytes),
+            (Prim::FormatU16Be, []) => write_const(writer, span, expr, const_u16, u16::to_be_bytes),
+            (Prim::FormatU16Le, []) => write_const(writer, span, expr, const_u16, u16::to_le_bytes),
+            (Prim::FormatU32Be, []) => write_const(writer, span, expr, const_u32, u32::to_be_bytes),
+            (Prim::FormatU32Le, []) => write_const(writer, span, expr, const_u32, u32::to_le_bytes),
+            (Prim::FormatU64Be, []) => write_const(writer, span, expr, const_u64, u64::to_be_bytes),
+            (Prim::FormatU64Le, []) => write_const(writer, span, expr, const_u64, u64::to_le_bytes),
+            (Prim::FormatS8, []) => write_const(writer, span, expr, const_s8, i8::to_be_bytes),
+            (Prim::FormatS16Be, []) => write_const(writer, span, expr, const_s16, i16::to_be_bytes),
+            (Prim::FormatS16Le, []) => write_const(writer, span, expr, const_s16, i16::to_le_bytes),
+            (Prim::FormatS32Be, []) => write_const(writer, span, expr, const_s32, i32::to_be_bytes),
+            (Prim::FormatS32Le, []) => write_const(writer, span, expr, const_s32, i32::to_le_bytes),
+            (Prim::FormatS64Be, []) => write_const(writer, span, expr, const_s64, i64::to_be_bytes),
+            (Prim::FormatS64Le, []) => write_const(writer, span, expr, const_s64, i64::to_le_bytes),
+            (Prim::FormatF32Be, []) => write_const(writer, span, expr, const_f32, f32::to_be_bytes),
+            (Prim::FormatF32Le, []) => write_const(writer, span, expr, const_f32, f32::to_le_bytes),
+            (Prim::FormatF64Be, []) => write_const(writer, span, expr, const_f64, f64::to_be_bytes),
+            (Prim::FormatF64Le, []) => write_const(writer, span, expr, const_f64, f64::to_le_bytes),
+            (Prim::FormatBitMsb, []) => write_bit(writer, expr, BitOrder::MsbFirst),
+            (Prim::FormatBitLsb, []) => write_bit(writer, expr, BitOrder::LsbFirst),
+            (Prim::FormatBits8Msb, [FunApp(_, count)]) => write_bits(writer, span, count, expr, BitOrder::MsbFirst, 8),
+            (Prim::FormatBits8Lsb, [FunApp(_, count)]) => write_bits(writer, span, count, expr, BitOrder::LsbFirst, 8),
+            (Prim::FormatBits16Msb, [FunApp(_, count)]) => write_bits(writer, span, count, expr, BitOrder::MsbFirst, 16),
+            (Prim::FormatBits16Lsb, [FunApp(_, count)]) => write_bits(writer, span, count, expr, BitOrder::LsbFirst, 16),
+            (Prim::FormatBits32Msb, [FunApp(_, count)]) => write_bits(writer, span, count, expr, BitOrder::MsbFirst, 32),
+            (Prim::FormatBits32Lsb, [FunApp(_, count)]) => write_bits(writer, span, count, expr, BitOrder::LsbFirst, 32),
+            (Prim::FormatBits64Msb, [FunApp(_, count)]) => write_bits(writer, span, count, expr, BitOrder::MsbFirst, 64),
+            (Prim::FormatBits64Lsb, [FunApp(_, count)]) => write_bits(writer, span, count, expr, BitOrder::LsbFirst, 64),
+            (Prim::FormatByteAlign, []) => {
+                writer.align_to_byte();
+                Ok(())
+            }
+            (Prim::FormatUleb128, []) => write_varint(writer, span, expr, const_u64, encode_uleb128),
+            (Prim::FormatSleb128, []) => write_varint(writer, span, expr, const_s64, encode_sleb128),
+            (Prim::FormatVarintZigzag, []) => write_varint(writer, span, expr, const_s64, encode_varint_zigzag),
+            (Prim::FormatVlqBe, []) => write_varint(writer, span, expr, const_u64, encode_vlq_be),
+            (Prim::FormatStringNul, [FunApp(_, encoding)]) => write_string_nul(writer, span, encoding, expr),
+            (Prim::FormatStringPrefixed8, [FunApp(_, encoding)]) => write_string_prefixed(writer, span, encoding, expr, |len| Some(u8::try_from(len).ok()?.to_be_bytes().to_vec())),
+            (Prim::FormatStringPrefixed16Be, [FunApp(_, encoding)]) => write_string_prefixed(writer, span, encoding, expr, |len| Some(u16::try_from(len).ok()?.to_be_bytes().to_vec())),
+            (Prim::FormatStringPrefixed16Le, [FunApp(_, encoding)]) => write_string_prefixed(writer, span, encoding, expr, |len| Some(u16::try_from(len).ok()?.to_le_bytes().to_vec())),
+            (Prim::FormatStringPrefixed32Be, [FunApp(_, encoding)]) => write_string_prefixed(writer, span, encoding, expr, |len| Some(u32::try_from(len).ok()?.to_be_bytes().to_vec())),
+            (Prim::FormatStringPrefixed32Le, [FunApp(_, encoding)]) => write_string_prefixed(writer, span, encoding, expr, |len| Some(u32::try_from(len).ok()?.to_le_bytes().to_vec())),
+            (Prim::FormatStringFixed8, [FunApp(_, len), FunApp(_, encoding)]) => write_string_fixed(writer, span, len, encoding, expr),
+            (Prim::FormatStringFixed16, [FunApp(_, len), FunApp(_, encoding)]) => write_string_fixed(writer, span, len, encoding, expr),
+            (Prim::FormatStringFixed32, [FunApp(_, len), FunApp(_, encoding)]) => write_string_fixed(writer, span, len, encoding, expr),
+            (Prim::FormatStringFixed64, [FunApp(_, len), FunApp(_, encoding)]) => write_string_fixed(writer, span, len, encoding, expr),
+            (Prim::FormatRepeatLen8, [FunApp(_, len), FunApp(_, format)]) => self.write_repeat_len(writer, span, len, format, expr),
+            (Prim::FormatRepeatLen16, [FunApp(_, len), FunApp(_, format)]) => self.write_repeat_len(writer, span, len, format, expr),
+            (Prim::FormatRepeatLen32, [FunApp(_, len), FunApp(_, format)]) => self.write_repeat_len(writer, span, len, format, expr),
+            (Prim::FormatRepeatLen64, [FunApp(_, len), FunApp(_, format)]) => self.write_repeat_len(writer, span, len, format, expr),
+            (Prim::FormatRepeatUntilEnd, [FunApp(_, format)]) => self.write_array(writer, format, expr),
+            (Prim::FormatRepeatUntil, [FunApp(_, format), FunApp(_, pred)]) => self.write_repeat_pred(writer, format, pred, expr, true, |index, len| index + 1 == len),
+            (Prim::FormatRepeatWhile, [FunApp(_, format), FunApp(_, pred)]) => self.write_repeat_pred(writer, format, pred, expr, false, |_, _| true),
+            (Prim::FormatLimit8, [FunApp(_, limit), FunApp(_, format)]) => self.write_limit(writer, limit, format, expr, false),
+            (Prim::FormatLimit16, [FunApp(_, limit), FunApp(_, format)]) => self.write_limit(writer, limit, format, expr, false),
+            (Prim::FormatLimit32, [FunApp(_, limit), FunApp(_, format)]) => self.write_limit(writer, limit, format, expr, false),
+            (Prim::FormatLimit64, [FunApp(_, limit), FunApp(_, format)]) => self.write_limit(writer, limit, format, expr, false),
+            (Prim::FormatLimitExact8, [FunApp(_, limit), FunApp(_, format)]) => self.write_limit(writer, limit, format, expr, true),
+            (Prim::FormatLimitExact16, [FunApp(_, limit), FunApp(_, format)]) => self.write_limit(writer, limit, format, expr, true),
+            (Prim::FormatLimitExact32, [FunApp(_, limit), FunApp(_, format)]) => self.write_limit(writer, limit, format, expr, true),
+            (Prim::FormatLimitExact64, [FunApp(_, limit), FunApp(_, format)]) => self.write_limit(writer, limit, format, expr, true),
+            // The compressed data is not part of the representation, and
+            // recompressing the data is not guaranteed to reproduce it
+            (Prim::FormatDeflate, [_, _]) => Err(WriteError::CompressedFormat(span, Compression::Deflate)),
+            (Prim::FormatZlib, [_, _]) => Err(WriteError::CompressedFormat(span, Compression::Zlib)),
+            (Prim::FormatBrotli, [_, _]) => Err(WriteError::CompressedFormat(span, Compression::Brotli)),
+            (Prim::FormatAlign, [FunApp(_, align)]) => write_align(writer, span, self.buffer_start, align),
+            (Prim::FormatSkip, [FunApp(_, len)]) => write_skip(writer, span, len),
+            (Prim::FormatReserved, [FunApp(_, format), FunApp(_, expected)]) => self.write_format(writer, format, expected),
+            (Prim::FormatLink, [FunApp(_, _), FunApp(_, format)]) => self.write_link(span, format, expr),
+            (Prim::FormatDeref, [FunApp(_, format), FunApp(_, r#ref)]) => self.write_deref(format, r#ref, expr),
+            // The end of the written data is not known until it has all been
+            // written, so the end of input cannot be checked here
+            (Prim::FormatEndOfInput, []) => Ok(()),
+            (Prim::FormatChoice, [FunApp(_, formats)]) => self.write_choice(writer, formats, expr),
+            // The function applied to the data that was read cannot be inverted
+            (Prim::FormatMap, [_, _, _]) => Err(WriteError::MapFormat(span)),
+            (Prim::FormatStreamPos, []) => match expr.as_ref() {
+                Value::ConstLit(Const::Pos(_)) => Ok(()),
+                _ => Err(WriteError::InvalidValue(expr.span())),
+            },
+            (Prim::FormatSucceed, [_, FunApp(_, _)]) => Ok(()),
+            (Prim::FormatFail, []) => Err(WriteError::WriteFailFormat(span)),
+            (Prim::FormatUnwrap, [_, FunApp(_, option)]) => match option.match_prim_spine() {
+                Some((Prim::OptionSome, [_, FunApp(_, _)])) => Ok(()),
+                Some((Prim::OptionNone, [_])) => Err(WriteError::UnwrappedNone(span)),
+                _ => Err(WriteError::InvalidValue(span)),
+            },
+            _ => Err(WriteError::InvalidFormat(span)),
+        }
+    }
+
+    fn write_repeat_len(
+        &mut self,
+        writer: &mut BufferWriter,
+        span: Span,
+        len: &ArcValue<'arena>,
+        elem_format: &ArcValue<'arena>,
+        expr: &ArcValue<'arena>,
+    ) -> Result<(), WriteError<'arena>> {
+        let len = match len.as_ref() {
+            Value::ConstLit(Const::U8(len, _)) => u64::from(*len),
+            Value::ConstLit(Const::U16(len, _)) => u64::from(*len),
+            Value::ConstLit(Const::U32(len, _)) => u64::from(*len),
+            Value::ConstLit(Const::U64(len, _)) => *len,
+            _ => return Err(WriteError::InvalidValue(len.span())),
+        };
+
+        match expr.as_ref() {
+            Value::ArrayLit(elem_exprs) if u64::try_from(elem_exprs.len()) == Ok(len) => {
+                self.write_array(writer, elem_format, expr)
+            }
+            Value::ArrayLit(elem_exprs) => Err(WriteError::LenMismatch {
+                span,
+                expected: len,
+                found: elem_exprs.len(),
+            }),
+            _ => Err(WriteError::InvalidValue(expr.span())),
+        }
+    }
+
+    fn write_array(
+        &mut self,
+        writer: &mut BufferWriter,
+        elem_format: &ArcValue<'arena>,
+        expr: &ArcValue<'arena>,
+    ) -> Result<(), WriteError<'arena>> {
+        match expr.as_ref() {
+            Value::ArrayLit(elem_exprs) => (elem_exprs.iter())
+                .try_for_each(|elem_expr| self.write_format(writer, elem_format, elem_expr)),
+            _ => Err(WriteError::InvalidValue(expr.span())),
+        }
+    }
+
+    /// Write the elements of a predicate-driven repeat format, checking that
+    /// the predicate on each element matches `expected_pred`, given the index
+    /// of the element and the length of the array. This ensures that the array
+    /// would be read back with the same number of elements. Arrays that end
+    /// with a terminating element must contain at least that element.
+    fn write_repeat_pred(
+        &mut self,
+        writer: &mut BufferWriter,
+        elem_format: &ArcValue<'arena>,
+        pred: &ArcValue<'arena>,
+        expr: &ArcValue<'arena>,
+        terminated: bool,
+        expected_pred: fn(usize, usize) -> bool,
+    ) -> Result<(), WriteError<'arena>> {
+        let elem_exprs = match expr.as_ref() {
+            Value::ArrayLit(elem_exprs) if !(terminated && elem_exprs.is_empty()) => elem_exprs,
+            _ => return Err(WriteError::InvalidValue(expr.span())),
+        };
+
+        for (index, elem_expr) in elem_exprs.iter().enumerate() {
+            self.write_format(writer, elem_format, elem_expr)?;
+
+            let elim_env = self.elim_env().with_binary_data(&*writer);
+            let result = elim_env.fun_app(Plicity::Explicit, pred.clone(), elem_expr.clone());
+            match result.as_ref() {
+                Value::ConstLit(Const::Bool(result))
+                    if *result == expected_pred(index, elem_exprs.len()) => {}
+                _ => return Err(WriteError::CondFailure(pred.span(), elem_expr.clone())),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_limit(
+        &mut self,
+        writer: &mut BufferWriter,
+        len: &ArcValue<'arena>,
+        elem_format: &ArcValue<'arena>,
+        expr: &ArcValue<'arena>,
+        exact: bool,
+    ) -> Result<(), WriteError<'arena>> {
+        let len_span = len.span();
+        let len = match len.as_ref() {
+            Value::ConstLit(Const::U8(len, _)) => Some(usize::from(*len)),
+            Value::ConstLit(Const::U16(len, _)) => Some(usize::from(*len)),
+            Value::ConstLit(Const::U32(len, _)) => usize::try_from(*len).ok(),
+            Value::ConstLit(Const::U64(len, _)) => usize::try_from(*len).ok(),
+            _ => return Err(WriteError::InvalidValue(len_span)),
+        }
+        .ok_or(WriteError::PositionOverflow(len_span))?;
+
+        let start_offset = writer.offset();
+        let end_offset =
+            (start_offset.checked_add(len)).ok_or(WriteError::PositionOverflow(len_span))?;
+
+        // Limited formats are read from a new buffer, so alignment within them
+        // is relative to their start
+        let buffer_start = std::mem::replace(&mut self.buffer_start, start_offset);
+        let result = self.write_format(writer, elem_format, expr);
+        self.buffer_start = buffer_start;
+        result?;
+
+        if writer.offset() > end_offset {
+            return Err(WriteError::LimitExceeded {
+                span: len_span,
+                limit: len,
+                len: writer.offset() - start_offset,
+            });
+        }
+        // A partially written byte is considered to be filled, like when reading
+        let written_end = writer.offset() + usize::from(!writer.is_byte_aligned());
+        if exact && written_end < end_offset {
+            return Err(WriteError::LimitNotFilled {
+                span: len_span,
+                limit: len,
+                len: written_end - start_offset,
+            });
+        }
+
+        // Any data that the format did not cover is filled with zeros. The
+        // reader does not advance past limited formats, so neither do we.
+        writer.extend_to(end_offset);
+        writer.set_offset(start_offset);
+
+        Ok(())
+    }
+
+    fn write_choice(
+        &mut self,
+        writer: &mut BufferWriter,
+        formats: &ArcValue<'arena>,
+        expr: &ArcValue<'arena>,
+    ) -> Result<(), WriteError<'arena>> {
+        let formats = match formats.as_ref() {
+            Value::ArrayLit(formats) => formats,
+            _ => return Err(WriteError::InvalidValue(formats.span())),
+        };
+        // The expression records the alternative that was read
+        let (index, elem_expr) = match expr.as_ref() {
+            Value::RecordLit(_, exprs) => match (exprs[0].as_ref(), &exprs[1]) {
+                (Value::ConstLit(Const::U64(index, _)), elem_expr) => (*index, elem_expr),
+                _ => return Err(WriteError::InvalidValue(expr.span())),
+            },
+            _ => return Err(WriteError::InvalidValue(expr.span())),
+        };
+        let format = (usize::try_from(index).ok())
+            .and_then(|index| formats.get(index))
+            .ok_or_else(|| WriteError::InvalidValue(expr.span()))?;
+
+        self.write_format(writer, format, elem_expr)
+    }
+
+    fn write_link(
+        &mut self,
+        span: Span,
+        elem_format: &ArcValue<'arena>,
+        expr: &ArcValue<'arena>,
+    ) -> Result<(), WriteError<'arena>> {
+        let pos = match expr.as_ref() {
+            Value::ConstLit(Const::Ref(pos, _)) if pos.buffer() == 0 => pos.offset(),
+            _ => return Err(WriteError::InvalidValue(expr.span())),
+        };
+
+        self.pending_refs.push(PendingRef {
+            span,
+            pos,
+            format: elem_format.clone(),
+            expr: None,
+        });
+
+        Ok(())
+    }
+
+    fn write_deref(
+        &mut self,
+        format: &ArcValue<'arena>,
+        r#ref: &ArcValue<'arena>,
+        expr: &ArcValue<'arena>,
+    ) -> Result<(), WriteError<'arena>> {
+        let pos = match r#ref.as_ref() {
+            Value::ConstLit(Const::Ref(pos, _)) if pos.buffer() == 0 => pos.offset(),
+            _ => return Err(WriteError::InvalidValue(r#ref.span())),
+        };
+
+        self.pending_refs.push(PendingRef {
+            span: r#ref.span(),
+            pos,
+            format: format.clone(),
+            expr: Some(expr.clone()),
+        });
+
+        Ok(())
+    }
+
+    fn is_equal_format(&self, format0: &ArcValue<'_>, format1: &ArcValue<'_>) -> bool {
+        (self.elim_env().conversion_env(EnvLen::new())).is_equal(format0, format1)
+    }
+
+    fn write_pending_ref(
+        &mut self,
+        writer: &mut BufferWriter,
+        pending_ref: PendingRef<'arena>,
+    ) -> Result<(), WriteError<'arena>> {
+        let PendingRef {
+            span,
+            pos,
+            format,
+            expr,
+        } = pending_ref;
+
+        if (self.written_refs.iter()).any(|(written_pos, written_format)| {
+            *written_pos == pos && self.is_equal_format(written_format, &format)
+        }) {
+            return Ok(());
+        }
+
+        let expr = match expr {
+            Some(expr) => expr,
+            None => {
+                // Prefer the cached references, falling back to any
+                // dereferences of the same link that have yet to be written.
+                let cached_expr = (self.cached_refs.get(&pos).into_iter().flatten())
+                    .find(|r| self.is_equal_format(&r.format, &format))
+                    .map(|r| r.expr.clone());
+                let pending_expr = || {
+                    (self.pending_refs.iter())
+                        .filter(|r| r.pos == pos && self.is_equal_format(&r.format, &format))
+                        .find_map(|r| r.expr.clone())
+                };
+
+                match cached_expr.or_else(pending_expr) {
+                    Some(expr) => expr,
+                    None => return Err(WriteError::UnknownRef(span, pos)),
+                }
+            }
+        };
+
+        writer.set_offset(pos);
+        self.write_format(writer, &format, &expr)?;
+        self.written_refs.push((pos, format));
+
+        Ok(())
+    }
+}
+
+impl<'arena> Default for Context<'arena> {
+    fn default() -> Context<'arena> {
+        Context::new()
+    }
+}
+
+/// The first byte that differs between data that was read and the data that
+/// was written back from the values that were read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The position of the byte
+    pub offset: usize,
+    /// The byte that was read, or `None` if the written data is longer
+    pub expected: Option<u8>,
+    /// The byte that was written, or `None` if the written data is shorter
+    pub found: Option<u8>,
+    /// The innermost value that the byte was read as part of
+    pub path: Option<ValuePath>,
+}
+
+/// Compare data that was read with the data that was written back from the
+/// values that were read, using the provenance recorded when reading to find
+/// the value that the first differing byte belongs to. Bytes that were not
+/// read as part of any value are ignored, as they cannot be reproduced from
+/// the values that were read.
+pub fn first_mismatch(
+    provenance: &ProvenanceMap,
+    read_data: &[u8],
+    written_data: &[u8],
+) -> Option<Mismatch> {
+    // Values are visited before the values contained within them, so the
+    // innermost value that covers each byte is the last one to be visited
+    let paths = provenance.iter().map(|(path, _)| path).collect::<Vec<_>>();
+    let mut values = vec![None; read_data.len()];
+    for (index, (_, range)) in provenance.iter().enumerate() {
+        let end = std::cmp::min(range.end, read_data.len());
+        for value in values.get_mut(range.start..end).into_iter().flatten() {
+            *value = Some(index);
+        }
+    }
+
+    let len = std::cmp::max(read_data.len(), written_data.len());
+    let offset = (0..len).find(|offset| {
+        let is_read = values.get(*offset).map_or(true, Option::is_some);
+        is_read && read_data.get(*offset) != written_data.get(*offset)
+    })?;
+
+    Some(Mismatch {
+        offset,
+        expected: read_data.get(offset).copied(),
+        found: written_data.get(offset).copied(),
+        path: values
+            .get(offset)
+            .copied()
+            .flatten()
+            .map(|index| paths[index].clone()),
+    })
+}
+
+/// Return the field expressions of a record literal, checking that it matches
+/// the labels of a record format.
+fn record_exprs<'context, 'arena>(
+    labels: &[crate::symbol::Symbol],
+    expr: &'context ArcValue<'arena>,
+) -> Result<&'context [ArcValue<'arena>], WriteError<'arena>> {
+    match expr.as_ref() {
+        Value::RecordLit(expr_labels, exprs) if *expr_labels == labels => Ok(exprs),
+        _ => Err(WriteError::InvalidValue(expr.span())),
+    }
+}
+
+fn write_bit<'arena>(
+    writer: &mut BufferWriter,
+    expr: &ArcValue<'arena>,
+    order: BitOrder,
+) -> Result<(), WriteError<'arena>> {
+    match expr.as_ref() {
+        Value::ConstLit(Const::Bool(bit)) => {
+            writer.write_bit(order, *bit);
+            Ok(())
+        }
+        _ => Err(WriteError::InvalidValue(expr.span())),
+    }
+}
+
+fn write_bits<'arena>(
+    writer: &mut BufferWriter,
+    span: Span,
+    count: &ArcValue<'arena>,
+    expr: &ArcValue<'arena>,
+    order: BitOrder,
+    max_count: u8,
+) -> Result<(), WriteError<'arena>> {
+    let count = match count.as_ref() {
+        Value::ConstLit(Const::U8(count, _)) if *count <= max_count => *count,
+        Value::ConstLit(Const::U8(count, _)) => {
+            return Err(WriteError::InvalidBitCount(span, *count));
+        }
+        _ => return Err(WriteError::InvalidValue(count.span())),
+    };
+    let value = match expr.as_ref() {
+        Value::ConstLit(Const::U8(value, _)) => u64::from(*value),
+        Value::ConstLit(Const::U16(value, _)) => u64::from(*value),
+        Value::ConstLit(Const::U32(value, _)) => u64::from(*value),
+        Value::ConstLit(Const::U64(value, _)) => *value,
+        _ => return Err(WriteError::InvalidValue(expr.span())),
+    };
+
+    // The value must fit in the number of bits being written
+    if count < 64 && value >> count != 0 {
+        return Err(WriteError::InvalidValue(expr.span()));
+    }
+
+    writer.write_bits(order, count, value);
+    Ok(())
+}
+
+fn write_byte_count<'arena>(value: &ArcValue<'arena>) -> Result<usize, WriteError<'arena>> {
+    match value.as_ref() {
+        Value::ConstLit(Const::U64(count, _)) => {
+            usize::try_from(*count).map_err(|_| WriteError::PositionOverflow(value.span()))
+        }
+        _ => Err(WriteError::InvalidValue(value.span())),
+    }
+}
+
+fn write_align<'arena>(
+    writer: &mut BufferWriter,
+    span: Span,
+    buffer_start: usize,
+    align: &ArcValue<'arena>,
+) -> Result<(), WriteError<'arena>> {
+    let align = write_byte_count(align)?;
+
+    if !writer.is_byte_aligned() {
+        return Err(WriteError::UnalignedWrite(span));
+    }
+    if align > 0 {
+        let padding = (align - (writer.offset() - buffer_start) % align) % align;
+        writer.skip_bytes(padding);
+    }
+
+    Ok(())
+}
+
+fn write_skip<'arena>(
+    writer: &mut BufferWriter,
+    span: Span,
+    len: &ArcValue<'arena>,
+) -> Result<(), WriteError<'arena>> {
+    let len = write_byte_count(len)?;
+
+    if !writer.is_byte_aligned() {
+        return Err(WriteError::UnalignedWrite(span));
+    }
+    writer.skip_bytes(len);
+
+    Ok(())
+}
+
+fn write_const<'arena, T, const N: usize>(
+    writer: &mut BufferWriter,
+    span: Span,
+    expr: &ArcValue<'arena>,
+    get: fn(&Const) -> Option<T>,
+    to_bytes: fn(T) -> [u8; N],
+) -> Result<(), WriteError<'arena>> {
+    if !writer.is_byte_aligned() {
+        return Err(WriteError::UnalignedWrite(span));
+    }
+
+    match expr.as_ref() {
+        Value::ConstLit(r#const) => match get(r#const) {
+            Some(data) => {
+                writer.write_bytes(&to_bytes(data));
+                Ok(())
+            }
+            None => Err(WriteError::InvalidValue(expr.span())),
+        },
+        _ => Err(WriteError::InvalidValue(expr.span())),
+    }
+}
+
+/// Encode a string value in the encoding described by `encoding`.
+fn encode_string<'arena>(
+    writer: &BufferWriter,
+    span: Span,
+    encoding: &ArcValue<'arena>,
+    expr: &ArcValue<'arena>,
+) -> Result<(TextEncoding, Vec<u8>), WriteError<'arena>> {
+    if !writer.is_byte_aligned() {
+        return Err(WriteError::UnalignedWrite(span));
+    }
+
+    let encoding = TextEncoding::from_value(encoding)
+        .ok_or_else(|| WriteError::InvalidValue(encoding.span()))?;
+    let string = match expr.as_ref() {
+        Value::ConstLit(Const::String(string)) => *string,
+        _ => return Err(WriteError::InvalidValue(expr.span())),
+    };
+
+    match encoding.encode(string) {
+        Some(bytes) => Ok((encoding, bytes)),
+        None => Err(WriteError::UnencodableString(expr.span(), encoding)),
+    }
+}
+
+fn write_string_nul<'arena>(
+    writer: &mut BufferWriter,
+    span: Span,
+    encoding: &ArcValue<'arena>,
+    expr: &ArcValue<'arena>,
+) -> Result<(), WriteError<'arena>> {
+    let (encoding, bytes) = encode_string(writer, span, encoding, expr)?;
+    let unit_len = encoding.code_unit_len();
+
+    // The string would be cut short by any null code units it contains
+    if (bytes.chunks(unit_len)).any(|unit| unit.iter().all(|byte| *byte == 0)) {
+        return Err(WriteError::UnencodableString(expr.span(), encoding));
+    }
+
+    writer.write_bytes(&bytes);
+    writer.write_bytes(&vec![0; unit_len]);
+    Ok(())
+}
+
+fn write_string_prefixed<'arena>(
+    writer: &mut BufferWriter,
+    span: Span,
+    encoding: &ArcValue<'arena>,
+    expr: &ArcValue<'arena>,
+    encode_len: fn(usize) -> Option<Vec<u8>>,
+) -> Result<(), WriteError<'arena>> {
+    let (_, bytes) = encode_string(writer, span, encoding, expr)?;
+    let len_bytes = encode_len(bytes.len()).ok_or(WriteError::InvalidValue(expr.span()))?;
+
+    writer.write_bytes(&len_bytes);
+    writer.write_bytes(&bytes);
+    Ok(())
+}
+
+fn write_string_fixed<'arena>(
+    writer: &mut BufferWriter,
+    span: Span,
+    len: &ArcValue<'arena>,
+    encoding: &ArcValue<'arena>,
+    expr: &ArcValue<'arena>,
+) -> Result<(), WriteError<'arena>> {
+    let len = match len.as_ref() {
+        Value::ConstLit(Const::U8(len, _)) => Some(usize::from(*len)),
+        Value::ConstLit(Const::U16(len, _)) => Some(usize::from(*len)),
+        Value::ConstLit(Const::U32(len, _)) => usize::try_from(*len).ok(),
+        Value::ConstLit(Const::U64(len, _)) => usize::try_from(*len).ok(),
+        _ => return Err(WriteError::InvalidValue(len.span())),
+    }
+    .ok_or(WriteError::PositionOverflow(len.span()))?;
+    let (_, bytes) = encode_string(writer, span, encoding, expr)?;
+
+    if bytes.len() > len {
+        return Err(WriteError::LimitExceeded {
+            span,
+            limit: len,
+            len: bytes.len(),
+        });
+    }
+
+    // Pad the remainder of the string with nulls
+    writer.write_bytes(&bytes);
+    writer.write_bytes(&vec![0; len - bytes.len()]);
+    Ok(())
+}
+
+fn write_varint<'arena, T>(
+    writer: &mut BufferWriter,
+    span: Span,
+    expr: &ArcValue<'arena>,
+    get: fn(&Const) -> Option<T>,
+    encode: fn(T) -> Vec<u8>,
+) -> Result<(), WriteError<'arena>> {
+    if !writer.is_byte_aligned() {
+        return Err(WriteError::UnalignedWrite(span));
+    }
+
+    match expr.as_ref() {
+        Value::ConstLit(r#const) => match get(r#const) {
+            Some(data) => {
+                writer.write_bytes(&encode(data));
+                Ok(())
+            }
+            None => Err(WriteError::InvalidValue(expr.span())),
+        },
+        _ => Err(WriteError::InvalidValue(expr.span())),
+    }
+}
+
+/// Encode an unsigned LEB128 integer, using as few bytes as possible.
+fn encode_uleb128(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let group = (value & 0x7f) as u8;
+        value >>= 7;
+        match value {
+            0 => {
+                bytes.push(group);
+                return bytes;
+            }
+            _ => bytes.push(group | 0x80),
+        }
+    }
+}
+
+/// Encode a signed LEB128 integer, using as few bytes as possible.
+fn encode_sleb128(mut value: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let group = (value & 0x7f) as u8;
+        value >>= 7;
+        // Stop once the remaining bits are a sign extension of the last group
+        match (value, group & 0x40) {
+            (0, 0) | (-1, 0x40) => {
+                bytes.push(group);
+                return bytes;
+            }
+            _ => bytes.push(group | 0x80),
+        }
+    }
+}
+
+/// Encode a zigzag encoded integer, using as few bytes as possible.
+fn encode_varint_zigzag(value: i64) -> Vec<u8> {
+    encode_uleb128(((value << 1) ^ (value >> 63)) as u64)
+}
+
+/// Encode a big endian variable-length quantity, using as few bytes as possible.
+fn encode_vlq_be(mut value: u64) -> Vec<u8> {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+/// Generates a function that extracts a primitive from a constant.
+macro_rules! const_prim {
+    ($const_prim:ident, $Const:ident, $T:ident) => {
+        fn $const_prim(r#const: &Const) -> Option<$T> {
+            match r#const {
+                Const::$Const(data, ..) => Some(*data),
+                _ => None,
+            }
+        }
+    };
+}
+
+const_prim!(const_u8, U8, u8);
+const_prim!(const_u16, U16, u16);
+const_prim!(const_u32, U32, u32);
+const_prim!(const_u64, U64, u64);
+const_prim!(const_s8, S8, i8);
+const_prim!(const_s16, S16, i16);
+const_prim!(const_s32, S32, i32);
+const_prim!(const_s64, S64, i64);
+const_prim!(const_f32, F32, f32);
+const_prim!(const_f64, F64, f64);
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use scoped_arena::Scope;
+
+    use super::*;
+    use crate::core::binary::tests::elab_format;
+    use crate::core::binary::{self, Buffer, PathSegment};
+    use crate::core::UIntStyle;
+    use crate::source::Spanned;
+    use crate::symbol::Symbol;
+
+    fn read_and_write(format_source: &str, data: &[u8]) -> Vec<u8> {
+        let scope = Scope::new();
+        let format = elab_format(&scope, format_source);
+
+        let refs = binary::Context::new(&scope, Buffer::from(data))
+            .read_entrypoint(&format)
+            .unwrap();
+        let expr = refs[&0][0].expr.clone();
+
+        let mut context = Context::new();
+        context.set_refs(refs);
+        context.write_entrypoint(&format, &expr).unwrap()
+    }
+
+    #[test]
+    fn write_prims() {
+        let format = "{ a <- u8, b <- u16be, c <- s32le, d <- f64be, e <- repeat_len8 a u16le }";
+        let data = [
+            2, 0, 1, 255, 255, 255, 255, 64, 9, 33, 251, 84, 68, 45, 24, 1, 0, 2, 0,
+        ];
+
+        assert_eq!(read_and_write(format, &data), data);
+    }
+
+    #[test]
+    fn write_overlap_and_links() {
+        let format =
+            "overlap { start <- stream_pos, header <- { len <- u8, r <- link start u16be } }";
+        let data = [1, 7];
+
+        assert_eq!(read_and_write(format, &data), data);
+    }
+
+    #[test]
+    fn write_bits() {
+        let format = "{ a <- bit_msb, b <- bits8_msb 3, c <- byte_align, d <- bits16_lsb 12, e <- bit_lsb, f <- byte_align, g <- u8 }";
+        let data = [0b1011_0000, 0b1010_0101, 0b0001_1111, 42];
+
+        assert_eq!(
+            read_and_write(format, &data),
+            [0b1011_0000, 0b1010_0101, 0b0001_1111, 42]
+        );
+    }
+
+    #[test]
+    fn write_varints() {
+        let format =
+            "{ a <- uleb128, b <- sleb128, c <- sleb128, d <- varint_zigzag, e <- vlq_be }";
+        let data = [
+            0xe5, 0x8e, 0x26, // 624485
+            0xc0, 0xbb, 0x78, // -123456
+            0x3f, // 63
+            0x03, // -2
+            0x81, 0x80, 0x00, // 16384
+        ];
+
+        assert_eq!(read_and_write(format, &data), data);
+    }
+
+    #[test]
+    fn write_strings() {
+        let format = "{ a <- string_nul utf8, b <- string_prefixed16be utf16be, c <- string_fixed8 8 latin1 }";
+        let data = [
+            b'h', b'i', 0, // "hi"
+            0, 4, 0x00, 0xe9, 0x00, b't', // "ét"
+            0xe9, b't', b'e', 0, 0, 0, 0, 0, // "éte"
+        ];
+
+        assert_eq!(read_and_write(format, &data), data);
+    }
+
+    #[test]
+    fn write_repeat_pred() {
+        let format = "{ a <- repeat_until u8 (fun x => u8_eq x 0), b <- repeat_while u8 (fun x => u8_lt x 128), c <- u8 }";
+        let data = [1, 2, 0, 3, 4, 200];
+
+        assert_eq!(read_and_write(format, &data), data);
+
+        // The terminating element must come last
+        let scope = Scope::new();
+        let format = elab_format(&scope, "repeat_until u8 (fun x => u8_eq x 0)");
+        let expr = binary::Context::new(&scope, Buffer::from(&[1, 0, 2, 0][..]))
+            .read_entrypoint(&elab_format(&scope, "repeat_len8 4 u8"))
+            .unwrap()[&0][0]
+            .expr
+            .clone();
+        let result = Context::new().write_entrypoint(&format, &expr);
+        assert!(matches!(result, Err(WriteError::CondFailure(_, _))));
+
+        // At least the terminating element must be present
+        let expr = binary::Context::new(&scope, Buffer::from(&[0; 0][..]))
+            .read_entrypoint(&elab_format(&scope, "repeat_len8 0 u8"))
+            .unwrap()[&0][0]
+            .expr
+            .clone();
+        let result = Context::new().write_entrypoint(&format, &expr);
+        assert!(matches!(result, Err(WriteError::InvalidValue(_))));
+
+        // Empty arrays are read back the same by `repeat_while`
+        let format = elab_format(&scope, "repeat_while u8 (fun x => u8_lt x 128)");
+        let result = Context::new().write_entrypoint(&format, &expr);
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[test]
+    fn write_checksums() {
+        let format = "{
+            start <- stream_pos,
+            data <- repeat_len8 9 u8,
+            crc <- u32be where u32_eq crc (crc32 start 9),
+            adler <- u32le where u32_eq adler (adler32 start 9),
+            sum <- u16be where u16_eq sum (sum_u16be start 9),
+        }";
+        let data = [
+            b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', // "123456789"
+            0xcb, 0xf4, 0x39, 0x26, // crc32
+            0xde, 0x01, 0x1e, 0x09, // adler32
+            0x09, 0xd4, // sum_u16be
+        ];
+
+        assert_eq!(read_and_write(format, &data), data);
+
+        let scope = Scope::new();
+        let format = elab_format(&scope, format);
+        let mut corrupted = data;
+        corrupted[4] = b'0';
+        let result =
+            binary::Context::new(&scope, Buffer::from(&corrupted[..])).read_entrypoint(&format);
+        assert!(matches!(result, Err(binary::ReadError::CondFailure(_, _))));
+    }
+
+    #[test]
+    fn write_align_skip_reserved() {
+        let format = "{
+            a <- u8,
+            pad <- align 4,
+            b <- u8,
+            gap <- skip 2,
+            r <- reserved u16be 0xffff,
+            c <- limit8 3 { x <- u8, pad <- align 2, y <- u8 },
+        }";
+        let data = [1, 7, 7, 7, 2, 9, 9, 0xff, 0xff, 3, 8, 4];
+
+        // Skipped data and padding is written as zeros
+        assert_eq!(
+            read_and_write(format, &data),
+            [1, 0, 0, 0, 2, 0, 0, 0xff, 0xff, 3, 0, 4]
+        );
+    }
+
+    #[test]
+    fn write_limit_exact() {
+        let format = "{ len <- u8, data <- limit_exact8 len (repeat_until_end u16be) }";
+        let data = [4, 0, 1, 0, 2];
+
+        assert_eq!(read_and_write(format, &data), data);
+
+        let scope = Scope::new();
+        let format = elab_format(&scope, "{ len <- u8, data <- limit_exact8 len u16be }");
+        let refs = binary::Context::new(&scope, Buffer::from(&[2, 0, 1][..]))
+            .read_entrypoint(&format)
+            .unwrap();
+        let expr = match refs[&0][0].expr.as_ref() {
+            Value::RecordLit(labels, exprs) => {
+                let len = Arc::new(Value::ConstLit(Const::U8(3, UIntStyle::Decimal)));
+                let exprs = vec![Spanned::empty(len), exprs[1].clone()];
+                Spanned::empty(Arc::new(Value::RecordLit(labels, exprs)))
+            }
+            _ => panic!("expected record"),
+        };
+
+        let result = Context::new().write_entrypoint(&format, &expr);
+        assert!(matches!(
+            result,
+            Err(WriteError::LimitNotFilled {
+                limit: 3,
+                len: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn write_choice() {
+        let format = "repeat_until_end (choice [
+            { tag <- u8 where u8_eq tag 0, value <- u8 },
+            { tag <- u8, value <- u16be },
+        ])";
+        let data = [0, 1, 2, 0, 3, 0, 4];
+
+        assert_eq!(read_and_write(format, &data), data);
+    }
+
+    #[test]
+    fn write_map() {
+        let scope = Scope::new();
+        let format = elab_format(&scope, "map u8 Bool (fun x => u8_neq x 0)");
+        let refs = binary::Context::new(&scope, Buffer::from(&[1][..]))
+            .read_entrypoint(&format)
+            .unwrap();
+        let expr = refs[&0][0].expr.clone();
+
+        let result = Context::new().write_entrypoint(&format, &expr);
+        assert!(matches!(result, Err(WriteError::MapFormat(_))));
+    }
+
+    #[test]
+    fn write_cond_failure() {
+        let scope = Scope::new();
+        let read_format = elab_format(&scope, "{ x <- u8 }");
+        let write_format = elab_format(&scope, "{ x <- u8 where u8_eq x 4 }");
+
+        let refs = binary::Context::new(&scope, Buffer::from(&[5][..]))
+            .read_entrypoint(&read_format)
+            .unwrap();
+        let expr = refs[&0][0].expr.clone();
+
+        let result = Context::new().write_entrypoint(&write_format, &expr);
+        assert!(matches!(result, Err(WriteError::CondFailure(_, _))));
+    }
+
+    #[test]
+    fn roundtrip_mismatch() {
+        let scope = Scope::new();
+        let roundtrip = |source, data: &[u8]| {
+            let format = elab_format(&scope, source);
+            let mut context = binary::Context::new(&scope, Buffer::from(data));
+            context.set_record_provenance(true);
+            let refs = context.read_entrypoint(&format).unwrap();
+            let expr = refs[&0][0].expr.clone();
+
+            let mut write_context = Context::new();
+            write_context.set_refs(refs);
+            let written_data = write_context.write_entrypoint(&format, &expr).unwrap();
+            first_mismatch(context.provenance().unwrap(), data, &written_data)
+        };
+
+        // Trailing data that was not read is ignored
+        let data = [1, 7, 7, 0, 2, 9];
+        assert_eq!(roundtrip("{ a <- u8, b <- u8, c <- u8 }", &data), None);
+
+        // Skipped data is written as zeros
+        let mut path = ValuePath::new(0, 0);
+        path.segments
+            .push(PathSegment::Field(Symbol::intern("gap")));
+        assert_eq!(
+            roundtrip("{ a <- u8, gap <- skip 2, b <- u16be }", &data),
+            Some(Mismatch {
+                offset: 1,
+                expected: Some(7),
+                found: Some(0),
+                path: Some(path),
+            }),
+        );
+    }
+}
@@ -1,9 +1,10 @@
 use std::sync::Arc;
 
 use fxhash::FxHashMap;
+use once_cell::sync::Lazy;
 use scoped_arena::Scope;
 
-use crate::core::semantics::{ArcValue, Elim, ElimEnv, Head, Value};
+use crate::core::semantics::{ArcValue, BinaryData, Elim, ElimEnv, Head, Telescope, Value};
 use crate::core::{self, Const, Plicity, Prim, UIntStyle};
 use crate::env::{self, SharedEnv, UniqueEnv};
 use crate::source::{Span, Spanned};
@@ -51,6 +52,8 @@ impl<'arena> Env<'arena> {
         const ARRAY32_TYPE: Term<'_> = Term::Prim(Span::Empty, Array32Type);
         const ARRAY64_TYPE: Term<'_> = Term::Prim(Span::Empty, Array64Type);
         const POS_TYPE: Term<'_> = Term::Prim(Span::Empty, PosType);
+        const STRING_TYPE: Term<'_> = Term::Prim(Span::Empty, StringType);
+        const TEXT_ENCODING_TYPE: Term<'_> = Term::Prim(Span::Empty, TextEncodingType);
 
         let mut env = EnvBuilder::new(scope);
 
@@ -74,6 +77,13 @@ impl<'arena> Env<'arena> {
         env.define_prim_fun(Array64Type, [&U64_TYPE, &UNIVERSE], &UNIVERSE);
         env.define_prim(PosType, &UNIVERSE);
         env.define_prim_fun(RefType, [&FORMAT_TYPE], &UNIVERSE);
+        env.define_prim(StringType, &UNIVERSE);
+        env.define_prim(TextEncodingType, &UNIVERSE);
+        env.define_prim(TextEncodingUtf8, &TEXT_ENCODING_TYPE);
+        env.define_prim(TextEncodingUtf16Le, &TEXT_ENCODING_TYPE);
+        env.define_prim(TextEncodingUtf16Be, &TEXT_ENCODING_TYPE);
+        env.define_prim(TextEncodingAscii, &TEXT_ENCODING_TYPE);
+        env.define_prim(TextEncodingLatin1, &TEXT_ENCODING_TYPE);
         env.define_prim(FormatType, &UNIVERSE);
 
         env.define_prim(FormatU8, &FORMAT_TYPE);
@@ -94,15 +104,130 @@ impl<'arena> Env<'arena> {
         env.define_prim(FormatF32Le, &FORMAT_TYPE);
         env.define_prim(FormatF64Be, &FORMAT_TYPE);
         env.define_prim(FormatF64Le, &FORMAT_TYPE);
+        env.define_prim(FormatBitMsb, &FORMAT_TYPE);
+        env.define_prim(FormatBitLsb, &FORMAT_TYPE);
+        env.define_prim_fun(FormatBits8Msb, [&U8_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatBits8Lsb, [&U8_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatBits16Msb, [&U8_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatBits16Lsb, [&U8_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatBits32Msb, [&U8_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatBits32Lsb, [&U8_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatBits64Msb, [&U8_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatBits64Lsb, [&U8_TYPE], &FORMAT_TYPE);
+        env.define_prim(FormatByteAlign, &FORMAT_TYPE);
+        env.define_prim(FormatUleb128, &FORMAT_TYPE);
+        env.define_prim(FormatSleb128, &FORMAT_TYPE);
+        env.define_prim(FormatVarintZigzag, &FORMAT_TYPE);
+        env.define_prim(FormatVlqBe, &FORMAT_TYPE);
+        env.define_prim_fun(FormatStringNul, [&TEXT_ENCODING_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatStringPrefixed8, [&TEXT_ENCODING_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(
+            FormatStringPrefixed16Be,
+            [&TEXT_ENCODING_TYPE],
+            &FORMAT_TYPE,
+        );
+        env.define_prim_fun(
+            FormatStringPrefixed16Le,
+            [&TEXT_ENCODING_TYPE],
+            &FORMAT_TYPE,
+        );
+        env.define_prim_fun(
+            FormatStringPrefixed32Be,
+            [&TEXT_ENCODING_TYPE],
+            &FORMAT_TYPE,
+        );
+        env.define_prim_fun(
+            FormatStringPrefixed32Le,
+            [&TEXT_ENCODING_TYPE],
+            &FORMAT_TYPE,
+        );
+        env.define_prim_fun(
+            FormatStringFixed8,
+            [&U8_TYPE, &TEXT_ENCODING_TYPE],
+            &FORMAT_TYPE,
+        );
+        env.define_prim_fun(
+            FormatStringFixed16,
+            [&U16_TYPE, &TEXT_ENCODING_TYPE],
+            &FORMAT_TYPE,
+        );
+        env.define_prim_fun(
+            FormatStringFixed32,
+            [&U32_TYPE, &TEXT_ENCODING_TYPE],
+            &FORMAT_TYPE,
+        );
+        env.define_prim_fun(
+            FormatStringFixed64,
+            [&U64_TYPE, &TEXT_ENCODING_TYPE],
+            &FORMAT_TYPE,
+        );
         env.define_prim_fun(FormatRepeatLen8, [&U8_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
         env.define_prim_fun(FormatRepeatLen16, [&U16_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
         env.define_prim_fun(FormatRepeatLen32, [&U32_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
         env.define_prim_fun(FormatRepeatLen64, [&U64_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
         env.define_prim_fun(FormatRepeatUntilEnd, [&FORMAT_TYPE], &FORMAT_TYPE);
+        // fun (f : Format) -> (Repr f -> Bool) -> Format
+        let repeat_pred_type = core::Term::FunType(
+            Span::Empty,
+            Plicity::Explicit,
+            env.name("f"),
+            &FORMAT_TYPE,
+            &Term::FunType(
+                Span::Empty,
+                Plicity::Explicit,
+                None,
+                &Term::FunType(
+                    Span::Empty,
+                    Plicity::Explicit,
+                    None,
+                    &Term::FunApp(
+                        Span::Empty,
+                        Plicity::Explicit,
+                        &Term::Prim(Span::Empty, FormatRepr),
+                        &VAR0,
+                    ),
+                    &BOOL_TYPE,
+                ),
+                &FORMAT_TYPE,
+            ),
+        );
+        env.define_prim(FormatRepeatUntil, &repeat_pred_type);
+        env.define_prim(FormatRepeatWhile, &repeat_pred_type);
         env.define_prim_fun(FormatLimit8, [&U8_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
         env.define_prim_fun(FormatLimit16, [&U16_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
         env.define_prim_fun(FormatLimit32, [&U32_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
         env.define_prim_fun(FormatLimit64, [&U64_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatLimitExact8, [&U8_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatLimitExact16, [&U16_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatLimitExact32, [&U32_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatLimitExact64, [&U64_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatDeflate, [&U32_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatZlib, [&U32_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatBrotli, [&U32_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatAlign, [&U64_TYPE], &FORMAT_TYPE);
+        env.define_prim_fun(FormatSkip, [&U64_TYPE], &FORMAT_TYPE);
+        env.define_prim(
+            FormatReserved,
+            // fun (f : Format) -> Repr f -> Format
+            &core::Term::FunType(
+                Span::Empty,
+                Plicity::Explicit,
+                env.name("f"),
+                &FORMAT_TYPE,
+                &Term::FunType(
+                    Span::Empty,
+                    Plicity::Explicit,
+                    None,
+                    &Term::FunApp(
+                        Span::Empty,
+                        Plicity::Explicit,
+                        &Term::Prim(Span::Empty, FormatRepr),
+                        &VAR0,
+                    ),
+                    &FORMAT_TYPE,
+                ),
+            ),
+        );
         env.define_prim_fun(FormatLink, [&POS_TYPE, &FORMAT_TYPE], &FORMAT_TYPE);
         env.define_prim(
             FormatDeref,
@@ -125,6 +250,56 @@ impl<'arena> Env<'arena> {
                 ),
             ),
         );
+        env.define_prim(FormatEndOfInput, &FORMAT_TYPE);
+        env.define_prim(
+            FormatMap,
+            // fun (f : Format) (A : Type) -> (Repr f@1 -> A@1) -> Format
+            &core::Term::FunType(
+                Span::Empty,
+                Plicity::Explicit,
+                env.name("f"),
+                &FORMAT_TYPE,
+                scope.to_scope(core::Term::FunType(
+                    Span::Empty,
+                    Plicity::Explicit,
+                    env.name("A"),
+                    &UNIVERSE,
+                    &core::Term::FunType(
+                        Span::Empty,
+                        Plicity::Explicit,
+                        None,
+                        &core::Term::FunType(
+                            Span::Empty,
+                            Plicity::Explicit,
+                            None,
+                            &Term::FunApp(
+                                Span::Empty,
+                                Plicity::Explicit,
+                                &Term::Prim(Span::Empty, FormatRepr),
+                                &VAR1,
+                            ),
+                            &VAR1,
+                        ),
+                        &FORMAT_TYPE,
+                    ),
+                )),
+            ),
+        );
+        env.define_prim(
+            FormatChoice,
+            &core::Term::FunType(
+                Span::Empty,
+                Plicity::Explicit,
+                None,
+                &Term::FunApp(
+                    Span::Empty,
+                    Plicity::Explicit,
+                    &Term::Prim(Span::Empty, ArrayType),
+                    &FORMAT_TYPE,
+                ),
+                &FORMAT_TYPE,
+            ),
+        );
         env.define_prim(FormatStreamPos, &FORMAT_TYPE);
         env.define_prim(
             FormatSucceed,
@@ -305,6 +480,9 @@ impl<'arena> Env<'arena> {
         env.define_prim_fun(S64Abs, [&S64_TYPE], &S64_TYPE);
         env.define_prim_fun(S64UAbs, [&S64_TYPE], &U64_TYPE);
 
+        env.define_prim_fun(StringEq, [&STRING_TYPE, &STRING_TYPE], &BOOL_TYPE);
+        env.define_prim_fun(StringNeq, [&STRING_TYPE, &STRING_TYPE], &BOOL_TYPE);
+
         env.define_prim(
             OptionSome,
             // fun (@A : Type) -> A   -> Option A
@@ -494,12 +672,48 @@ impl<'arena> Env<'arena> {
         env.define_prim(Array16Index, array16_index_type);
         env.define_prim(Array32Index, array32_index_type);
         env.define_prim(Array64Index, array64_index_type);
+        env.define_prim(
+            ArrayIndex,
+            // fun (@A : Type) (index : U64) -> Array A@1 -> A@2
+            &core::Term::FunType(
+                Span::Empty,
+                Plicity::Implicit,
+                env.name("A"),
+                &UNIVERSE,
+                scope.to_scope(core::Term::FunType(
+                    Span::Empty,
+                    Plicity::Explicit,
+                    env.name("index"),
+                    &U64_TYPE,
+                    &core::Term::FunType(
+                        Span::Empty,
+                        Plicity::Explicit,
+                        None,
+                        &Term::FunApp(
+                            Span::Empty,
+                            Plicity::Explicit,
+                            &Term::Prim(Span::Empty, ArrayType),
+                            &VAR1,
+                        ),
+                        &VAR2,
+                    ),
+                )),
+            ),
+        );
 
         env.define_prim_fun(PosAddU8, [&POS_TYPE, &U8_TYPE], &POS_TYPE);
         env.define_prim_fun(PosAddU16, [&POS_TYPE, &U16_TYPE], &POS_TYPE);
         env.define_prim_fun(PosAddU32, [&POS_TYPE, &U32_TYPE], &POS_TYPE);
         env.define_prim_fun(PosAddU64, [&POS_TYPE, &U64_TYPE], &POS_TYPE);
 
+        env.define_prim_fun(Crc32, [&POS_TYPE, &U32_TYPE], &U32_TYPE);
+        env.define_prim_fun(Adler32, [&POS_TYPE, &U32_TYPE], &U32_TYPE);
+        env.define_prim_fun(SumU8, [&POS_TYPE, &U32_TYPE], &U8_TYPE);
+        env.define_prim_fun(SumU16Be, [&POS_TYPE, &U32_TYPE], &U16_TYPE);
+        env.define_prim_fun(SumU16Le, [&POS_TYPE, &U32_TYPE], &U16_TYPE);
+        env.define_prim_fun(SumU32Be, [&POS_TYPE, &U32_TYPE], &U32_TYPE);
+        env.define_prim_fun(SumU32Le, [&POS_TYPE, &U32_TYPE], &U32_TYPE);
+
         env.build()
     }
 }
@@ -590,6 +804,180 @@ macro_rules! const_step {
     };
 }
 
+/// Define an evaluation step for a checksum, computed over a range of the
+/// binary data given a starting position and a length.
+macro_rules! checksum_step {
+    (|$range:ident| $body:expr) => {
+        step!(env, [pos, len] => match (pos.as_ref(), len.as_ref()) {
+            (Value::ConstLit(Const::Pos(pos)), Value::ConstLit(Const::U32(len, _))) => {
+                let $range = BinaryRange {
+                    data: env.binary_data()?,
+                    pos: *pos,
+                    len: usize::try_from(*len).ok()?,
+                };
+                Spanned::empty(Arc::new(Value::ConstLit($body)))
+            }
+            _ => return None,
+        })
+    };
+}
+
+/// A range of binary data that a checksum is computed over.
+struct BinaryRange<'env> {
+    data: &'env dyn BinaryData,
+    pos: core::Pos,
+    len: usize,
+}
+
+impl<'env> BinaryRange<'env> {
+    /// Call `on_byte` for each byte in the range, returning `None` if the
+    /// range is outside of the binary data.
+    fn for_each_byte(&self, mut on_byte: impl FnMut(u8)) -> Option<()> {
+        (self.data).for_each_chunk(self.pos, self.len, &mut |chunk| {
+            chunk.iter().for_each(|byte| on_byte(*byte))
+        })
+    }
+}
+
+/// Lookup table for the CRC-32 polynomial used by PNG, gzip and zip.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                0 => crc >> 1,
+                _ => (crc >> 1) ^ 0xedb88320,
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+fn crc32(range: BinaryRange<'_>) -> Option<u32> {
+    let mut crc = !0;
+    range.for_each_byte(|byte| {
+        crc = CRC32_TABLE[usize::from(crc as u8 ^ byte)] ^ (crc >> 8);
+    })?;
+    Some(!crc)
+}
+
+fn adler32(range: BinaryRange<'_>) -> Option<u32> {
+    const MODULUS: u32 = 65521;
+
+    let (mut a, mut b) = (1, 0);
+    range.for_each_byte(|byte| {
+        a = (a + u32::from(byte)) % MODULUS;
+        b = (b + a) % MODULUS;
+    })?;
+    Some((b << 16) | a)
+}
+
+#[derive(Copy, Clone)]
+enum ByteOrder {
+    Big,
+    Little,
+}
+
+/// Compute the wrapping sum of the `N`-byte words in a range, padding the
+/// final word with zeros if the length of the range is not a multiple of `N`.
+fn word_sum<const N: usize>(range: BinaryRange<'_>, order: ByteOrder) -> Option<u32> {
+    let mut sum: u32 = 0;
+    let mut index = 0;
+    // Addition distributes over the bytes of each word, so there is no need
+    // to assemble the words before adding them to the sum
+    range.for_each_byte(|byte| {
+        let shift = match order {
+            ByteOrder::Big => N - 1 - index % N,
+            ByteOrder::Little => index % N,
+        };
+        sum = sum.wrapping_add(u32::from(byte) << (shift * 8));
+        index += 1;
+    })?;
+    Some(sum)
+}
+
+/// The labels of the representation of a choice format.
+pub fn choice_labels() -> &'static [Symbol] {
+    static LABELS: Lazy<[Symbol; 2]> = Lazy::new(|| {
+        [
+            Symbol::intern_static("index"),
+            Symbol::intern_static("value"),
+        ]
+    });
+    &*LABELS
+}
+
+/// The representation of a choice format, which records the index of the
+/// alternative that was read:
+///
+/// ```fathom
+/// { index : U64, value : Repr (array_index index formats) }
+/// ```
+fn choice_repr<'arena>(formats: ArcValue<'arena>) -> ArcValue<'arena> {
+    use crate::core::Term;
+
+    const VAR0: Term<'_> = Term::LocalVar(Span::Empty, env::Index::last());
+    const VAR1: Term<'_> = Term::LocalVar(Span::Empty, env::Index::last().prev());
+    const TERMS: &[Term<'_>] = &[
+        Term::Prim(Span::Empty, Prim::U64Type),
+        // Repr (array_index @Format index@0 formats@1)
+        Term::FunApp(
+            Span::Empty,
+            Plicity::Explicit,
+            &Term::Prim(Span::Empty, Prim::FormatRepr),
+            &Term::FunApp(
+                Span::Empty,
+                Plicity::Explicit,
+                &Term::FunApp(
+                    Span::Empty,
+                    Plicity::Explicit,
+                    &Term::FunApp(
+                        Span::Empty,
+                        Plicity::Implicit,
+                        &Term::Prim(Span::Empty, Prim::ArrayIndex),
+                        &Term::Prim(Span::Empty, Prim::FormatType),
+                    ),
+                    &VAR0,
+                ),
+                &VAR1,
+            ),
+        ),
+    ];
+
+    let mut local_exprs = SharedEnv::new();
+    local_exprs.push(formats);
+    Spanned::empty(Arc::new(Value::RecordType(
+        choice_labels(),
+        Telescope::new(local_exprs, TERMS),
+    )))
+}
+
+/// Look up an element of an array literal.
+fn array_index<'arena>(
+    index: &ArcValue<'arena>,
+    array: &ArcValue<'arena>,
+) -> Option<ArcValue<'arena>> {
+    match array.as_ref() {
+        Value::ArrayLit(elems) => {
+            let index = match index.as_ref() {
+                Value::ConstLit(Const::U8(index, _)) => Some(usize::from(*index)),
+                Value::ConstLit(Const::U16(index, _)) => Some(usize::from(*index)),
+                Value::ConstLit(Const::U32(index, _)) => usize::try_from(*index).ok(),
+                Value::ConstLit(Const::U64(index, _)) => usize::try_from(*index).ok(),
+                _ => None,
+            }?;
+            elems.get(index).cloned()
+        }
+        _ => None,
+    }
+}
+
 #[rustfmt::skip]
 pub fn repr(prim: Prim) -> Step {
     match prim {
@@ -611,6 +999,31 @@ pub fn repr(prim: Prim) -> Step {
         Prim::FormatF32Le => step!(_, [] => Spanned::empty(Arc::new(Value::prim(Prim::F32Type, [])))),
         Prim::FormatF64Be => step!(_, [] => Spanned::empty(Arc::new(Value::prim(Prim::F64Type, [])))),
         Prim::FormatF64Le => step!(_, [] => Spanned::empty(Arc::new(Value::prim(Prim::F64Type, [])))),
+        Prim::FormatBitMsb => step!(_, [] => Spanned::empty(Arc::new(Value::prim(Prim::BoolType, [])))),
+        Prim::FormatBitLsb => step!(_, [] => Spanned::empty(Arc::new(Value::prim(Prim::BoolType, [])))),
+        Prim::FormatBits8Msb => step!(_, [_] => Spanned::empty(Arc::new(Value::prim(Prim::U8Type, [])))),
+        Prim::FormatBits8Lsb => step!(_, [_] => Spanned::empty(Arc::new(Value::prim(Prim::U8Type, [])))),
+        Prim::FormatBits16Msb => step!(_, [_] => Spanned::empty(Arc::new(Value::prim(Prim::U16Type, [])))),
+        Prim::FormatBits16Lsb => step!(_, [_] => Spanned::empty(Arc::new(Value::prim(Prim::U16Type, [])))),
+        Prim::FormatBits32Msb => step!(_, [_] => Spanned::empty(Arc::new(Value::prim(Prim::U32Type, [])))),
+        Prim::FormatBits32Lsb => step!(_, [_] => Spanned::empty(Arc::new(Value::prim(Prim::U32Type, [])))),
+        Prim::FormatBits64Msb => step!(_, [_] => Spanned::empty(Arc::new(Value::prim(Prim::U64Type, [])))),
+        Prim::FormatBits64Lsb => step!(_, [_] => Spanned::empty(Arc::new(Value::prim(Prim::U64Type, [])))),
+        Prim::FormatByteAlign => step!(_, [] => Spanned::empty(Arc::new(Value::RecordType(&[], Telescope::new(SharedEnv::new(), &[]))))),
+        Prim::FormatUleb128 => step!(_, [] => Spanned::empty(Arc::new(Value::prim(Prim::U64Type, [])))),
+        Prim::FormatSleb128 => step!(_, [] => Spanned::empty(Arc::new(Value::prim(Prim::S64Type, [])))),
+        Prim::FormatVarintZigzag => step!(_, [] => Spanned::empty(Arc::new(Value::prim(Prim::S64Type, [])))),
+        Prim::FormatVlqBe => step!(_, [] => Spanned::empty(Arc::new(Value::prim(Prim::U64Type, [])))),
+        Prim::FormatStringNul
+        | Prim::FormatStringPrefixed8
+        | Prim::FormatStringPrefixed16Be
+        | Prim::FormatStringPrefixed16Le
+        | Prim::FormatStringPrefixed32Be
+        | Prim::FormatStringPrefixed32Le => step!(_, [_] => Spanned::empty(Arc::new(Value::prim(Prim::StringType, [])))),
+        Prim::FormatStringFixed8
+        | Prim::FormatStringFixed16
+        | Prim::FormatStringFixed32
+        | Prim::FormatStringFixed64 => step!(_, [_, _] => Spanned::empty(Arc::new(Value::prim(Prim::StringType, [])))),
         Prim::FormatRepeatLen8 => step!(env, [len, elem] => Spanned::empty(Arc::new(Value::prim(Prim::Array8Type, [len.clone(), env.format_repr(elem)])))),
         Prim::FormatRepeatLen16 => step!(env, [len, elem] => Spanned::empty(Arc::new(Value::prim(Prim::Array16Type, [len.clone(), env.format_repr(elem)])))),
         Prim::FormatRepeatLen32 => step!(env, [len, elem] => Spanned::empty(Arc::new(Value::prim(Prim::Array32Type, [len.clone(), env.format_repr(elem)])))),
@@ -619,9 +1032,24 @@ pub fn repr(prim: Prim) -> Step {
         Prim::FormatLimit16 => step!(env, [_, elem] => env.format_repr(elem)),
         Prim::FormatLimit32 => step!(env, [_, elem] => env.format_repr(elem)),
         Prim::FormatLimit64 => step!(env, [_, elem] => env.format_repr(elem)),
+        Prim::FormatLimitExact8 => step!(env, [_, elem] => env.format_repr(elem)),
+        Prim::FormatLimitExact16 => step!(env, [_, elem] => env.format_repr(elem)),
+        Prim::FormatLimitExact32 => step!(env, [_, elem] => env.format_repr(elem)),
+        Prim::FormatLimitExact64 => step!(env, [_, elem] => env.format_repr(elem)),
+        Prim::FormatDeflate => step!(env, [_, elem] => env.format_repr(elem)),
+        Prim::FormatZlib => step!(env, [_, elem] => env.format_repr(elem)),
+        Prim::FormatBrotli => step!(env, [_, elem] => env.format_repr(elem)),
+        Prim::FormatAlign => step!(_, [_] => Spanned::empty(Arc::new(Value::RecordType(&[], Telescope::new(SharedEnv::new(), &[]))))),
+        Prim::FormatSkip => step!(_, [_] => Spanned::empty(Arc::new(Value::RecordType(&[], Telescope::new(SharedEnv::new(), &[]))))),
+        Prim::FormatReserved => step!(_, [_, _] => Spanned::empty(Arc::new(Value::RecordType(&[], Telescope::new(SharedEnv::new(), &[]))))),
         Prim::FormatRepeatUntilEnd => step!(env, [elem] => Spanned::empty(Arc::new(Value::prim(Prim::ArrayType, [env.format_repr(elem)])))),
+        Prim::FormatRepeatUntil => step!(env, [elem, _] => Spanned::empty(Arc::new(Value::prim(Prim::ArrayType, [env.format_repr(elem)])))),
+        Prim::FormatRepeatWhile => step!(env, [elem, _] => Spanned::empty(Arc::new(Value::prim(Prim::ArrayType, [env.format_repr(elem)])))),
         Prim::FormatLink => step!(_, [_, elem] => Spanned::empty(Arc::new(Value::prim(Prim::RefType, [elem.clone()])))),
         Prim::FormatDeref => step!(env, [elem, _] => env.format_repr(elem)),
+        Prim::FormatEndOfInput => step!(_, [] => Spanned::empty(Arc::new(Value::RecordType(&[], Telescope::new(SharedEnv::new(), &[]))))),
+        Prim::FormatChoice => step!(_, [formats] => choice_repr(formats.clone())),
+        Prim::FormatMap => step!(_, [_, r#type, _] => r#type.clone()),
         Prim::FormatStreamPos => step!(_, [] => Spanned::empty(Arc::new(Value::prim(Prim::PosType, [])))),
         Prim::FormatSucceed => step!(_, [elem, _] => elem.clone()),
         Prim::FormatFail => step!(_, [] => Spanned::empty(Arc::new(Value::prim(Prim::VoidType, [])))),
@@ -774,6 +1202,9 @@ pub fn step(prim: Prim) -> Step {
         Prim::S64Abs => const_step!([x: S64] => Const::S64(i64::abs(*x))),
         Prim::S64UAbs => const_step!([x: S64] => Const::U64(i64::unsigned_abs(*x), UIntStyle::Decimal)),
 
+        Prim::StringEq => const_step!([x: String, y: String] => Const::Bool(x == y)),
+        Prim::StringNeq => const_step!([x: String, y: String] => Const::Bool(x != y)),
+
         Prim::OptionFold => step!(env, [_, _, on_none, on_some, option] => {
             match option.match_prim_spine()? {
                 (Prim::OptionSome, [_, Elim::FunApp(Plicity::Explicit, value)]) => {
@@ -813,26 +1244,23 @@ pub fn step(prim: Prim) -> Step {
             })
         }
 
+        Prim::ArrayIndex => step!(_, [_, index, array] => array_index(index, array)?),
         Prim::Array8Index | Prim::Array16Index | Prim::Array32Index | Prim::Array64Index => {
-            step!(_, [_, _, index, array] => match array.as_ref() {
-                Value::ArrayLit(elems) => {
-                    let index = match (index).as_ref() {
-                        Value::ConstLit(Const::U8(index, _)) => Some(usize::from(*index)),
-                        Value::ConstLit(Const::U16(index, _)) => Some(usize::from(*index)),
-                        Value::ConstLit(Const::U32(index, _)) => usize::try_from(*index).ok(),
-                        Value::ConstLit(Const::U64(index, _)) => usize::try_from(*index).ok(),
-                        _ => return None,
-                    }?;
-                    elems.get(index).cloned()?
-                }
-                _ => return None,
-            })
+            step!(_, [_, _, index, array] => array_index(index, array)?)
         }
 
-        Prim::PosAddU8 => const_step!([x: Pos, y: U8] => Const::Pos(usize::checked_add(*x, usize::from(*y))?)),
-        Prim::PosAddU16 => const_step!([x: Pos, y: U16] => Const::Pos(usize::checked_add(*x, usize::from(*y))?)),
-        Prim::PosAddU32 => const_step!([x: Pos, y: U32] => Const::Pos(usize::checked_add(*x, usize::try_from(*y).ok()?)?)),
-        Prim::PosAddU64 => const_step!([x: Pos, y: U64] => Const::Pos(usize::checked_add(*x, usize::try_from(*y).ok()?)?)),
+        Prim::PosAddU8 => const_step!([x: Pos, y: U8] => Const::Pos(x.checked_add(usize::from(*y))?)),
+        Prim::PosAddU16 => const_step!([x: Pos, y: U16] => Const::Pos(x.checked_add(usize::from(*y))?)),
+        Prim::PosAddU32 => const_step!([x: Pos, y: U32] => Const::Pos(x.checked_add(usize::try_from(*y).ok()?)?)),
+        Prim::PosAddU64 => const_step!([x: Pos, y: U64] => Const::Pos(x.checked_add(usize::try_from(*y).ok()?)?)),
+
+        Prim::Crc32 => checksum_step!(|data| Const::U32(crc32(data)?, UIntStyle::Hexadecimal)),
+        Prim::Adler32 => checksum_step!(|data| Const::U32(adler32(data)?, UIntStyle::Hexadecimal)),
+        Prim::SumU8 => checksum_step!(|data| Const::U8(word_sum::<1>(data, ByteOrder::Big)? as u8, UIntStyle::Hexadecimal)),
+        Prim::SumU16Be => checksum_step!(|data| Const::U16(word_sum::<2>(data, ByteOrder::Big)? as u16, UIntStyle::Hexadecimal)),
+        Prim::SumU16Le => checksum_step!(|data| Const::U16(word_sum::<2>(data, ByteOrder::Little)? as u16, UIntStyle::Hexadecimal)),
+        Prim::SumU32Be => checksum_step!(|data| Const::U32(word_sum::<4>(data, ByteOrder::Big)?, UIntStyle::Hexadecimal)),
+        Prim::SumU32Le => checksum_step!(|data| Const::U32(word_sum::<4>(data, ByteOrder::Little)?, UIntStyle::Hexadecimal)),
 
         _ => |_, _| None,
     }
@@ -7,7 +7,7 @@ use std::sync::Arc;
 use scoped_arena::Scope;
 
 use crate::alloc::SliceVec;
-use crate::core::{prim, Const, LocalInfo, Plicity, Prim, Term};
+use crate::core::{prim, Const, LocalInfo, Plicity, Pos, Prim, Term};
 use crate::env::{EnvLen, Index, Level, SharedEnv, SliceEnv};
 use crate::source::{Span, Spanned};
 use crate::symbol::Symbol;
@@ -50,7 +50,7 @@ pub enum Value<'arena> {
     FormatOverlap(&'arena [Symbol], Telescope<'arena>),
 
     /// Constant literals.
-    ConstLit(Const),
+    ConstLit(Const<'arena>),
 }
 
 impl<'arena> Value<'arena> {
@@ -102,7 +102,7 @@ pub enum Elim<'arena> {
     /// Record projections.
     RecordProj(Symbol),
     /// Match on a constant.
-    ConstMatch(Branches<'arena, Const>),
+    ConstMatch(Branches<'arena, Const<'arena>>),
 }
 
 /// A closure is a term that can later be instantiated with a value.
@@ -396,6 +396,14 @@ impl<'arena, 'env> EvalEnv<'arena, 'env> {
     }
 }
 
+/// Binary data that can be inspected by primitives during evaluation, for
+/// example when computing checksums.
+pub trait BinaryData {
+    /// Call `on_chunk` with successive chunks of the `len` bytes starting at
+    /// `pos`, returning `None` if any of the range lies outside of the data.
+    fn for_each_chunk(&self, pos: Pos, len: usize, on_chunk: &mut dyn FnMut(&[u8])) -> Option<()>;
+}
+
 /// Elimination environment.
 ///
 /// Contains enough state to run computations, but does not contain a local
@@ -404,6 +412,7 @@ impl<'arena, 'env> EvalEnv<'arena, 'env> {
 pub struct ElimEnv<'arena, 'env> {
     item_exprs: &'env SliceEnv<ArcValue<'arena>>,
     meta_exprs: &'env SliceEnv<Option<ArcValue<'arena>>>,
+    binary_data: Option<&'env dyn BinaryData>,
 }
 
 impl<'arena, 'env> ElimEnv<'arena, 'env> {
@@ -414,9 +423,24 @@ impl<'arena, 'env> ElimEnv<'arena, 'env> {
         ElimEnv {
             item_exprs,
             meta_exprs,
+            binary_data: None,
         }
     }
 
+    /// Allow primitives to inspect some binary data during evaluation.
+    /// Without this, primitives that depend on binary data remain stuck.
+    pub fn with_binary_data(self, binary_data: &'env dyn BinaryData) -> ElimEnv<'arena, 'env> {
+        ElimEnv {
+            binary_data: Some(binary_data),
+            ..self
+        }
+    }
+
+    /// The binary data that is available during evaluation, if any.
+    pub fn binary_data(&self) -> Option<&'env dyn BinaryData> {
+        self.binary_data
+    }
+
     pub fn eval_env(
         &self,
         local_exprs: &'env mut SharedEnv<ArcValue<'arena>>,
@@ -566,7 +590,7 @@ impl<'arena, 'env> ElimEnv<'arena, 'env> {
     fn const_match(
         &self,
         mut head_expr: ArcValue<'arena>,
-        mut branches: Branches<'arena, Const>,
+        mut branches: Branches<'arena, Const<'arena>>,
     ) -> ArcValue<'arena> {
         match Arc::make_mut(&mut head_expr) {
             Value::ConstLit(r#const) => {
@@ -689,6 +713,7 @@ impl<'in_arena, 'env> QuoteEnv<'in_arena, 'env> {
                         let default_branch = loop {
                             match self.elim_env.split_branches(branches) {
                                 SplitBranches::Branch((r#const, body_expr), next_branches) => {
+                                    let r#const = r#const.to_scope(scope);
                                     pattern_branches.push((r#const, self.quote(scope, &body_expr)));
                                     branches = next_branches;
                                 }
@@ -758,7 +783,7 @@ impl<'in_arena, 'env> QuoteEnv<'in_arena, 'env> {
                 self.quote_telescope(scope, formats),
             ),
 
-            Value::ConstLit(r#const) => Term::ConstLit(span, *r#const),
+            Value::ConstLit(r#const) => Term::ConstLit(span, r#const.to_scope(scope)),
         }
     }
 
@@ -935,7 +960,7 @@ impl<'arena, 'env> EvalEnv<'arena, 'env> {
 
             Term::Prim(span, prim) => Term::Prim(*span, *prim),
 
-            Term::ConstLit(span, r#const) => Term::ConstLit(*span, *r#const),
+            Term::ConstLit(span, r#const) => Term::ConstLit(*span, r#const.to_scope(scope)),
         }
     }
 
@@ -1000,10 +1025,9 @@ impl<'arena, 'env> EvalEnv<'arena, 'env> {
                     TermOrValue::Term(head_expr) => TermOrValue::Term(Term::ConstMatch(
                         *span,
                         scope.to_scope(head_expr),
-                        scope.to_scope_from_iter(
-                            (branches.iter())
-                                .map(|(r#const, expr)| (*r#const, self.unfold_metas(scope, expr))),
-                        ),
+                        scope.to_scope_from_iter((branches.iter()).map(|(r#const, expr)| {
+                            (r#const.to_scope(scope), self.unfold_metas(scope, expr))
+                        })),
                         default_branch
                             .map(|(name, expr)| (name, self.unfold_bound_metas(scope, expr))),
                     )),
@@ -1,18 +1,174 @@
 use std::cell::RefCell;
-use std::io::Read;
+use std::collections::HashMap;
+use std::io::{Read, Write};
 use std::path::Path;
 
 use codespan_reporting::diagnostic::{Diagnostic, Label, Severity};
 use codespan_reporting::files::SimpleFiles;
 use codespan_reporting::term::termcolor::{BufferedStandardStream, ColorChoice, WriteColor};
 
+use crate::core::binary::generate::GenerateError;
+use crate::core::binary::hexdump::HexdumpError;
+use crate::core::binary::write::WriteError;
 use crate::core::binary::{self, BufferError, ReadError};
 use crate::files::{FileId, Files};
 use crate::source::{ByteRange, ProgramSource, SourceTooBig, Span, MAX_SOURCE_LEN};
 use crate::surface::elaboration::ItemEnv;
 use crate::surface::{self, elaboration};
+use crate::symbol::Symbol;
 use crate::{core, BUG_REPORT_URL};
 
+mod explore;
+
+/// How binary data that was read should be displayed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DataOutput {
+    /// Display the data as Fathom terms
+    Term,
+    /// Display the data as JSON, with the references keyed by position
+    Json,
+    /// Explore the data interactively, reading commands from standard input
+    Explore,
+}
+
+/// Builds the nodes of the tree of values displayed by an explorer, as they
+/// are expanded.
+struct ExplorerBuilder<'a, 'arena, 'data> {
+    elab_context: &'a elaboration::Context<'arena>,
+    binary_context: &'a binary::Context<'arena, 'data>,
+    /// Scope used when rendering values, which is reset after each value
+    scope: scoped_arena::Scope<'static>,
+    /// The paths, formats and values of the nodes, indexed by their keys
+    values: Vec<(
+        binary::ValuePath,
+        core::semantics::ArcValue<'arena>,
+        core::semantics::ArcValue<'arena>,
+    )>,
+    /// The indices of the roots of the references, by their positions and
+    /// indices
+    ref_roots: HashMap<(usize, usize), usize>,
+}
+
+impl<'a, 'arena, 'data> ExplorerBuilder<'a, 'arena, 'data> {
+    /// Build an explorer for the references that were read.
+    fn explorer(
+        elab_context: &'a elaboration::Context<'arena>,
+        binary_context: &'a binary::Context<'arena, 'data>,
+        refs: &HashMap<usize, Vec<binary::ParsedRef<'arena>>>,
+    ) -> explore::Explorer<Self> {
+        let mut positions = refs.keys().copied().collect::<Vec<_>>();
+        positions.sort_unstable();
+        let refs = (positions.iter())
+            .flat_map(|pos| (refs[pos].iter().enumerate()).map(move |(index, r)| (*pos, index, r)))
+            .collect::<Vec<_>>();
+
+        let mut builder = ExplorerBuilder {
+            elab_context,
+            binary_context,
+            scope: scoped_arena::Scope::new(),
+            values: Vec::new(),
+            ref_roots: (refs.iter().enumerate())
+                .map(|(root, (pos, index, _))| ((*pos, *index), root))
+                .collect(),
+        };
+        let roots = (refs.into_iter())
+            .map(|(pos, ref_index, parsed_ref)| {
+                let path = binary::ValuePath {
+                    ref_pos: pos,
+                    ref_index,
+                    segments: Vec::new(),
+                };
+                let label = binary_context.pos_to_string(pos);
+                builder.node(label, path, &parsed_ref.format, &parsed_ref.expr)
+            })
+            .collect();
+
+        explore::Explorer::new(builder, roots)
+    }
+
+    /// Create a node for a value. Its children are built when it is expanded.
+    fn node(
+        &mut self,
+        label: String,
+        path: binary::ValuePath,
+        format: &core::semantics::ArcValue<'arena>,
+        expr: &core::semantics::ArcValue<'arena>,
+    ) -> explore::Node {
+        use crate::core::semantics::Value;
+
+        let has_children = !(self.binary_context.value_children(format, expr)).is_empty();
+        let summary = match expr.as_ref() {
+            Value::RecordLit(..) if has_children => "{ .. }".to_owned(),
+            Value::ArrayLit(exprs) if has_children => format!("[ {} elements ]", exprs.len()),
+            _ => self.pretty_value(expr),
+        };
+        let repr = self.elab_context.elim_env().format_repr(format);
+        let r#type = self.pretty_value(&repr);
+        let range = (self.binary_context.provenance())
+            .and_then(|provenance| provenance.get(&path))
+            .map(|range| {
+                let start = self.binary_context.pos_to_string(range.start);
+                let end = self.binary_context.pos_to_string(range.end);
+                format!("{start}..{end}")
+            });
+        let link = match expr.as_ref() {
+            Value::ConstLit(core::Const::Ref(pos, index)) => (self.binary_context)
+                .pos_to_offset(*pos)
+                .and_then(|pos| self.ref_roots.get(&(pos, *index as usize)).copied()),
+            _ => None,
+        };
+
+        let key = self.values.len();
+        self.values.push((path, format.clone(), expr.clone()));
+
+        explore::Node {
+            label,
+            summary,
+            r#type,
+            range,
+            key,
+            has_children,
+            link,
+        }
+    }
+
+    /// Render a value as a single line of text.
+    fn pretty_value(&mut self, value: &core::semantics::ArcValue<'_>) -> String {
+        let scope = &self.scope;
+        let term = self.elab_context.quote_env().quote(scope, value);
+        let pos_to_string = |pos| self.binary_context.pos_value_to_string(pos);
+        let term = (self.elab_context.distillation_context(scope))
+            .with_pos_to_string(&pos_to_string)
+            .check(&term);
+        let context = surface::pretty::Context::new(scope);
+        let text = (context.term(&term).into_doc())
+            .pretty(usize::MAX)
+            .to_string();
+
+        self.scope.reset();
+        text
+    }
+}
+
+impl<'a, 'arena, 'data> explore::Tree for ExplorerBuilder<'a, 'arena, 'data> {
+    fn children(&mut self, key: usize) -> Vec<explore::Node> {
+        let (path, format, expr) = self.values[key].clone();
+        let children = self.binary_context.value_children(&format, &expr);
+
+        (children.into_iter())
+            .map(|child| {
+                let label = match child.segment {
+                    binary::PathSegment::Field(label) => label.resolve().to_owned(),
+                    binary::PathSegment::Index(index) => format!("[{index}]"),
+                };
+                let mut path = path.clone();
+                path.segments.push(child.segment);
+                self.node(label, path, &child.format, &child.expr)
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum Status {
     Ok,
@@ -34,6 +190,14 @@ pub struct Driver<'surface, 'core> {
     core_scope: scoped_arena::Scope<'core>,
 
     allow_errors: bool,
+    recover: bool,
+    hexdump: bool,
+    data_output: DataOutput,
+    data_offset: Option<usize>,
+    data_length: Option<usize>,
+    data_select: Option<binary::select::Query>,
+    data_eval: Option<FileId>,
+    read_limits: binary::ReadLimits,
     seen_errors: RefCell<bool>,
     codespan_config: codespan_reporting::term::Config,
     diagnostic_writer: RefCell<Box<dyn WriteColor>>,
@@ -50,6 +214,14 @@ impl<'surface, 'core> Driver<'surface, 'core> {
             files: Files::new(),
 
             allow_errors: false,
+            recover: false,
+            hexdump: false,
+            data_output: DataOutput::Term,
+            data_offset: None,
+            data_length: None,
+            data_select: None,
+            data_eval: None,
+            read_limits: binary::ReadLimits::default(),
             seen_errors: RefCell::new(false),
             codespan_config: codespan_reporting::term::Config::default(),
             diagnostic_writer: RefCell::new(Box::new(BufferedStandardStream::stderr(
@@ -124,6 +296,49 @@ impl<'surface, 'core> Driver<'surface, 'core> {
         self.allow_errors = allow_errors;
     }
 
+    /// Set to true if we should attempt to continue reading binary data after
+    /// encountering errors
+    pub fn set_recover(&mut self, recover: bool) {
+        self.recover = recover;
+    }
+
+    /// Set to true if binary data should be displayed as an annotated hexdump,
+    /// instead of as the terms that were read
+    pub fn set_hexdump(&mut self, hexdump: bool) {
+        self.hexdump = hexdump;
+    }
+
+    /// Set how binary data that was read should be displayed
+    pub fn set_data_output(&mut self, data_output: DataOutput) {
+        self.data_output = data_output;
+    }
+
+    /// Set the offset in the binary data to start reading from, and the number
+    /// of bytes the format should be limited to. Positions remain relative to
+    /// the start of the binary data.
+    pub fn set_data_range(&mut self, offset: Option<usize>, length: Option<usize>) {
+        self.data_offset = offset;
+        self.data_length = length;
+    }
+
+    /// Set the limits on the resources used when reading binary data
+    pub fn set_read_limits(&mut self, read_limits: binary::ReadLimits) {
+        self.read_limits = read_limits;
+    }
+
+    /// Set a query used to select the values that are displayed from the
+    /// binary data that was read
+    pub fn set_data_select(&mut self, query: Option<binary::select::Query>) {
+        self.data_select = query;
+    }
+
+    /// Set a term to evaluate against the binary data that was read, instead
+    /// of displaying the data. The entrypoint is bound to its name in the
+    /// term, or to `data` if the format is not a name.
+    pub fn set_data_eval(&mut self, file_id: Option<FileId>) {
+        self.data_eval = file_id;
+    }
+
     /// Set the writer to use when rendering diagnostics
     pub fn set_diagnostic_writer(&mut self, stream: impl 'static + WriteColor) {
         self.diagnostic_writer = RefCell::new(Box::new(stream) as Box<dyn WriteColor>);
@@ -201,6 +416,17 @@ impl<'surface, 'core> Driver<'surface, 'core> {
         }
     }
 
+    /// Open a file for reading binary data from lazily.
+    pub fn open_stream_path(&mut self, path: &Path) -> Option<binary::StreamData> {
+        match std::fs::File::open(path).and_then(binary::StreamData::new) {
+            Ok(stream) => Some(stream),
+            Err(error) => {
+                self.emit_read_diagnostic(path.display(), error);
+                None
+            }
+        }
+    }
+
     pub fn elaborate_and_emit_module(&mut self, file_id: FileId, pretty_core: bool) -> Status {
         let mut context = elaboration::Context::new(file_id, &self.core_scope, ItemEnv::new());
 
@@ -281,23 +507,385 @@ impl<'surface, 'core> Driver<'surface, 'core> {
         &mut self,
         module_file_id: Option<FileId>,
         format_file_id: FileId,
-        buffer_data: &[u8],
+        initial_buffer: binary::Buffer<'_>,
     ) -> Status {
         use itertools::Itertools;
 
-        let initial_buffer = binary::Buffer::from(buffer_data);
-        let mut binary_context = binary::Context::new(initial_buffer);
+        let mut binary_context = binary::Context::new(&self.core_scope, initial_buffer);
+        binary_context.set_recover(self.recover);
+        binary_context.set_limits(self.read_limits);
+        binary_context
+            .set_record_provenance(self.hexdump || self.data_output == DataOutput::Explore);
+        let (mut elab_context, format) = match self.elaborate_format(
+            &self.core_scope,
+            module_file_id,
+            format_file_id,
+            |module| binary_context.add_module(module),
+        ) {
+            Some(elaborated) => elaborated,
+            None => return Status::Error,
+        };
+
+        let offset = (self.data_offset).unwrap_or_else(|| initial_buffer.start_offset());
+        let refs = match binary_context.read_entrypoint_at(&format, offset, self.data_length) {
+            Ok(refs) => refs,
+            Err(err) => {
+                let diagnostic =
+                    self.read_error_to_diagnostic(err, &mut elab_context, &binary_context);
+                self.emit_diagnostic(diagnostic);
+                return Status::Error;
+            }
+        };
+
+        // Render the data we have read
+        let mut status = Status::Ok;
+        if self.hexdump {
+            if let Err(err) = self.emit_hexdump(&binary_context, initial_buffer) {
+                let diagnostic = match err {
+                    HexdumpError::Read(err) => self.read_error_to_diagnostic(
+                        err.into(),
+                        &mut elab_context,
+                        &binary_context,
+                    ),
+                    HexdumpError::Write(err) => {
+                        Diagnostic::error().with_message(format!("couldn't write hexdump: {err}"))
+                    }
+                };
+                self.emit_diagnostic(diagnostic);
+                return Status::Error;
+            }
+        } else if let Some(query) = &self.data_select {
+            let entrypoint_name = self.entrypoint_name(format_file_id);
+            status = self.emit_selections(
+                &elab_context,
+                &binary_context,
+                &refs,
+                offset,
+                entrypoint_name,
+                query,
+            );
+        } else if let Some(eval_file_id) = self.data_eval {
+            // Elaborate the term with the items of the module in scope, and
+            // with the entrypoint bound to a local definition
+            let name = (self.entrypoint_name(format_file_id))
+                .unwrap_or_else(|| Symbol::intern_static("data"));
+            let format = elab_context.eval_env().eval(&format);
+            elab_context =
+                elaboration::Context::new(eval_file_id, &self.core_scope, elab_context.finish());
+            let r#type = elab_context.elim_env().format_repr(&format);
+            elab_context.define_local(name, refs[&offset][0].expr.clone(), r#type);
+
+            let surface_term = self.parse_term(eval_file_id);
+            let (term, r#type) =
+                elab_context.elab_term(&self.core_scope, &surface_term, &mut |m| {
+                    self.emit_diagnostic(m.to_diagnostic());
+                });
+
+            if *self.seen_errors.borrow() && !self.allow_errors {
+                return Status::Error;
+            }
+
+            let term = elab_context.eval_env().normalize(&self.core_scope, &term);
+            let r#type = elab_context.eval_env().normalize(&self.core_scope, &r#type);
+
+            let mut context = elab_context.distillation_context(&self.surface_scope);
+            let term = context.check(&term);
+            let r#type = context.check(&r#type);
+
+            self.emit_term(&surface::Term::Ann((), &term, &r#type));
+        } else if self.data_output == DataOutput::Json {
+            let json = binary::json::refs_to_json(&self.core_scope, &binary_context, &refs);
+            self.emit_json(&json);
+        } else if self.data_output == DataOutput::Explore {
+            let mut explorer = ExplorerBuilder::explorer(&elab_context, &binary_context, &refs);
+            let mut emit_writer = self.emit_writer.borrow_mut();
+            if let Err(error) = explorer.run(std::io::stdin().lock(), &mut *emit_writer) {
+                let diagnostic =
+                    Diagnostic::error().with_message(format!("couldn't explore data: {error}"));
+                self.emit_diagnostic(diagnostic);
+                return Status::Error;
+            }
+        } else {
+            let pos_to_string = |pos| binary_context.pos_value_to_string(pos);
+            for (pos, parsed_refs) in refs.into_iter().sorted_by_key(|(pos, _)| *pos) {
+                self.surface_scope.reset(); // Reuse the surface scope for distillation
+
+                let exprs = parsed_refs.iter().map(|parsed_ref| {
+                    let core_scope = &self.core_scope;
+                    let surface_scope = &self.surface_scope;
+                    // Omit the data that was skipped over while reading
+                    let expr = binary_context.hide_skipped(
+                        core_scope,
+                        &parsed_ref.format,
+                        &parsed_ref.expr,
+                    );
+                    let expr = elab_context.quote_env().quote(core_scope, &expr);
+                    elab_context
+                        .distillation_context(surface_scope)
+                        .with_pos_to_string(&pos_to_string)
+                        .check(&expr)
+                });
+
+                self.emit_ref(binary_context.pos_to_string(pos), exprs.collect());
+            }
+        }
+
+        // Report the errors that were recovered from while reading
+        let error_nodes = binary_context.error_nodes();
+        for error_node in error_nodes {
+            let pos = binary_context.pos_to_string(error_node.pos);
+            let diagnostic = self.read_error_to_diagnostic(
+                error_node.error.clone(),
+                &mut elab_context,
+                &binary_context,
+            );
+            let diagnostic = diagnostic.with_notes(vec![format!(
+                "Reading was resumed after this error, which occurred at {pos}."
+            )]);
+            self.emit_diagnostic(diagnostic);
+        }
+
+        match error_nodes {
+            [] => status,
+            [_, ..] => Status::Error,
+        }
+    }
+
+    pub fn generate_and_emit_format(
+        &mut self,
+        module_file_id: Option<FileId>,
+        format_file_id: FileId,
+        seed: u64,
+    ) -> Status {
+        let mut generate_context = binary::generate::Context::new(&self.core_scope, seed);
+        let (mut elab_context, format) = match self.elaborate_format(
+            &self.core_scope,
+            module_file_id,
+            format_file_id,
+            |module| generate_context.add_module(module),
+        ) {
+            Some(elaborated) => elaborated,
+            None => return Status::Error,
+        };
+
+        match generate_context.generate_entrypoint(&format) {
+            Ok(data) => {
+                let mut emit_writer = self.emit_writer.borrow_mut();
+                emit_writer.write_all(&data).unwrap();
+                emit_writer.flush().unwrap();
+                Status::Ok
+            }
+            Err(err) => {
+                let diagnostic = self.generate_error_to_diagnostic(err, &mut elab_context);
+                self.emit_diagnostic(diagnostic);
+                Status::Error
+            }
+        }
+    }
+
+    /// Read binary data, write the values that were read back with the same
+    /// format, and check that the same data was written.
+    pub fn roundtrip_format(
+        &mut self,
+        module_file_id: Option<FileId>,
+        format_file_id: FileId,
+        data: &[u8],
+    ) -> Status {
+        let mut binary_context = binary::Context::new(&self.core_scope, binary::Buffer::from(data));
+        binary_context.set_limits(self.read_limits);
+        binary_context.set_record_provenance(true);
+        let mut write_context = binary::write::Context::new();
+        let (mut elab_context, format) = match self.elaborate_format(
+            &self.core_scope,
+            module_file_id,
+            format_file_id,
+            |module| {
+                binary_context.add_module(module);
+                write_context.add_module(module);
+            },
+        ) {
+            Some(elaborated) => elaborated,
+            None => return Status::Error,
+        };
+
+        let refs = match binary_context.read_entrypoint(&format) {
+            Ok(refs) => refs,
+            Err(err) => {
+                let diagnostic =
+                    self.read_error_to_diagnostic(err, &mut elab_context, &binary_context);
+                self.emit_diagnostic(diagnostic);
+                return Status::Error;
+            }
+        };
+
+        let expr = refs[&0][0].expr.clone();
+        write_context.set_refs(refs);
+        let written_data = match write_context.write_entrypoint(&format, &expr) {
+            Ok(written_data) => written_data,
+            Err(err) => {
+                self.emit_diagnostic(
+                    Diagnostic::error()
+                        .with_message(err.to_string())
+                        .with_labels(
+                            label_for_span(&write_error_span(&err))
+                                .into_iter()
+                                .collect(),
+                        )
+                        .with_notes(vec![
+                            "The data that was read could not be written back with this format."
+                                .to_owned(),
+                        ]),
+                );
+                return Status::Error;
+            }
+        };
+
+        let provenance = binary_context.provenance().unwrap();
+        let mismatch = match binary::write::first_mismatch(provenance, data, &written_data) {
+            Some(mismatch) => mismatch,
+            None => return Status::Ok,
+        };
+
+        let byte_to_string = |byte: Option<u8>| match byte {
+            Some(byte) => format!("{byte:#04x}"),
+            None => "nothing".to_owned(),
+        };
+        let offset = binary_context.pos_to_string(mismatch.offset);
+        let mut notes = vec![format!(
+            "The byte at offset {offset} was read as {}, but was written as {}.",
+            byte_to_string(mismatch.expected),
+            byte_to_string(mismatch.found),
+        )];
+        let span = match &mismatch.path {
+            Some(path) => {
+                let pos = binary_context.pos_to_string(path.ref_pos);
+                notes.push(match (path.ref_pos, path.ref_index, &path.segments[..]) {
+                    (0, 0, []) => "This byte was read as part of the entrypoint.".to_owned(),
+                    (0, 0, _) => format!("This byte was read as part of `{path}`."),
+                    (_, _, []) => {
+                        format!("This byte was read as part of the data linked to at {pos}.")
+                    }
+                    (_, _, _) => format!(
+                        "This byte was read as part of `{path}` in the data linked to at {pos}."
+                    ),
+                });
+                provenance.get(path).map_or(Span::Empty, |range| range.span)
+            }
+            None => Span::Empty,
+        };
+
+        self.emit_diagnostic(
+            Diagnostic::error()
+                .with_message("written data differs from the data that was read")
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(notes),
+        );
+
+        Status::Error
+    }
+
+    /// The name of the entrypoint, if the format is a single name like `main`.
+    fn entrypoint_name(&self, format_file_id: FileId) -> Option<Symbol> {
+        let source = self.files.get(format_file_id).unwrap().source().trim();
+        let mut chars = source.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+            _ => return None,
+        }
+        match chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            true => Some(Symbol::intern(source)),
+            false => None,
+        }
+    }
+
+    /// Emit the values selected by a query from the data that was read. The
+    /// query may start with the name of the entrypoint, eg. `main.header`.
+    fn emit_selections<'arena>(
+        &self,
+        elab_context: &elaboration::Context<'arena>,
+        binary_context: &binary::Context<'arena, '_>,
+        refs: &HashMap<usize, Vec<binary::ParsedRef<'arena>>>,
+        entrypoint_pos: usize,
+        entrypoint_name: Option<Symbol>,
+        query: &binary::select::Query,
+    ) -> Status {
+        let stripped_query = entrypoint_name.and_then(|name| query.strip_prefix(name));
+        let selections = binary::select::select(
+            binary_context,
+            refs,
+            entrypoint_pos,
+            stripped_query.as_ref().unwrap_or(query),
+        );
+        if selections.is_empty() {
+            let message = format!("no values matched `{query}`");
+            self.emit_diagnostic(Diagnostic::error().with_message(message));
+            return Status::Error;
+        }
+
+        // Keep the name of the entrypoint in the paths that are displayed
+        let prefix = stripped_query.as_ref().and(entrypoint_name);
+        let label = |selection: &binary::select::Selection<'_>| match (prefix, selection.label()) {
+            (None, label) => label,
+            (Some(name), label) if label.is_empty() => name.resolve().to_owned(),
+            (Some(name), label) if label.starts_with('[') => format!("{}{label}", name.resolve()),
+            (Some(name), label) => format!("{}.{label}", name.resolve()),
+        };
+
+        if self.data_output == DataOutput::Json {
+            let json = (selections.iter())
+                .map(|selection| {
+                    let expr = binary_context.hide_skipped(
+                        &self.core_scope,
+                        &selection.format,
+                        &selection.expr,
+                    );
+                    (
+                        label(selection),
+                        binary::json::value_to_json(binary_context, &expr),
+                    )
+                })
+                .collect();
+            self.emit_json(&serde_json::Value::Object(json));
+        } else {
+            let pos_to_string = |pos| binary_context.pos_value_to_string(pos);
+            for selection in &selections {
+                let core_scope = &self.core_scope;
+                let expr =
+                    binary_context.hide_skipped(core_scope, &selection.format, &selection.expr);
+                let expr = elab_context.quote_env().quote(core_scope, &expr);
+                let expr = (elab_context.distillation_context(&self.surface_scope))
+                    .with_pos_to_string(&pos_to_string)
+                    .check(&expr);
+                self.emit_selection(label(selection), &expr);
+            }
+        }
+
+        Status::Ok
+    }
+
+    /// Parse and elaborate a format, with the items of a module in scope if
+    /// one was provided. The elaborated module is passed to `add_module`, so
+    /// that it can be added to the contexts that will use the format.
+    ///
+    /// Returns `None` if any errors were seen, unless `allow_errors` is
+    /// enabled.
+    fn elaborate_format<'arena>(
+        &self,
+        core_scope: &'arena scoped_arena::Scope<'arena>,
+        module_file_id: Option<FileId>,
+        format_file_id: FileId,
+        mut add_module: impl FnMut(&core::Module<'arena>),
+    ) -> Option<(elaboration::Context<'arena>, core::Term<'arena>)> {
         let mut item_env = ItemEnv::new();
 
         // Parse and elaborate a module if one was provided
         if let Some(file_id) = module_file_id {
-            let mut elab_context = elaboration::Context::new(file_id, &self.core_scope, item_env);
+            let mut elab_context = elaboration::Context::new(file_id, core_scope, item_env);
             let surface_module = self.parse_module(file_id);
-            let module = elab_context.elab_module(&self.core_scope, &surface_module, &mut |m| {
+            let module = elab_context.elab_module(core_scope, &surface_module, &mut |m| {
                 self.emit_diagnostic(m.to_diagnostic());
             });
-            // Add it to the binary context
-            binary_context.add_module(&module);
+            add_module(&module);
             item_env = elab_context.finish();
         }
 
@@ -305,43 +893,18 @@ impl<'surface, 'core> Driver<'surface, 'core> {
         // supplied in the module in scope. This is still a bit of a hack, and
         // will need to be revisited if we need to support multiple modules, but
         // it works for now!
-        let mut elab_context =
-            elaboration::Context::new(format_file_id, &self.core_scope, item_env);
+        let mut elab_context = elaboration::Context::new(format_file_id, core_scope, item_env);
         let surface_format = self.parse_term(format_file_id);
-        let format = elab_context.elab_format(&self.core_scope, &surface_format, &mut |m| {
+        let format = elab_context.elab_format(core_scope, &surface_format, &mut |m| {
             self.emit_diagnostic(m.to_diagnostic());
         });
 
         // Return early if we’ve seen any errors, unless `allow_errors` is enabled
         if *self.seen_errors.borrow() && !self.allow_errors {
-            return Status::Error;
-        }
-
-        let refs = match binary_context.read_entrypoint(&format) {
-            Ok(refs) => refs,
-            Err(err) => {
-                self.emit_diagnostic(self.read_error_to_diagnostic(err, &mut elab_context));
-                return Status::Error;
-            }
-        };
-
-        // Render the data we have read
-        for (pos, parsed_refs) in refs.into_iter().sorted_by_key(|(pos, _)| *pos) {
-            self.surface_scope.reset(); // Reuse the surface scope for distillation
-
-            let exprs = parsed_refs.iter().map(|parsed_ref| {
-                let core_scope = &self.core_scope;
-                let surface_scope = &self.surface_scope;
-                let expr = elab_context.quote_env().quote(core_scope, &parsed_ref.expr);
-                elab_context
-                    .distillation_context(surface_scope)
-                    .check(&expr)
-            });
-
-            self.emit_ref(pos, exprs.collect());
+            return None;
         }
 
-        Status::Ok
+        Some((elab_context, format))
     }
 
     fn parse_module(&'surface self, file_id: FileId) -> surface::Module<'surface, ByteRange> {
@@ -379,11 +942,26 @@ impl<'surface, 'core> Driver<'surface, 'core> {
         self.emit_doc(context.term(term).into_doc());
     }
 
-    fn emit_ref(&self, pos: usize, exprs: Vec<surface::Term<'_, ()>>) {
+    fn emit_json(&self, json: &serde_json::Value) {
+        let mut emit_writer = self.emit_writer.borrow_mut();
+        serde_json::to_writer_pretty(&mut *emit_writer, json).unwrap();
+        writeln!(emit_writer).unwrap();
+        emit_writer.flush().unwrap();
+    }
+
+    fn emit_hexdump(
+        &self,
+        binary_context: &binary::Context<'_, '_>,
+        initial_buffer: binary::Buffer<'_>,
+    ) -> Result<(), HexdumpError> {
+        let mut emit_writer = self.emit_writer.borrow_mut();
+        binary::hexdump::write_hexdump(&mut *emit_writer, binary_context, initial_buffer)
+    }
+
+    fn emit_ref(&self, pos: String, exprs: Vec<surface::Term<'_, ()>>) {
         use pretty::DocAllocator;
 
         let context = surface::pretty::Context::new(&self.surface_scope);
-        let pos = pos.to_string();
         let doc = context
             .concat([
                 context.text(pos),
@@ -403,6 +981,23 @@ impl<'surface, 'core> Driver<'surface, 'core> {
         self.emit_doc(doc);
     }
 
+    fn emit_selection(&self, label: String, expr: &surface::Term<'_, ()>) {
+        use pretty::DocAllocator;
+
+        let context = surface::pretty::Context::new(&self.surface_scope);
+        let doc = context
+            .concat([
+                context.text(label),
+                context.space(),
+                context.text("="),
+                context.space(),
+                context.term(expr),
+            ])
+            .into_doc();
+
+        self.emit_doc(doc);
+    }
+
     fn emit_doc(&self, doc: pretty::RefDoc) {
         let mut emit_writer = self.emit_writer.borrow_mut();
         writeln!(emit_writer, "{}", doc.pretty(self.emit_width)).unwrap();
@@ -445,6 +1040,7 @@ impl<'surface, 'core> Driver<'surface, 'core> {
         &self,
         err: ReadError<'_>,
         context: &mut elaboration::Context,
+        binary_context: &binary::Context<'_, '_>,
     ) -> Diagnostic<FileId> {
         match err {
             ReadError::ReadFailFormat(span) => Diagnostic::error()
@@ -457,7 +1053,10 @@ impl<'surface, 'core> Driver<'surface, 'core> {
                 let core_scope = &self.core_scope;
                 let surface_scope = &self.surface_scope;
                 let expr = context.quote_env().quote(core_scope, value);
-                let surface_term = context.distillation_context(surface_scope).check(&expr);
+                let pos_to_string = |pos| binary_context.pos_value_to_string(pos);
+                let surface_term = (context.distillation_context(surface_scope))
+                    .with_pos_to_string(&pos_to_string)
+                    .check(&expr);
                 let pretty_context = surface::pretty::Context::new(surface_scope);
                 let doc = pretty_context.term(&surface_term).into_doc();
 
@@ -469,6 +1068,158 @@ impl<'surface, 'core> Driver<'surface, 'core> {
                         format!("failed value: {}", doc.pretty(self.emit_width)),
                     ])
             }
+            ReadError::InvalidBitCount(span, count) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "{count} bits do not fit in the representation of this format."
+                )]),
+            ReadError::VarintOverflow(span, offset) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "The integer starting at offset {} does not fit in 64 bits.",
+                    binary_context.pos_to_string(offset),
+                )]),
+            ReadError::InvalidString(span, offset, encoding) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "The string starting at offset {} is not valid {encoding}.",
+                    binary_context.pos_to_string(offset),
+                )]),
+            ReadError::DecompressionFailed(span, offset, compression) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "The data starting at offset {} could not be decompressed as {compression}.",
+                    binary_context.pos_to_string(offset),
+                )]),
+            ReadError::RepeatWithoutProgress(span) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "An element was read without consuming any data, so the repetition would never end."
+                )]),
+            ReadError::ReservedMismatch(span, ref expected, ref found) => {
+                let core_scope = &self.core_scope;
+                let surface_scope = &self.surface_scope;
+                let pretty_context = surface::pretty::Context::new(surface_scope);
+                let pos_to_string = |pos| binary_context.pos_value_to_string(pos);
+                let pretty_value = |value| {
+                    let expr = context.quote_env().quote(core_scope, value);
+                    let surface_term = (context.distillation_context(surface_scope))
+                        .with_pos_to_string(&pos_to_string)
+                        .check(&expr);
+                    let doc = pretty_context.term(&surface_term).into_doc();
+                    doc.pretty(self.emit_width).to_string()
+                };
+
+                Diagnostic::error()
+                    .with_message(err.to_string())
+                    .with_labels(label_for_span(&span).into_iter().collect())
+                    .with_notes(vec![
+                        "The data read by a reserved format did not match the expected value."
+                            .to_string(),
+                        format!("expected value: {}", pretty_value(expected)),
+                        format!("found value: {}", pretty_value(found)),
+                    ])
+            }
+            ReadError::ExpectedEndOfInput(span, len) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "{len} bytes were left unread at the end of the input."
+                )]),
+            ReadError::UnconsumedLimit(span, len) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "The limited format did not read the final {len} bytes of the limit."
+                )]),
+            ReadError::LinkCycle(span, ref chain) => {
+                let chain = (chain.iter())
+                    .map(|pos| binary_context.pos_to_string(*pos))
+                    .collect::<Vec<_>>();
+
+                Diagnostic::error()
+                    .with_message(err.to_string())
+                    .with_labels(label_for_span(&span).into_iter().collect())
+                    .with_notes(vec![
+                        "A reference was dereferenced while it was still being read.".to_string(),
+                        format!("link chain: {}", chain.join(" -> ")),
+                    ])
+            }
+            ReadError::ArrayLimitExceeded(span, len) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "The array has at least {len} elements, but at most {} are allowed by `--max-array-len`.",
+                    limit_to_string(binary_context.limits().max_array_len),
+                )]),
+            ReadError::ValueLimitExceeded(span) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "At most {} values can be read, as set by `--max-values`.",
+                    limit_to_string(binary_context.limits().max_values),
+                )]),
+            ReadError::DepthLimitExceeded(span) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "Formats can be nested at most {} deep, as set by `--max-depth`.",
+                    limit_to_string(binary_context.limits().max_depth),
+                )]),
+            ReadError::PendingLinkLimitExceeded(span) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "At most {} links can be waiting to be read, as set by `--max-pending-links`.",
+                    limit_to_string(binary_context.limits().max_pending_links),
+                )]),
+            ReadError::StepLimitExceeded(span) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "At most {} formats can be read, as set by `--max-steps`.",
+                    limit_to_string(binary_context.limits().max_steps),
+                )]),
+            ReadError::DecompressedLimitExceeded(span) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "At most {} bytes can be decompressed, as set by `--max-decompressed-len`.",
+                    limit_to_string(binary_context.limits().max_decompressed_len),
+                )]),
+            ReadError::ChoiceFailure(span, ref errors) => {
+                let mut labels = label_for_span(&span).into_iter().collect::<Vec<_>>();
+                let mut notes = Vec::with_capacity(errors.len());
+
+                // Combine the diagnostics of each of the alternatives
+                for (index, err) in errors.iter().enumerate() {
+                    let diagnostic =
+                        self.read_error_to_diagnostic(err.clone(), context, binary_context);
+                    labels.extend(diagnostic.labels.into_iter().map(|label| {
+                        let message = match label.message.as_str() {
+                            "" => format!("alternative {index}: {}", diagnostic.message),
+                            message => format!("alternative {index}: {message}"),
+                        };
+                        Label::secondary(label.file_id, label.range).with_message(message)
+                    }));
+                    let mut note = format!("alternative {index}: {}", diagnostic.message);
+                    for alternative_note in &diagnostic.notes {
+                        note.push_str("\n  ");
+                        note.push_str(alternative_note);
+                    }
+                    notes.push(note);
+                }
+
+                Diagnostic::error()
+                    .with_message(err.to_string())
+                    .with_labels(labels)
+                    .with_notes(notes)
+            }
             ReadError::UnwrappedNone(_) => Diagnostic::error()
                 .with_message(err.to_string())
                 .with_notes(vec![format!("option_unwrap was called on a none value.")]),
@@ -487,6 +1238,73 @@ impl<'surface, 'core> Driver<'surface, 'core> {
         }
     }
 
+    fn generate_error_to_diagnostic(
+        &self,
+        err: GenerateError<'_>,
+        context: &mut elaboration::Context,
+    ) -> Diagnostic<FileId> {
+        let note = "No data could be generated for this format, even after retrying.";
+        match err {
+            // Errors from reading back the generated data do not refer to the
+            // data itself, so an empty buffer suffices for reporting them
+            GenerateError::ReadError(err) => {
+                let binary_context =
+                    binary::Context::new(&self.core_scope, binary::Buffer::from(&[][..]));
+                let mut diagnostic = self.read_error_to_diagnostic(err, context, &binary_context);
+                diagnostic.notes.push(note.to_owned());
+                diagnostic
+            }
+            GenerateError::WriteError(ref write_err) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(
+                    label_for_span(&write_error_span(write_err))
+                        .into_iter()
+                        .collect(),
+                )
+                .with_notes(vec![note.to_owned()]),
+            GenerateError::CondFailure(span) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![
+                    "No value was found that satisfies this predicate.".to_owned(),
+                    note.to_owned(),
+                ]),
+            GenerateError::TooLarge(span) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![
+                    "The data generated for this format was too long.".to_owned(),
+                    note.to_owned(),
+                ]),
+            GenerateError::ChoiceFailure(span) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![
+                    "No alternative was generated that would be read back as the same alternative."
+                        .to_owned(),
+                    note.to_owned(),
+                ]),
+            GenerateError::GenerateFailFormat(span) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "A fail format was encountered when generating data."
+                )]),
+            GenerateError::UnwrappedNone(span) => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!("option_unwrap was called on a none value.")]),
+            GenerateError::InvalidFormat(span) | GenerateError::InvalidValue(span) => {
+                Diagnostic::bug()
+                    .with_message(format!("unexpected error '{err}'"))
+                    .with_labels(label_for_span(&span).into_iter().collect())
+                    .with_notes(vec![format!(
+                        "please file a bug report at: {BUG_REPORT_URL}"
+                    )])
+            }
+        }
+    }
+
     fn buffer_error_to_diagnostic(&self, err: BufferError, span: Span) -> Diagnostic<FileId> {
         match err {
             BufferError::UnexpectedEndOfBuffer => Diagnostic::error()
@@ -515,11 +1333,23 @@ impl<'surface, 'core> Driver<'surface, 'core> {
                 .with_notes(vec![format!(
                     "The offset is beyond the end of the buffer (overflow).",
                 )]),
-            BufferError::PositionOverflow => Diagnostic::bug()
-                .with_message(format!("unexpected error '{err}'"))
+            BufferError::UnalignedRead => Diagnostic::error()
+                .with_message(err.to_string())
                 .with_labels(label_for_span(&span).into_iter().collect())
                 .with_notes(vec![format!(
-                    "please file a bug report at: {BUG_REPORT_URL}"
+                    "Use `byte_align` to skip the remaining bits of a byte before reading bytes."
+                )]),
+            BufferError::ReadFailed { offset, .. } => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "The data at offset {offset} could not be read from the input."
+                )]),
+            BufferError::PositionOverflow => Diagnostic::error()
+                .with_message(err.to_string())
+                .with_labels(label_for_span(&span).into_iter().collect())
+                .with_notes(vec![format!(
+                    "The position is too large to be represented."
                 )]),
         }
     }
@@ -531,3 +1361,30 @@ fn label_for_span(span: &Span) -> Option<Label<FileId>> {
         Span::Empty => None,
     }
 }
+
+fn write_error_span(err: &WriteError<'_>) -> Span {
+    match *err {
+        WriteError::InvalidFormat(span)
+        | WriteError::InvalidValue(span)
+        | WriteError::UnwrappedNone(span)
+        | WriteError::WriteFailFormat(span)
+        | WriteError::CondFailure(span, _)
+        | WriteError::LenMismatch { span, .. }
+        | WriteError::LimitExceeded { span, .. }
+        | WriteError::LimitNotFilled { span, .. }
+        | WriteError::UnknownRef(span, _)
+        | WriteError::InvalidBitCount(span, _)
+        | WriteError::UnalignedWrite(span)
+        | WriteError::UnencodableString(span, _)
+        | WriteError::CompressedFormat(span, _)
+        | WriteError::MapFormat(span)
+        | WriteError::PositionOverflow(span) => span,
+    }
+}
+
+fn limit_to_string(limit: Option<impl std::fmt::Display>) -> String {
+    match limit {
+        Some(limit) => limit.to_string(),
+        None => "unlimited".to_owned(),
+    }
+}
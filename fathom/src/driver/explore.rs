@@ -0,0 +1,418 @@
+//! An interactive explorer for data that was read.
+//!
+//! The explorer displays the references that were read as a tree that can be
+//! navigated using commands read from a line-based input, so that it can be
+//! used over plain standard input and output.
+
+use std::io::{self, BufRead};
+
+use codespan_reporting::term::termcolor::{Color, ColorSpec, WriteColor};
+
+/// A node in the tree of data that was read.
+pub struct Node {
+    /// The label of the node, which is the name of a field, the index of an
+    /// array element, or the position of a reference
+    pub label: String,
+    /// A short description of the value of the node
+    pub summary: String,
+    /// The type of the value of the node
+    pub r#type: String,
+    /// The range of data that the value was read from, if known
+    pub range: Option<String>,
+    /// The key used to build the children of the node with [`Tree::children`]
+    pub key: usize,
+    pub has_children: bool,
+    /// The index of the root of the reference that this node links to
+    pub link: Option<usize>,
+}
+
+/// Builds the children of nodes as they are expanded, so that only the parts
+/// of the tree that are explored need to be built.
+pub trait Tree {
+    /// The children of the node that was created with `key`. This is called
+    /// at most once for each node.
+    fn children(&mut self, key: usize) -> Vec<Node>;
+}
+
+struct Entry {
+    node: Node,
+    parent: Option<usize>,
+    /// The children of the node, if they have been built
+    children: Option<Vec<usize>>,
+    expanded: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Command {
+    Down,
+    Up,
+    Expand,
+    Collapse,
+    Toggle,
+    Follow,
+    Back,
+    Help,
+    Quit,
+}
+
+impl Command {
+    fn parse(src: &str) -> Option<Command> {
+        match src.trim() {
+            "j" | "down" => Some(Command::Down),
+            "k" | "up" => Some(Command::Up),
+            "l" | "expand" => Some(Command::Expand),
+            "h" | "collapse" => Some(Command::Collapse),
+            "" | "t" | "toggle" => Some(Command::Toggle),
+            "f" | "follow" => Some(Command::Follow),
+            "b" | "back" => Some(Command::Back),
+            "?" | "help" => Some(Command::Help),
+            "q" | "quit" => Some(Command::Quit),
+            _ => None,
+        }
+    }
+}
+
+const HELP: &str = "\
+commands:
+  j, down       select the next node
+  k, up         select the previous node
+  l, expand     expand the selected node
+  h, collapse   collapse the selected node, or select its parent
+  t, toggle     expand or collapse the selected node (or press enter)
+  f, follow     follow the reference at the selected node
+  b, back       return to where the last reference was followed from
+  ?, help       display this help
+  q, quit       exit the explorer";
+
+pub struct Explorer<T> {
+    tree: T,
+    entries: Vec<Entry>,
+    /// The entries of the references that were read
+    roots: Vec<usize>,
+    selected: usize,
+    /// The entries that references were followed from
+    history: Vec<usize>,
+}
+
+impl<T: Tree> Explorer<T> {
+    /// Create an explorer for the roots of a tree, with the first root
+    /// expanded and selected.
+    pub fn new(tree: T, roots: Vec<Node>) -> Explorer<T> {
+        let mut explorer = Explorer {
+            tree,
+            entries: Vec::with_capacity(roots.len()),
+            roots: (0..roots.len()).collect(),
+            selected: 0,
+            history: Vec::new(),
+        };
+        explorer.push_entries(None, roots);
+        if !explorer.entries.is_empty() {
+            explorer.expand(0);
+        }
+
+        explorer
+    }
+
+    /// Run the explorer, reading commands from the input until it is closed
+    /// or the explorer is quit.
+    pub fn run(&mut self, input: impl BufRead, output: &mut dyn WriteColor) -> io::Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+
+        self.render(output)?;
+        for line in input.lines() {
+            let line = line?;
+            match Command::parse(&line) {
+                Some(Command::Quit) => break,
+                Some(Command::Help) => writeln!(output, "{HELP}")?,
+                Some(command) => {
+                    if let Err(message) = self.handle(command) {
+                        writeln!(output, "{message}")?;
+                    }
+                }
+                None => writeln!(output, "unknown command `{}`, try `?`", line.trim())?,
+            }
+            self.render(output)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, command: Command) -> Result<(), &'static str> {
+        match command {
+            Command::Down => self.selected = self.next(self.selected).ok_or("at last node")?,
+            Command::Up => self.selected = self.previous(self.selected).ok_or("at first node")?,
+            Command::Expand => match self.entries[self.selected].node.has_children {
+                true => self.expand(self.selected),
+                false => return Err("node has no children"),
+            },
+            Command::Collapse => match self.entries[self.selected].expanded {
+                true => self.entries[self.selected].expanded = false,
+                false => {
+                    self.selected = self.entries[self.selected].parent.ok_or("at a root node")?
+                }
+            },
+            Command::Toggle => match self.entries[self.selected] {
+                Entry { expanded: true, .. } => self.entries[self.selected].expanded = false,
+                Entry { ref node, .. } if node.has_children => self.expand(self.selected),
+                _ => return Err("node has no children"),
+            },
+            Command::Follow => {
+                let link = self.entries[self.selected]
+                    .node
+                    .link
+                    .ok_or("node is not a reference")?;
+                self.history.push(self.selected);
+                self.selected = self.roots[link];
+                self.expand(self.selected);
+            }
+            Command::Back => {
+                let entry = self.history.pop().ok_or("no reference has been followed")?;
+                // Make sure the node that we return to is visible
+                let mut parent = self.entries[entry].parent;
+                while let Some(entry) = parent {
+                    self.entries[entry].expanded = true;
+                    parent = self.entries[entry].parent;
+                }
+                self.selected = entry;
+            }
+            Command::Help | Command::Quit => {}
+        }
+
+        Ok(())
+    }
+
+    fn push_entries(&mut self, parent: Option<usize>, nodes: Vec<Node>) -> Vec<usize> {
+        let start = self.entries.len();
+        self.entries.extend(nodes.into_iter().map(|node| Entry {
+            node,
+            parent,
+            children: None,
+            expanded: false,
+        }));
+        (start..self.entries.len()).collect()
+    }
+
+    /// Expand an entry, building its children if this is the first time that
+    /// it has been expanded.
+    fn expand(&mut self, entry: usize) {
+        if self.entries[entry].children.is_none() {
+            let nodes = self.tree.children(self.entries[entry].node.key);
+            let children = self.push_entries(Some(entry), nodes);
+            self.entries[entry].children = Some(children);
+        }
+        self.entries[entry].expanded = true;
+    }
+
+    /// The children of an entry that are currently visible.
+    fn visible_children(&self, entry: usize) -> &[usize] {
+        match &self.entries[entry] {
+            Entry {
+                expanded: true,
+                children: Some(children),
+                ..
+            } => children,
+            _ => &[],
+        }
+    }
+
+    /// The entry and its siblings, along with the index of the entry among
+    /// them.
+    fn siblings(&self, entry: usize) -> (&[usize], usize) {
+        let siblings = match self.entries[entry].parent {
+            Some(parent) => self.entries[parent].children.as_deref().unwrap_or(&[]),
+            None => &self.roots,
+        };
+        // Entries are created in the order of their siblings
+        let index = siblings.binary_search(&entry).unwrap();
+        (siblings, index)
+    }
+
+    /// The visible entry after an entry.
+    fn next(&self, entry: usize) -> Option<usize> {
+        if let Some(child) = self.visible_children(entry).first() {
+            return Some(*child);
+        }
+
+        let mut entry = entry;
+        loop {
+            match self.siblings(entry) {
+                (siblings, index) if index + 1 < siblings.len() => {
+                    return Some(siblings[index + 1])
+                }
+                _ => entry = self.entries[entry].parent?,
+            }
+        }
+    }
+
+    /// The visible entry before an entry.
+    fn previous(&self, entry: usize) -> Option<usize> {
+        let mut entry = match self.siblings(entry) {
+            (_, 0) => return self.entries[entry].parent,
+            (siblings, index) => siblings[index - 1],
+        };
+        while let Some(child) = self.visible_children(entry).last() {
+            entry = *child;
+        }
+        Some(entry)
+    }
+
+    /// Render the selected node and the nodes visible within it, along with
+    /// the nodes that contain it. The rest of the tree is not rendered, so
+    /// that the output of each command stays small.
+    fn render(&self, output: &mut dyn WriteColor) -> io::Result<()> {
+        writeln!(output)?;
+
+        let mut ancestors = Vec::new();
+        let mut parent = self.entries[self.selected].parent;
+        while let Some(entry) = parent {
+            ancestors.push(entry);
+            parent = self.entries[entry].parent;
+        }
+        for (depth, entry) in ancestors.iter().rev().enumerate() {
+            self.render_entry(output, depth, *entry)?;
+        }
+
+        let mut stack = vec![(ancestors.len(), self.selected)];
+        while let Some((depth, entry)) = stack.pop() {
+            self.render_entry(output, depth, entry)?;
+            let children = self.visible_children(entry).iter().rev();
+            stack.extend(children.map(|child| (depth + 1, *child)));
+        }
+
+        let node = &self.entries[self.selected].node;
+        let range = node.range.as_deref().unwrap_or("unknown");
+        writeln!(output, "offset: {range}")?;
+        writeln!(output, "type: {}", node.r#type)?;
+        output.flush()
+    }
+
+    fn render_entry(
+        &self,
+        output: &mut dyn WriteColor,
+        depth: usize,
+        entry: usize,
+    ) -> io::Result<()> {
+        let Entry { node, expanded, .. } = &self.entries[entry];
+        let selected = entry == self.selected;
+        let marker = match (node.has_children, expanded) {
+            (false, _) => ' ',
+            (true, true) => '-',
+            (true, false) => '+',
+        };
+
+        write!(output, "{}", if selected { "> " } else { "  " })?;
+        write!(output, "{:indent$}{marker} ", "", indent = depth * 2)?;
+        if selected {
+            output.set_color(ColorSpec::new().set_bold(true))?;
+        }
+        write!(output, "{}", node.label)?;
+        output.reset()?;
+        write!(output, " = {}", node.summary)?;
+        if node.link.is_some() {
+            output.set_color(ColorSpec::new().set_fg(Some(Color::Cyan)))?;
+            write!(output, " (follow with `f`)")?;
+            output.reset()?;
+        }
+        writeln!(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan_reporting::term::termcolor::NoColor;
+
+    use super::*;
+
+    fn node(label: &str, key: usize, has_children: bool, link: Option<usize>) -> Node {
+        Node {
+            label: label.to_owned(),
+            summary: String::new(),
+            r#type: String::new(),
+            range: None,
+            key,
+            has_children,
+            link,
+        }
+    }
+
+    struct TestTree;
+
+    impl Tree for TestTree {
+        fn children(&mut self, key: usize) -> Vec<Node> {
+            match key {
+                0 => vec![node("a", 1, false, None), node("r", 2, false, Some(1))],
+                3 => vec![node("b", 4, false, None)],
+                _ => panic!("node has no children"),
+            }
+        }
+    }
+
+    fn explorer() -> Explorer<TestTree> {
+        let roots = vec![node("0", 0, true, None), node("8", 3, true, None)];
+        Explorer::new(TestTree, roots)
+    }
+
+    #[test]
+    fn navigate() {
+        let mut explorer = explorer();
+        assert_eq!(explorer.selected, 0);
+
+        explorer.handle(Command::Down).unwrap();
+        explorer.handle(Command::Down).unwrap();
+        assert_eq!(explorer.selected, 3);
+        // The collapsed reference is visible, but its children have not been
+        // built yet
+        explorer.handle(Command::Down).unwrap();
+        assert_eq!(explorer.selected, 1);
+        assert_eq!(explorer.entries.len(), 4);
+        assert!(explorer.handle(Command::Down).is_err());
+
+        explorer.handle(Command::Collapse).unwrap_err();
+        explorer.handle(Command::Expand).unwrap();
+        explorer.handle(Command::Down).unwrap();
+        assert_eq!(explorer.selected, 4);
+        explorer.handle(Command::Collapse).unwrap();
+        assert_eq!(explorer.selected, 1);
+
+        // Children are only built once
+        explorer.handle(Command::Toggle).unwrap();
+        explorer.handle(Command::Toggle).unwrap();
+        assert_eq!(explorer.entries.len(), 5);
+        explorer.handle(Command::Up).unwrap();
+        assert_eq!(explorer.selected, 3);
+    }
+
+    #[test]
+    fn follow_and_back() {
+        let mut explorer = explorer();
+        explorer.handle(Command::Down).unwrap();
+        assert!(explorer.handle(Command::Follow).is_err());
+
+        explorer.handle(Command::Down).unwrap();
+        explorer.handle(Command::Follow).unwrap();
+        assert_eq!(explorer.selected, 1);
+        assert!(explorer.entries[1].expanded);
+
+        // Returning expands the nodes that lead to where we came from
+        explorer.entries[0].expanded = false;
+        explorer.handle(Command::Back).unwrap();
+        assert_eq!(explorer.selected, 3);
+        assert!(explorer.entries[0].expanded);
+        assert!(explorer.handle(Command::Back).is_err());
+    }
+
+    #[test]
+    fn run() {
+        let mut explorer = explorer();
+        let mut output = NoColor::new(Vec::new());
+        explorer.run(&b"j\nj\nf\nq\nj\n"[..], &mut output).unwrap();
+        assert_eq!(explorer.selected, 1);
+
+        // Only the selected node and the nodes containing it are rendered
+        let output = String::from_utf8(output.into_inner()).unwrap();
+        assert!(output.contains("\n  - 0 = \n>     r =  (follow with `f`)\noffset: unknown\n"));
+        assert!(output.ends_with("\n> - 8 = \n      b = \noffset: unknown\ntype: \n"));
+    }
+}
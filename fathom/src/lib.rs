@@ -20,4 +20,4 @@ mod driver;
 pub const BUG_REPORT_URL: &str = concat!(env!("CARGO_PKG_REPOSITORY"), "/issues/new");
 
 // Public exports
-pub use driver::{Driver, Status};
+pub use driver::{DataOutput, Driver, Status};
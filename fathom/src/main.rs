@@ -1,6 +1,9 @@
-use std::path::PathBuf;
+use std::io::{stdin, Read};
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
+use fathom::core::binary::select::Query;
+use fathom::core::binary::{Buffer, ReadLimits, StreamData};
 
 /// A language for declaratively specifying binary data formats
 #[derive(Parser)]
@@ -68,6 +71,156 @@ enum Cli {
         /// Continue even if errors were encountered
         #[clap(long = "allow-errors")]
         allow_errors: bool,
+        /// Offset in the binary data to start reading from
+        ///
+        /// Positions are still relative to the start of the binary data, so
+        /// links to data outside of the format can be followed.
+        #[clap(long = "offset", name = "OFFSET", value_parser = parse_usize)]
+        offset: Option<usize>,
+        /// Number of bytes that the format is limited to reading
+        #[clap(long = "length", name = "LENGTH", value_parser = parse_usize)]
+        length: Option<usize>,
+        /// Continue reading after encountering errors in the binary data
+        #[clap(long = "recover")]
+        recover: bool,
+        /// Display the binary data as a hexdump annotated with the fields that
+        /// read each byte
+        #[clap(long = "hexdump")]
+        hexdump: bool,
+        /// Format used when displaying the binary data
+        #[clap(
+            long = "output",
+            value_enum,
+            default_value = "term",
+            conflicts_with = "hexdump"
+        )]
+        output: DataOutput,
+        /// Path to the values to display, eg. `main.header.num_tables`
+        ///
+        /// Paths are made up of field projections and array indices, and may
+        /// begin with the name of the entrypoint. The wildcards `*` and `[*]`
+        /// select every field and every element. References are followed to
+        /// the data that they refer to.
+        #[clap(long = "select", name = "PATH", conflicts_with = "hexdump")]
+        select: Option<Query>,
+        /// Term to evaluate using the binary data, instead of displaying it
+        ///
+        /// The data read by the entrypoint is bound to the name of the
+        /// entrypoint in the term, eg. `main`, or to `data` if the format is
+        /// not a name.
+        #[clap(
+            long = "eval",
+            name = "TERM",
+            conflicts_with_all = ["hexdump", "output", "PATH"]
+        )]
+        eval: Option<String>,
+        #[clap(flatten)]
+        limits: ReadLimitArgs,
+    },
+    /// Interactively explore binary data based on a Fathom format
+    ///
+    /// Commands for navigating the data are read from stdin, one per line.
+    /// Enter `?` to list the available commands.
+    Explore {
+        /// Path to a module to load when reading
+        #[clap(long = "module", name = "MODULE_FILE", display_order = 0)]
+        module_file: Option<PathOrStdin>,
+        /// Format used when reading the binary data
+        ///
+        /// The term provided by `FORMAT` must be of type `Format`.
+        ///
+        /// Required unless `--module` is present.
+        #[clap(
+            long = "format",
+            name = "FORMAT",
+            default_value = "main",
+            required_unless_present = "MODULE_FILE",
+            display_order = 1
+        )]
+        format: String,
+        /// Path to the binary data to read from
+        #[clap(name = "BINARY_FILE")]
+        binary_file: PathBuf,
+        /// Continue even if errors were encountered
+        #[clap(long = "allow-errors")]
+        allow_errors: bool,
+        /// Offset in the binary data to start reading from
+        ///
+        /// Positions are still relative to the start of the binary data, so
+        /// links to data outside of the format can be followed.
+        #[clap(long = "offset", name = "OFFSET", value_parser = parse_usize)]
+        offset: Option<usize>,
+        /// Number of bytes that the format is limited to reading
+        #[clap(long = "length", name = "LENGTH", value_parser = parse_usize)]
+        length: Option<usize>,
+        /// Continue reading after encountering errors in the binary data
+        #[clap(long = "recover")]
+        recover: bool,
+        #[clap(flatten)]
+        limits: ReadLimitArgs,
+    },
+    /// Generate random binary data based on a Fathom format, writing it to
+    /// stdout
+    ///
+    /// The same data is generated each time for a given seed, so that any
+    /// problems found with the data can be reproduced.
+    Gen {
+        /// Path to a module to load when generating
+        #[clap(long = "module", name = "MODULE_FILE", display_order = 0)]
+        module_file: Option<PathOrStdin>,
+        /// Format used when generating the binary data
+        ///
+        /// The term provided by `FORMAT` must be of type `Format`.
+        ///
+        /// Required unless `--module` is present.
+        #[clap(
+            long = "format",
+            name = "FORMAT",
+            default_value = "main",
+            required_unless_present = "MODULE_FILE",
+            display_order = 1
+        )]
+        format: String,
+        /// Seed used to choose the values in the generated data
+        #[clap(long = "seed", name = "SEED", default_value = "0")]
+        seed: u64,
+        /// Continue even if errors were encountered
+        #[clap(long = "allow-errors")]
+        allow_errors: bool,
+    },
+    /// Check that binary data is written back unchanged after reading it with
+    /// a Fathom format
+    ///
+    /// The data is read with the format, and the values that were read are
+    /// written back with the same format. If the written data differs from
+    /// the data that was read, the first byte that differs is reported along
+    /// with the value that it was read as part of. Bytes that were not read
+    /// as part of any value are ignored.
+    Roundtrip {
+        /// Path to a module to load when reading
+        #[clap(long = "module", name = "MODULE_FILE", display_order = 0)]
+        module_file: Option<PathOrStdin>,
+        /// Format used when reading and writing the binary data
+        ///
+        /// The term provided by `FORMAT` must be of type `Format`.
+        ///
+        /// Required unless `--module` is present.
+        #[clap(
+            long = "format",
+            name = "FORMAT",
+            default_value = "main",
+            required_unless_present = "MODULE_FILE",
+            display_order = 1
+        )]
+        format: String,
+        /// Path to the binary data to read from
+        #[clap(name = "BINARY_FILE")]
+        binary_file: PathOrStdin,
+        /// Continue even if errors were encountered
+        #[clap(long = "allow-errors")]
+        allow_errors: bool,
+        #[clap(flatten)]
+        limits: ReadLimitArgs,
     },
 }
 
@@ -95,6 +248,67 @@ When a module is specified the binary data is read assuming that it contains a
   $ fathom data --module formats/icns.fathom --format header AppIcon.icns
 ";
 
+/// Parse a decimal or `0x` prefixed hexadecimal number.
+fn parse_usize(src: &str) -> Result<usize, std::num::ParseIntError> {
+    match src.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => src.parse(),
+    }
+}
+
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
+enum DataOutput {
+    /// Fathom terms
+    Term,
+    /// JSON, with the references keyed by position
+    Json,
+}
+
+impl From<DataOutput> for fathom::DataOutput {
+    fn from(output: DataOutput) -> fathom::DataOutput {
+        match output {
+            DataOutput::Term => fathom::DataOutput::Term,
+            DataOutput::Json => fathom::DataOutput::Json,
+        }
+    }
+}
+
+/// Limits on the resources used when reading untrusted binary data
+#[derive(Copy, Clone, Debug, clap::Args)]
+struct ReadLimitArgs {
+    /// Maximum number of elements in an array
+    #[clap(long = "max-array-len", name = "ELEMENTS")]
+    max_array_len: Option<u64>,
+    /// Maximum number of values to read in total
+    #[clap(long = "max-values", name = "VALUES")]
+    max_values: Option<usize>,
+    /// Maximum depth that formats can be nested, including through links
+    #[clap(long = "max-depth", name = "DEPTH")]
+    max_depth: Option<usize>,
+    /// Maximum number of links waiting to be read
+    #[clap(long = "max-pending-links", name = "LINKS")]
+    max_pending_links: Option<usize>,
+    /// Maximum number of formats to read, including those that failed
+    #[clap(long = "max-steps", name = "STEPS")]
+    max_steps: Option<u64>,
+    /// Maximum number of bytes to decompress in total
+    #[clap(long = "max-decompressed-len", name = "BYTES")]
+    max_decompressed_len: Option<usize>,
+}
+
+impl From<ReadLimitArgs> for ReadLimits {
+    fn from(args: ReadLimitArgs) -> ReadLimits {
+        ReadLimits {
+            max_array_len: args.max_array_len,
+            max_values: args.max_values,
+            max_depth: args.max_depth,
+            max_pending_links: args.max_pending_links,
+            max_steps: args.max_steps,
+            max_decompressed_len: args.max_decompressed_len,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 enum PathOrStdin {
     StdIn,
@@ -131,11 +345,12 @@ fn load_source_or_exit(
     unwrap_or_exit(driver.load_source(name, source.as_bytes()))
 }
 
-fn read_bytes_or_exit(driver: &mut fathom::Driver, file: PathOrStdin) -> Vec<u8> {
-    unwrap_or_exit(match file {
-        PathOrStdin::StdIn => driver.read_bytes("<stdin>".to_owned(), std::io::stdin()),
-        PathOrStdin::Path(path) => driver.read_bytes_path(&path),
-    })
+fn read_bytes_or_exit(driver: &mut fathom::Driver, name: String, reader: impl Read) -> Vec<u8> {
+    unwrap_or_exit(driver.read_bytes(name, reader))
+}
+
+fn open_stream_or_exit(driver: &mut fathom::Driver, path: &Path) -> StreamData {
+    unwrap_or_exit(driver.open_stream_path(path))
 }
 
 const MAX_PRETTY_WIDTH: usize = 80;
@@ -193,6 +408,84 @@ fn main() -> ! {
             format,
             binary_file,
             allow_errors,
+            recover,
+            offset,
+            length,
+            hexdump,
+            output,
+            select,
+            eval,
+            limits,
+        } => {
+            let mut driver = fathom::Driver::new();
+            driver.install_panic_hook();
+            driver.set_allow_errors(allow_errors);
+            driver.set_recover(recover);
+            driver.set_hexdump(hexdump);
+            driver.set_data_output(output.into());
+            driver.set_data_range(offset, length);
+            driver.set_data_select(select);
+            driver.set_read_limits(limits.into());
+            driver.set_emit_width(get_pretty_width());
+
+            let module_file_id = module_file.map(|input| load_file_or_exit(&mut driver, input));
+            let format_file_id = load_source_or_exit(&mut driver, "<FORMAT>".to_owned(), format);
+            let eval_file_id =
+                eval.map(|term| load_source_or_exit(&mut driver, "<TERM>".to_owned(), term));
+            driver.set_data_eval(eval_file_id);
+
+            let status = match binary_file {
+                PathOrStdin::StdIn => {
+                    let data = read_bytes_or_exit(&mut driver, "<stdin>".to_owned(), stdin());
+                    let buffer = Buffer::from(&data[..]);
+                    driver.read_and_emit_format(module_file_id, format_file_id, buffer)
+                }
+                // Read files lazily, so that large files do not need to be
+                // loaded into memory all at once
+                PathOrStdin::Path(path) => {
+                    let stream = open_stream_or_exit(&mut driver, &path);
+                    let buffer = Buffer::from(&stream);
+                    driver.read_and_emit_format(module_file_id, format_file_id, buffer)
+                }
+            };
+
+            std::process::exit(status.exit_code());
+        }
+        Cli::Explore {
+            module_file,
+            format,
+            binary_file,
+            allow_errors,
+            offset,
+            length,
+            recover,
+            limits,
+        } => {
+            let mut driver = fathom::Driver::new();
+            driver.install_panic_hook();
+            driver.set_allow_errors(allow_errors);
+            driver.set_recover(recover);
+            driver.set_data_output(fathom::DataOutput::Explore);
+            driver.set_data_range(offset, length);
+            driver.set_read_limits(limits.into());
+            driver.set_emit_width(get_pretty_width());
+
+            let module_file_id = module_file.map(|input| load_file_or_exit(&mut driver, input));
+            let format_file_id = load_source_or_exit(&mut driver, "<FORMAT>".to_owned(), format);
+
+            // Commands are read from stdin, so the binary data must be read
+            // from a file
+            let stream = open_stream_or_exit(&mut driver, &binary_file);
+            let buffer = Buffer::from(&stream);
+            let status = driver.read_and_emit_format(module_file_id, format_file_id, buffer);
+
+            std::process::exit(status.exit_code());
+        }
+        Cli::Gen {
+            module_file,
+            format,
+            seed,
+            allow_errors,
         } => {
             let mut driver = fathom::Driver::new();
             driver.install_panic_hook();
@@ -201,9 +494,35 @@ fn main() -> ! {
 
             let module_file_id = module_file.map(|input| load_file_or_exit(&mut driver, input));
             let format_file_id = load_source_or_exit(&mut driver, "<FORMAT>".to_owned(), format);
+            let status = driver.generate_and_emit_format(module_file_id, format_file_id, seed);
 
-            let data = read_bytes_or_exit(&mut driver, binary_file);
-            let status = driver.read_and_emit_format(module_file_id, format_file_id, &data);
+            std::process::exit(status.exit_code());
+        }
+        Cli::Roundtrip {
+            module_file,
+            format,
+            binary_file,
+            allow_errors,
+            limits,
+        } => {
+            let mut driver = fathom::Driver::new();
+            driver.install_panic_hook();
+            driver.set_allow_errors(allow_errors);
+            driver.set_read_limits(limits.into());
+            driver.set_emit_width(get_pretty_width());
+
+            let module_file_id = module_file.map(|input| load_file_or_exit(&mut driver, input));
+            let format_file_id = load_source_or_exit(&mut driver, "<FORMAT>".to_owned(), format);
+
+            // The whole of the data is needed to compare it with the data
+            // that is written back
+            let data = match binary_file {
+                PathOrStdin::StdIn => {
+                    read_bytes_or_exit(&mut driver, "<stdin>".to_owned(), stdin())
+                }
+                PathOrStdin::Path(path) => unwrap_or_exit(driver.read_bytes_path(&path)),
+            };
+            let status = driver.roundtrip_format(module_file_id, format_file_id, &data);
 
             std::process::exit(status.exit_code());
         }
@@ -4,7 +4,7 @@ use scoped_arena::Scope;
 
 use crate::alloc::SliceVec;
 use crate::core;
-use crate::core::{Const, Plicity, UIntStyle};
+use crate::core::{Const, Plicity, Pos, UIntStyle};
 use crate::env::{self, EnvLen, Index, Level, UniqueEnv};
 use crate::source::Span;
 use crate::surface::elaboration::MetaSource;
@@ -44,6 +44,9 @@ pub struct Context<'arena, 'env> {
     local_names: UniqueEnv<Option<Symbol>>,
     /// Metavariable sources.
     meta_sources: &'env UniqueEnv<MetaSource>,
+    /// Used to render position and reference values, if they should be
+    /// rendered differently from their offsets.
+    pos_to_string: Option<&'env dyn Fn(Pos) -> String>,
 }
 
 impl<'arena, 'env> Context<'arena, 'env> {
@@ -59,6 +62,20 @@ impl<'arena, 'env> Context<'arena, 'env> {
             item_names,
             local_names,
             meta_sources,
+            pos_to_string: None,
+        }
+    }
+
+    /// Render position and reference values using `pos_to_string`, for
+    /// example to distinguish positions in derived buffers from offsets in
+    /// the binary data.
+    pub fn with_pos_to_string(
+        self,
+        pos_to_string: &'env dyn Fn(Pos) -> String,
+    ) -> Context<'arena, 'env> {
+        Context {
+            pos_to_string: Some(pos_to_string),
+            ..self
         }
     }
 
@@ -165,6 +182,13 @@ impl<'arena, 'env> Context<'arena, 'env> {
         Term::NumberLiteral((), number)
     }
 
+    fn pos_string(&self, pos: Pos) -> String {
+        match self.pos_to_string {
+            Some(pos_to_string) => pos_to_string(pos),
+            None => pos.offset().to_string(),
+        }
+    }
+
     fn check_number_pattern<T: std::fmt::Display>(&mut self, number: T) -> Pattern<()> {
         let number = Symbol::intern(number.to_string());
         Pattern::NumberLiteral((), number)
@@ -194,8 +218,9 @@ impl<'arena, 'env> Context<'arena, 'env> {
             Const::S64(number) => self.check_number_pattern(number),
             Const::F32(number) => self.check_number_pattern(number),
             Const::F64(number) => self.check_number_pattern(number),
-            Const::Pos(number) => self.check_number_pattern(number),
-            Const::Ref(number) => self.check_number_pattern(number),
+            Const::Pos(pos) => self.check_number_pattern(self.pos_string(*pos)),
+            Const::Ref(pos, _) => self.check_number_pattern(self.pos_string(*pos)),
+            Const::String(string) => Pattern::StringLiteral((), escape_string(string)),
         }
     }
 
@@ -611,6 +636,7 @@ impl<'arena, 'env> Context<'arena, 'env> {
             (core::Term::FormatOverlap(_, labels, formats), _) => {
                 Term::FormatOverlap((), self.synth_format_fields(labels, formats))
             }
+            (core::Term::Prim(_, core::Prim::ReportedError), _) => Term::ReportedError(()),
             (core::Term::Prim(_, prim), _) => self.synth_prim(*prim),
             (core::Term::ConstLit(_, r#const), Mode::Synth) => match r#const {
                 Const::Bool(boolean) => Term::BooleanLiteral((), *boolean),
@@ -632,8 +658,23 @@ impl<'arena, 'env> Context<'arena, 'env> {
                 Const::S64(number) => self.synth_number_literal(prec, number, core::Prim::S64Type),
                 Const::F32(number) => self.synth_number_literal(prec, number, core::Prim::F32Type),
                 Const::F64(number) => self.synth_number_literal(prec, number, core::Prim::F64Type),
-                Const::Pos(number) => self.synth_number_literal(prec, number, core::Prim::PosType),
-                Const::Ref(number) => self.synth_number_literal(prec, number, core::Prim::RefType),
+                Const::Pos(pos) => {
+                    let pos = self.pos_string(*pos);
+                    self.synth_number_literal(prec, pos, core::Prim::PosType)
+                }
+                Const::Ref(pos, _) => {
+                    let pos = self.pos_string(*pos);
+                    self.synth_number_literal(prec, pos, core::Prim::RefType)
+                }
+                Const::String(string) => {
+                    let expr = Term::StringLiteral((), escape_string(string));
+                    let r#type = self.synth_prim(core::Prim::StringType);
+
+                    self.paren(
+                        prec > Prec::Top,
+                        Term::Ann((), self.scope.to_scope(expr), self.scope.to_scope(r#type)),
+                    )
+                }
             },
             (core::Term::ConstLit(_, r#const), Mode::Check) => match r#const {
                 Const::Bool(boolean) => Term::BooleanLiteral((), *boolean),
@@ -647,8 +688,9 @@ impl<'arena, 'env> Context<'arena, 'env> {
                 Const::S64(number) => self.check_number_literal(number),
                 Const::F32(number) => self.check_number_literal(number),
                 Const::F64(number) => self.check_number_literal(number),
-                Const::Pos(number) => self.check_number_literal(number),
-                Const::Ref(number) => self.check_number_literal(number),
+                Const::Pos(pos) => self.check_number_literal(self.pos_string(*pos)),
+                Const::Ref(pos, _) => self.check_number_literal(self.pos_string(*pos)),
+                Const::String(string) => Term::StringLiteral((), escape_string(string)),
             },
             (core::Term::ConstMatch(_, head_expr, const_branches, default_expr), _) => {
                 if let Some((then_expr, else_expr)) =
@@ -720,6 +762,13 @@ fn name_to_pattern(name: Option<Symbol>) -> Pattern<()> {
     }
 }
 
+/// Escape the contents of a string so that it can be printed as a string
+/// literal.
+fn escape_string(string: &str) -> Symbol {
+    let escaped = format!("{string:?}");
+    Symbol::intern(&escaped[1..escaped.len() - 1])
+}
+
 fn match_if_then_else<'arena>(
     branches: &'arena [(Const, core::Term<'arena>)],
     default_branch: Option<(Option<Symbol>, &'arena core::Term<'arena>)>,
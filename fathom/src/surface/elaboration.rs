@@ -281,6 +281,41 @@ fn suggest_name(name: Symbol, candidates: impl Iterator<Item = Symbol>) -> Optio
     })
 }
 
+/// Parse the escape sequence at the start of `source`, returning the escaped
+/// character (if the escape sequence is valid) and the byte length of the
+/// escape sequence.
+fn parse_escape(source: &str) -> (Option<char>, usize) {
+    let ch = match source.chars().nth(1) {
+        Some(ch) => ch,
+        None => return (None, source.len()),
+    };
+    let len = 1 + ch.len_utf8();
+
+    match ch {
+        '\\' => (Some('\\'), len),
+        '"' => (Some('"'), len),
+        '\'' => (Some('\''), len),
+        'n' => (Some('\n'), len),
+        'r' => (Some('\r'), len),
+        't' => (Some('\t'), len),
+        '0' => (Some('\0'), len),
+        'u' => match source[len..]
+            .strip_prefix('{')
+            .and_then(|s| s.split_once('}'))
+        {
+            Some((digits, _)) if (1..=6).contains(&digits.len()) => {
+                let ch = (digits.chars().all(|ch| ch.is_ascii_hexdigit()))
+                    .then(|| u32::from_str_radix(digits, 16).ok())
+                    .flatten()
+                    .and_then(char::from_u32);
+                (ch, len + digits.len() + 2)
+            }
+            Some(_) | None => (None, len),
+        },
+        _ => (None, len),
+    }
+}
+
 impl<'arena> Context<'arena> {
     /// Construct a new elaboration context, backed by the supplied arena.
     pub fn new(
@@ -309,6 +344,12 @@ impl<'arena> Context<'arena> {
         self.item_env
     }
 
+    /// Bind a name to an expression in the local environment, so that it can
+    /// be referred to by terms that are elaborated in the context.
+    pub fn define_local(&mut self, name: Symbol, expr: ArcValue<'arena>, r#type: ArcValue<'arena>) {
+        self.local_env.push_def(Some(name), expr, r#type);
+    }
+
     fn file_range(&self, byte_range: ByteRange) -> FileRange {
         FileRange::new(self.file_id, byte_range)
     }
@@ -465,13 +506,49 @@ impl<'arena> Context<'arena> {
         (labels.into(), filtered_fields)
     }
 
+    /// Parse a source string into a string constant, interpreting any escape
+    /// sequences that it contains.
+    fn parse_string(&mut self, range: ByteRange, symbol: Symbol) -> Option<Const<'arena>> {
+        let source = symbol.resolve();
+        let mut string = Some(String::with_capacity(source.len()));
+        let mut offset = 0;
+
+        while let Some(index) = source[offset..].find('\\') {
+            let escape_start = offset + index;
+            let (ch, len) = parse_escape(&source[escape_start..]);
+
+            match ch {
+                Some(ch) => {
+                    if let Some(string) = &mut string {
+                        string.push_str(&source[offset..escape_start]);
+                        string.push(ch);
+                    }
+                }
+                None => {
+                    let escape_start = range.start() + 1 + escape_start as BytePos;
+                    let escape_end = escape_start + len as BytePos;
+
+                    self.push_message(Message::InvalidStringEscape {
+                        invalid_range: self.file_range(ByteRange::new(escape_start, escape_end)),
+                    });
+                    string = None;
+                }
+            }
+            offset = escape_start + len;
+        }
+
+        let mut string = string?;
+        string.push_str(&source[offset..]);
+        Some(Const::string(self.scope, &string))
+    }
+
     /// Parse a source string into number, assuming an ASCII encoding.
     fn parse_ascii<T>(
         &mut self,
         range: ByteRange,
         symbol: Symbol,
-        make: fn(T, UIntStyle) -> Const,
-    ) -> Option<Const>
+        make: fn(T, UIntStyle) -> Const<'arena>,
+    ) -> Option<Const<'arena>>
     where
         T: From<u8> + std::ops::Shl<Output = T> + std::ops::BitOr<Output = T>,
     {
@@ -520,8 +597,8 @@ impl<'arena> Context<'arena> {
         &mut self,
         range: ByteRange,
         symbol: Symbol,
-        make: fn(T) -> Const,
-    ) -> Option<Const>
+        make: fn(T) -> Const<'arena>,
+    ) -> Option<Const<'arena>>
     where
         T::Err: std::fmt::Display,
     {
@@ -544,8 +621,8 @@ impl<'arena> Context<'arena> {
         &mut self,
         range: ByteRange,
         symbol: Symbol,
-        make: fn(T, UIntStyle) -> Const,
-    ) -> Option<Const> {
+        make: fn(T, UIntStyle) -> Const<'arena>,
+    ) -> Option<Const<'arena>> {
         // TODO: Custom parsing and improved errors
         let s = symbol.resolve();
         let (s, radix, style) = if let Some(s) = s.strip_prefix("0x") {
@@ -723,7 +800,7 @@ impl<'arena> Context<'arena> {
         &mut self,
         pattern: &Pattern<ByteRange>,
         expected_type: &ArcValue<'arena>,
-    ) -> CheckedPattern {
+    ) -> CheckedPattern<'arena> {
         let file_range = self.file_range(pattern.range());
         match pattern {
             Pattern::Name(_, name) => CheckedPattern::Binder(file_range, *name),
@@ -734,6 +811,7 @@ impl<'arena> Context<'arena> {
                     Some((Prim::U16Type, [])) => self.parse_ascii(*range, *lit, Const::U16),
                     Some((Prim::U32Type, [])) => self.parse_ascii(*range, *lit, Const::U32),
                     Some((Prim::U64Type, [])) => self.parse_ascii(*range, *lit, Const::U64),
+                    Some((Prim::StringType, [])) => self.parse_string(*range, *lit),
                     // Some((Prim::Array8Type, [len, _])) => todo!(),
                     // Some((Prim::Array16Type, [len, _])) => todo!(),
                     // Some((Prim::Array32Type, [len, _])) => todo!(),
@@ -806,7 +884,7 @@ impl<'arena> Context<'arena> {
     fn synth_pattern(
         &mut self,
         pattern: &Pattern<ByteRange>,
-    ) -> (CheckedPattern, ArcValue<'arena>) {
+    ) -> (CheckedPattern<'arena>, ArcValue<'arena>) {
         let file_range = self.file_range(pattern.range());
         match pattern {
             Pattern::Name(_, name) => {
@@ -845,7 +923,7 @@ impl<'arena> Context<'arena> {
         pattern: &Pattern<ByteRange>,
         r#type: Option<&Term<'_, ByteRange>>,
         expected_type: &ArcValue<'arena>,
-    ) -> CheckedPattern {
+    ) -> CheckedPattern<'arena> {
         match r#type {
             None => self.check_pattern(pattern, expected_type),
             Some(r#type) => {
@@ -874,7 +952,7 @@ impl<'arena> Context<'arena> {
         &mut self,
         pattern: &Pattern<ByteRange>,
         r#type: Option<&Term<'_, ByteRange>>,
-    ) -> (CheckedPattern, core::Term<'arena>, ArcValue<'arena>) {
+    ) -> (CheckedPattern<'arena>, core::Term<'arena>, ArcValue<'arena>) {
         match r#type {
             None => {
                 let (pattern, type_value) = self.synth_pattern(pattern);
@@ -893,7 +971,7 @@ impl<'arena> Context<'arena> {
     /// The supplied `pattern` is expected to be irrefutable.
     fn push_local_def(
         &mut self,
-        pattern: CheckedPattern,
+        pattern: CheckedPattern<'arena>,
         expr: ArcValue<'arena>,
         r#type: ArcValue<'arena>,
     ) -> Option<Symbol> {
@@ -919,7 +997,7 @@ impl<'arena> Context<'arena> {
     /// The supplied `pattern` is expected to be irrefutable.
     fn push_local_param(
         &mut self,
-        pattern: CheckedPattern,
+        pattern: CheckedPattern<'arena>,
         r#type: ArcValue<'arena>,
     ) -> (Option<Symbol>, ArcValue<'arena>) {
         let name = match pattern {
@@ -1207,6 +1285,7 @@ impl<'arena> Context<'arena> {
                     Some((Prim::U16Type, [])) => self.parse_ascii(*range, *lit, Const::U16),
                     Some((Prim::U32Type, [])) => self.parse_ascii(*range, *lit, Const::U32),
                     Some((Prim::U64Type, [])) => self.parse_ascii(*range, *lit, Const::U64),
+                    Some((Prim::StringType, [])) => self.parse_string(*range, *lit),
                     // Some((Prim::Array8Type, [len, _])) => todo!(),
                     // Some((Prim::Array16Type, [len, _])) => todo!(),
                     // Some((Prim::Array32Type, [len, _])) => todo!(),
@@ -2302,7 +2381,7 @@ impl<'arena> Context<'arena> {
         &mut self,
         match_info: &MatchInfo<'arena>,
         is_reachable: bool,
-        (const_range, r#const, body_expr): (FileRange, Const, core::Term<'arena>),
+        (const_range, r#const, body_expr): (FileRange, Const<'arena>, core::Term<'arena>),
         mut equations: impl Iterator<Item = &'a (Pattern<ByteRange>, Term<'a, ByteRange>)>,
     ) -> core::Term<'arena> {
         // The full range of this series of patterns
@@ -2455,13 +2534,13 @@ impl_from_str_radix!(u64);
 
 /// Simple patterns that have had some initial elaboration performed on them
 #[derive(Debug)]
-enum CheckedPattern {
+enum CheckedPattern<'arena> {
     /// Pattern that binds local variable
     Binder(FileRange, Symbol),
     /// Placeholder patterns that match everything
     Placeholder(FileRange),
     /// Constant literals
-    ConstLit(FileRange, Const),
+    ConstLit(FileRange, Const<'arena>),
     /// Error sentinel
     ReportedError(FileRange),
 }
@@ -83,6 +83,9 @@ pub enum Message {
     NonAsciiStringLiteral {
         invalid_range: FileRange,
     },
+    InvalidStringEscape {
+        invalid_range: FileRange,
+    },
     StringLiteralNotSupported {
         range: FileRange,
         expected_type: String,
@@ -343,6 +346,11 @@ impl Message {
                 .with_labels(vec![
                     primary_label(invalid_range).with_message("non-ASCII character")
                 ]),
+            Message::InvalidStringEscape { invalid_range } => Diagnostic::error()
+                .with_message("invalid escape sequence in string literal")
+                .with_labels(vec![
+                    primary_label(invalid_range).with_message("invalid escape sequence")
+                ]),
             Message::StringLiteralNotSupported {
                 range,
                 expected_type,
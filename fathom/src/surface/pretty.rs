@@ -75,7 +75,9 @@ impl<'arena> Context<'arena> {
         match pattern {
             Pattern::Placeholder(_) => self.text("_"),
             Pattern::Name(_, name) => self.ident(*name),
-            Pattern::StringLiteral(_, number) => self.symbol(*number),
+            Pattern::StringLiteral(_, number) => {
+                self.concat([self.text("\""), self.symbol(*number), self.text("\"")])
+            }
             Pattern::NumberLiteral(_, number) => self.symbol(*number),
             Pattern::BooleanLiteral(_, boolean) => match *boolean {
                 true => self.text("true"),
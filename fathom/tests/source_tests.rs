@@ -50,6 +50,9 @@ struct Config {
     example_data: Vec<String>,
     #[serde(default = "DEFAULT_EXAMPLE_DATA")]
     example_data_invalid: Vec<String>,
+    /// Check that the example data is written back unchanged after reading it
+    #[serde(default = "DEFAULT_ROUNDTRIP")]
+    roundtrip: bool,
     #[serde(skip)]
     update_snapshots: bool,
     #[serde(default = "DEFAULT_TEST_NORMALIZATION")]
@@ -61,6 +64,7 @@ const DEFAULT_IGNORE: fn() -> bool = || false;
 const DEFAULT_EXIT_CODE: fn() -> i32 = || 0;
 const DEFAULT_EXAMPLE_DATA: fn() -> Vec<String> = Vec::new;
 const DEFAULT_TEST_NORMALIZATION: fn() -> bool = || false;
+const DEFAULT_ROUNDTRIP: fn() -> bool = || false;
 
 struct TestFailure {
     name: &'static str,
@@ -101,6 +105,7 @@ enum Command<'a> {
     ElabTerm,
     Normalize,
     ParseData(&'a Path, ExpectedOutcome),
+    Roundtrip(&'a Path),
 }
 
 #[derive(Copy, Clone)]
@@ -113,16 +118,30 @@ impl<'a> Command<'a> {
     fn snap_name(&self) -> &'static str {
         match self {
             Command::Normalize => "norm",
+            Command::Roundtrip(_) => "roundtrip",
             Command::ElabModule | Command::ElabTerm | Command::ParseData(_, _) => "",
         }
     }
 
+    /// Whether the output of the command is compared with a snapshot. Round
+    /// trips only print diagnostics on failure, so only their status is checked.
+    fn has_snapshot(&self) -> bool {
+        match self {
+            Command::Roundtrip(_) => false,
+            Command::ElabModule
+            | Command::ElabTerm
+            | Command::Normalize
+            | Command::ParseData(..) => true,
+        }
+    }
+
     pub(crate) fn expected_outcome(&self) -> ExpectedOutcome {
         match self {
             Command::ParseData(_, outcome) => *outcome,
-            Command::ElabModule | Command::ElabTerm | Command::Normalize => {
-                ExpectedOutcome::Success
-            }
+            Command::ElabModule
+            | Command::ElabTerm
+            | Command::Normalize
+            | Command::Roundtrip(_) => ExpectedOutcome::Success,
         }
     }
 }
@@ -224,15 +243,21 @@ fn run_test(
         .unwrap();
 
     for example_file in example_data.filter_map(Result::ok) {
-        let command = Command::ParseData(&input_file, ExpectedOutcome::Success);
-        let test_command = TestCommand::new(command, &config, example_file.path());
-        match test_command.run() {
-            Ok(mut test_failures) => failures.append(&mut test_failures),
-            Err(error) => {
-                failures.push(TestFailure {
-                    name: "unexpected test command error",
-                    details: vec![("std::io::Error", error.to_string())],
-                });
+        let mut commands = vec![Command::ParseData(&input_file, ExpectedOutcome::Success)];
+        if config.roundtrip {
+            commands.push(Command::Roundtrip(&input_file));
+        }
+
+        for command in commands {
+            let test_command = TestCommand::new(command, &config, example_file.path());
+            match test_command.run() {
+                Ok(mut test_failures) => failures.append(&mut test_failures),
+                Err(error) => {
+                    failures.push(TestFailure {
+                        name: "unexpected test command error",
+                        details: vec![("std::io::Error", error.to_string())],
+                    });
+                }
             }
         }
     }
@@ -308,11 +333,16 @@ impl<'a> TestCommand<'a> {
                 let mut snapshot = Snapshot::new(self.command, self.input_file, &output)?;
 
                 // Update if requested
-                if self.config.update_snapshots && snapshot.outcome() != SnapshotOutcome::Equal {
+                let has_snapshot = self.command.has_snapshot();
+                if has_snapshot
+                    && self.config.update_snapshots
+                    && snapshot.outcome() != SnapshotOutcome::Equal
+                {
                     snapshot.update()?;
                 }
 
                 match snapshot.outcome() {
+                    _ if !has_snapshot => {}
                     SnapshotOutcome::Equal => {}
                     SnapshotOutcome::Different => {
                         let mut details = vec![
@@ -417,6 +447,10 @@ impl<'a> From<Command<'a>> for process::Command {
                 exe.args(["data", "--module"]);
                 exe.arg(format);
             }
+            Command::Roundtrip(format) => {
+                exe.args(["roundtrip", "--module"]);
+                exe.arg(format);
+            }
         }
         exe
     }